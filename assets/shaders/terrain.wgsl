@@ -0,0 +1,110 @@
+// Shades the terrain chunks. Each triangle carries up to four material ids with weights which blend between them,
+// every material is a layer of the texture array projected along each axis, divided by the mean color of the layer
+// and tinted with the color of the material.
+
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_functions,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    view_transformations::position_world_to_clip,
+}
+
+struct TerrainShading {
+    layers: array<vec4<u32>, 4>,
+    tints: array<vec4<f32>, 16>,
+    layer_means: array<vec4<f32>, 8>,
+    texture_size: f32,
+    triplanar_sharpness: f32,
+}
+
+@group(2) @binding(100) var<uniform> terrain: TerrainShading;
+@group(2) @binding(101) var terrain_textures: texture_2d_array<f32>;
+@group(2) @binding(102) var terrain_sampler: sampler;
+
+struct TerrainVertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(8) material_ids: u32,
+    @location(9) material_weights: vec4<f32>,
+    @location(10) triplanar_position: vec3<f32>,
+}
+
+struct TerrainVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    @location(6) @interpolate(flat) instance_index: u32,
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    @location(7) @interpolate(flat) visibility_range_dither: i32,
+#endif
+    // The vertices of a triangle share their ids, only the weights change across it.
+    @location(8) @interpolate(flat) material_ids: u32,
+    @location(9) material_weights: vec4<f32>,
+    @location(10) triplanar_position: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: TerrainVertex) -> TerrainVertexOutput {
+    var out: TerrainVertexOutput;
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    out.world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4<f32>(vertex.position, 1.0));
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(
+        vertex.instance_index, world_from_local[3]);
+#endif
+    out.material_ids = vertex.material_ids;
+    out.material_weights = vertex.material_weights;
+    out.triplanar_position = vertex.triplanar_position;
+    return out;
+}
+
+// The color of the material, its layer sampled along each axis weighted by how much the surface faces that axis.
+fn material_color(id: u32, position: vec3<f32>, axis_weights: vec3<f32>) -> vec3<f32> {
+    let layer = terrain.layers[id / 4u][id % 4u];
+    let uv = position / terrain.texture_size;
+    let x = textureSample(terrain_textures, terrain_sampler, uv.zy, layer).rgb;
+    let y = textureSample(terrain_textures, terrain_sampler, uv.xz, layer).rgb;
+    let z = textureSample(terrain_textures, terrain_sampler, uv.xy, layer).rgb;
+    let detail = (x * axis_weights.x + y * axis_weights.y + z * axis_weights.z) / terrain.layer_means[layer].rgb;
+    return terrain.tints[id].rgb * detail;
+}
+
+@fragment
+fn fragment(in: TerrainVertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var mesh: VertexOutput;
+    mesh.position = in.position;
+    mesh.world_position = in.world_position;
+    mesh.world_normal = in.world_normal;
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    mesh.instance_index = in.instance_index;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    mesh.visibility_range_dither = in.visibility_range_dither;
+#endif
+    var pbr_input = pbr_input_from_standard_material(mesh, is_front);
+
+    var axis_weights = pow(abs(normalize(in.world_normal)), vec3<f32>(terrain.triplanar_sharpness));
+    axis_weights /= axis_weights.x + axis_weights.y + axis_weights.z;
+    let weights = in.material_weights / max(dot(in.material_weights, vec4<f32>(1.0)), 1.0e-4);
+    var color = vec3<f32>(0.0);
+    for (var slot = 0u; slot < 4u; slot += 1u) {
+        let id = (in.material_ids >> (slot * 8u)) & 0xffu;
+        color += material_color(id, in.triplanar_position, axis_weights) * weights[slot];
+    }
+    pbr_input.material.base_color = vec4<f32>(pbr_input.material.base_color.rgb * color, pbr_input.material.base_color.a);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,269 @@
+// Runs the planetoid simulation and bakes it without opening a window, so world generation can be scripted.
+//
+// voyage-worldgen [--seed <seed>] [--subdivisions <n>] [--plates <n>] [--steps <n>] [--width <pixels>]
+//                 [--droplets <n>] [--thermal-iterations <n>] [--tilt <degrees>] [--output <directory>]
+//
+// The droplets and the thermal iterations set how long each erosion pass runs on the elevation map, 0 skips it.
+// The baked maps and a summary.json with the plate statistics are written to the output directory, which defaults to
+// assets/planets/<seed> so a density graph can sample the elevation from planets/<seed>/elevation.f32,
+// and its planet can take the climate and the rock layers from planets/<seed>/climate.ron and layers.ron.
+// The same arguments always write the same files, so the outputs of two commits can be compared directly.
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::ExitCode,
+    time::Instant,
+};
+
+use serde::Serialize;
+use voyage_engine::{
+    seed::WorldSeed,
+    terrain::{
+        bake::{bake_planetoid_simulation_state, BakeManifest, BakeSettings},
+        climate::{ClimateSettings, EARTH_AXIAL_TILT_DEGREES},
+        erosion::{HydraulicErosionSettings, ThermalErosionSettings},
+        simulation::{CrustType, PlanetoidSimulation, TectonicsConfig, TIME_STEP},
+    },
+};
+
+const SUMMARY_FILE: &str = "summary.json";
+
+const USAGE: &str = "usage: voyage-worldgen [--seed <seed>] [--subdivisions <n>] [--plates <n>] [--steps <n>] [--width <pixels>] \
+                     [--droplets <n>] [--thermal-iterations <n>] [--tilt <degrees>] [--output <directory>]";
+
+struct Arguments {
+    seed: WorldSeed,
+    subdivisions: u32,
+    plates: u32,
+    steps: u32,
+    width: u32,
+    droplets: u32,
+    thermal_iterations: u32,
+    // The axial tilt in degrees.
+    tilt: f32,
+    output: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    world_seed: u64,
+    simulation_seed: u64,
+    subdivisions: u32,
+    vertices: usize,
+    steps: u32,
+    droplets: u32,
+    thermal_iterations: u32,
+    axial_tilt_degrees: f32,
+    simulated_years: f64,
+    radius_km: f32,
+    continental_fraction: f32,
+    min_elevation_km: f32,
+    max_elevation_km: f32,
+    mean_elevation_km: f32,
+    // The plates which still cover part of the surface, in the order they were created.
+    plates: Vec<PlateSummary>,
+}
+
+#[derive(Serialize)]
+struct PlateSummary {
+    index: usize,
+    vertices: usize,
+    area_km2: f32,
+    continental_fraction: f32,
+    mean_elevation_km: f32,
+    speed_mm_per_year: f32,
+    axis: [f32; 3],
+    centroid: [f32; 3],
+}
+
+fn main() -> ExitCode {
+    let arguments = match parse_arguments(env::args().skip(1)) {
+        Ok(Some(arguments)) => arguments,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = TectonicsConfig {
+        seed: arguments.seed.derive("tectonics"),
+        subdivisions: arguments.subdivisions,
+        plate_count: arguments.plates,
+        ..Default::default()
+    };
+    let start: Instant = Instant::now();
+    let mut simulation = PlanetoidSimulation::generate(&config);
+    println!(
+        "Generated {} vertices and {} plates for seed {} in {:?}",
+        simulation.sphere.len(),
+        simulation.plates.len(),
+        arguments.seed.0,
+        start.elapsed()
+    );
+
+    let start: Instant = Instant::now();
+    simulation.run(arguments.steps);
+    println!(
+        "Simulated {} million years in {:?}",
+        arguments.steps as f32 * TIME_STEP / 1_000_000.0,
+        start.elapsed()
+    );
+
+    let directory: PathBuf = arguments
+        .output
+        .unwrap_or_else(|| PathBuf::from("assets/planets").join(arguments.seed.0.to_string()));
+    let settings = BakeSettings {
+        width: arguments.width,
+        hydraulic: (arguments.droplets > 0).then(|| HydraulicErosionSettings {
+            iterations: arguments.droplets,
+            ..Default::default()
+        }),
+        thermal: (arguments.thermal_iterations > 0).then(|| ThermalErosionSettings {
+            iterations: arguments.thermal_iterations,
+            ..Default::default()
+        }),
+        climate: ClimateSettings {
+            axial_tilt_degrees: arguments.tilt,
+            ..Default::default()
+        },
+    };
+    let start: Instant = Instant::now();
+    let manifest: BakeManifest = match bake_planetoid_simulation_state(&simulation, &arguments.seed, &settings, &directory) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            eprintln!("Failed to bake to {}: {}", directory.display(), error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let summary: Summary = summarize(&simulation, &manifest, &arguments);
+    let written = serde_json::to_string_pretty(&summary)
+        .map_err(|error| error.to_string())
+        .and_then(|json| fs::write(directory.join(SUMMARY_FILE), json).map_err(|error| error.to_string()));
+    if let Err(error) = written {
+        eprintln!("Failed to write the summary: {}", error);
+        return ExitCode::FAILURE;
+    }
+
+    println!(
+        "Baked {}x{} maps to {} in {:?}, elevation from {:.2} km to {:.2} km",
+        manifest.width,
+        manifest.height,
+        directory.display(),
+        start.elapsed(),
+        manifest.min_elevation,
+        manifest.max_elevation
+    );
+    ExitCode::SUCCESS
+}
+
+// Returns None when the usage was asked for.
+fn parse_arguments(mut args: impl Iterator<Item = String>) -> Result<Option<Arguments>, String> {
+    let defaults = TectonicsConfig::default();
+    let mut arguments = Arguments {
+        seed: WorldSeed::default(),
+        subdivisions: defaults.subdivisions,
+        plates: defaults.plate_count,
+        steps: 50,
+        width: BakeSettings::default().width,
+        droplets: HydraulicErosionSettings::default().iterations,
+        thermal_iterations: ThermalErosionSettings::default().iterations,
+        tilt: EARTH_AXIAL_TILT_DEGREES,
+        output: None,
+    };
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(None);
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_owned(), value.to_owned()),
+            None => {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                (arg, value)
+            }
+        };
+        let number = |value: &str| -> Result<u32, String> {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("{} expects a whole number, got {}", name, value))
+        };
+        match name.as_str() {
+            "--seed" => arguments.seed = WorldSeed::from_text(&value),
+            "--subdivisions" => arguments.subdivisions = number(&value)?,
+            "--plates" => arguments.plates = number(&value)?,
+            "--steps" => arguments.steps = number(&value)?,
+            "--width" => arguments.width = number(&value)?,
+            "--droplets" => arguments.droplets = number(&value)?,
+            "--thermal-iterations" => arguments.thermal_iterations = number(&value)?,
+            "--tilt" => {
+                arguments.tilt = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|tilt| (0.0..=180.0).contains(tilt))
+                    .ok_or_else(|| format!("{} expects degrees from 0 to 180, got {}", name, value))?
+            }
+            "--output" => arguments.output = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown argument {}", name)),
+        }
+    }
+    // Each subdivision quadruples the vertices, 10 already has over ten million.
+    if arguments.subdivisions > 10 {
+        return Err(format!("{} subdivisions is too many, the most is 10", arguments.subdivisions));
+    }
+    Ok(Some(arguments))
+}
+
+fn summarize(simulation: &PlanetoidSimulation, manifest: &BakeManifest, arguments: &Arguments) -> Summary {
+    let vertex_area: f32 = simulation.vertex_area();
+    let is_continental = |vertex: usize| simulation.crust_type(vertex) == CrustType::Continental;
+    let vertices: usize = simulation.sphere.len();
+
+    let plates: Vec<PlateSummary> = simulation
+        .plates
+        .iter()
+        .enumerate()
+        .filter(|(_, plate)| plate.area > 0)
+        .map(|(index, plate)| {
+            let members: Vec<usize> = (0..vertices)
+                .filter(|vertex| simulation.crust[*vertex].plate as usize == index)
+                .collect();
+            let continental: usize = members.iter().filter(|vertex| is_continental(**vertex)).count();
+            let elevation: f32 = members.iter().map(|vertex| simulation.elevation(*vertex)).sum();
+            PlateSummary {
+                index,
+                vertices: plate.area,
+                area_km2: plate.area as f32 * vertex_area,
+                continental_fraction: continental as f32 / plate.area as f32,
+                mean_elevation_km: elevation / plate.area as f32,
+                // The speed at the equator of the rotation, in mm.
+                speed_mm_per_year: plate.angular_speed * simulation.radius * 1.0e6,
+                axis: plate.axis.to_array(),
+                centroid: plate.centroid.to_array(),
+            }
+        })
+        .collect();
+
+    let elevations = (0..vertices).map(|vertex| simulation.elevation(vertex));
+    Summary {
+        world_seed: manifest.world_seed,
+        simulation_seed: manifest.simulation_seed,
+        subdivisions: arguments.subdivisions,
+        vertices,
+        steps: arguments.steps,
+        droplets: arguments.droplets,
+        thermal_iterations: arguments.thermal_iterations,
+        axial_tilt_degrees: manifest.axial_tilt,
+        simulated_years: simulation.age,
+        radius_km: simulation.radius,
+        continental_fraction: (0..vertices).filter(|vertex| is_continental(*vertex)).count() as f32 / vertices as f32,
+        min_elevation_km: elevations.clone().fold(f32::INFINITY, f32::min),
+        max_elevation_km: elevations.clone().fold(f32::NEG_INFINITY, f32::max),
+        mean_elevation_km: elevations.sum::<f32>() / vertices as f32,
+        plates,
+    }
+}
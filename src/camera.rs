@@ -1,5 +1,5 @@
 use crate::{
-    config::Bindings, input::Input, player::Player, utils::{exp_decay, InterpolatedValue}
+    config::Bindings, input::Input, origin::GridCell, player::Player, utils::{exp_decay, InterpolatedValue}
 };
 use avian3d::prelude::TransformInterpolation;
 use bevy::{
@@ -75,6 +75,7 @@ pub struct FreeCamera;
 pub fn create_free_camera(mut commands: Commands) {
     commands.spawn((
         Transform::from_xyz(0.0, 5.0, 0.0).looking_to(Vec3::ZERO, Vec3::Y),
+        GridCell::default(),
         FreeCamera,
     ));
 }
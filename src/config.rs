@@ -25,6 +25,8 @@ pub struct Bindings {
     pub move_descend: KeyCode,
     pub action_sprint: Binding,
     pub action_interact: Binding,
+    pub action_build: Binding,
+    pub action_cycle_brush: KeyCode,
     pub action_toggle_crouched: Binding,
     pub action_screenshot: Binding,
     pub action_toggle_cursor_focus: KeyCode,
@@ -53,6 +55,11 @@ impl Default for Bindings {
                 key: KeyCode::KeyE,
                 button: GamepadButton::East,
             },
+            action_build: Binding {
+                key: KeyCode::KeyQ,
+                button: GamepadButton::West,
+            },
+            action_cycle_brush: KeyCode::KeyB,
             action_screenshot: Binding {
                 key: KeyCode::Equal,
                 button: GamepadButton::Start,
@@ -0,0 +1,11 @@
+// The game and the tools share these modules, the game is in main.rs and the tools are in src/bin.
+pub mod camera;
+pub mod config;
+pub mod input;
+pub mod origin;
+pub mod physics;
+pub mod player;
+pub mod seed;
+pub mod terrain;
+pub mod user_interface;
+pub mod utils;
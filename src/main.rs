@@ -1,12 +1,4 @@
-pub mod camera;
-pub mod config;
-pub mod input;
-mod player;
-mod terrain;
-mod user_interface;
-mod utils;
-
-use bevy::color::palettes::tailwind::{AMBER_400, SKY_400, ZINC_200};
+use bevy::color::palettes::tailwind::{AMBER_400, ZINC_200};
 use bevy::core_pipeline::experimental::taa::TemporalAntiAliasPlugin;
 use bevy::pbr::{CascadeShadowConfigBuilder, ExtendedMaterial};
 
@@ -24,27 +16,33 @@ use bevy_sun_move::random_stars::{RandomStarsPlugin, StarSpawner};
 use bevy_sun_move::{SkyCenter, SunMovePlugin};
 use bevy_turborand::prelude::RngPlugin;
 
-use camera::{
+use voyage_engine::camera::{
     create_camera, create_free_camera, load_toggle_camera_soundfxs, move_free_camera,
     play_toggle_camera_soundfx, swap_camera_target, take_screenshot, CameraConfig,
     ToggleCameraEvent,
 };
-use config::{Bindings, EngineSettings};
-use player::PlayerPlugin;
-use user_interface::DebugInterfacePlugin;
+use voyage_engine::config::{Bindings, EngineSettings};
+use voyage_engine::origin::{FloatingOriginPlugin, GridCell};
+use voyage_engine::physics::GamePhysicsPlugin;
+use voyage_engine::player::PlayerPlugin;
+use voyage_engine::seed::{log_world_seed, WorldSeed};
+use voyage_engine::terrain::{climate::EARTH_AXIAL_TILT_DEGREES, planet::Planetoid, TerrainPlugin};
+use voyage_engine::user_interface::DebugInterfacePlugin;
 
 use std::time::Duration;
 
-use utils::{detect_toggle_cursor, generate_plane_mesh};
+use voyage_engine::utils::detect_toggle_cursor;
 
-use crate::input::update_input_resource;
-use crate::utils::{initial_grab_cursor, format_percentage};
+use voyage_engine::input::update_input_resource;
+use voyage_engine::utils::{initial_grab_cursor, format_percentage};
 
 #[derive(Component)]
 struct Sun;
 
 fn main() {
+    let world_seed: WorldSeed = WorldSeed::resolve();
     App::new()
+        .insert_resource(world_seed)
         .init_resource::<Bindings>()
         .insert_resource(EngineSettings { ..default() })
         .insert_resource(DirectionalLightShadowMap { size: 4098 })
@@ -53,13 +51,14 @@ fn main() {
         .add_plugins((
             DefaultPlugins,
             bevy_panic_handler::PanicHandler::new().build(),
-            RngPlugin::new().with_rng_seed(0),
+            RngPlugin::new().with_rng_seed(world_seed.derive("global_rng")),
             //TransformInterpolationPlugin::default(),
             PhysicsPlugins::default(),
             PhysicsDebugPlugin::default(),
             DebugInterfacePlugin,
             TemporalAntiAliasPlugin,
             PlayerPlugin,
+            TerrainPlugin,
             AudioPlugin,
             AtmospherePlugin,
             InfiniteGridPlugin,
@@ -67,6 +66,8 @@ fn main() {
             SunMovePlugin,
             RandomStarsPlugin,
         ))
+        // The tuple above is at the 15 plugin limit.
+        .add_plugins((GamePhysicsPlugin, FloatingOriginPlugin))
         .add_systems(
             PreStartup,
             (
@@ -77,7 +78,7 @@ fn main() {
         )
         .add_systems(
             Startup,
-            (setup, start_background_audio, load_toggle_camera_soundfxs, initial_grab_cursor).chain(),
+            (log_world_seed, setup, start_background_audio, load_toggle_camera_soundfxs, initial_grab_cursor).chain(),
         )
         .add_systems(
             Update,
@@ -88,6 +89,7 @@ fn main() {
                 move_free_camera,
                 play_toggle_camera_soundfx,
                 take_screenshot,
+                match_sky_to_planet.run_if(resource_exists_and_changed::<Planetoid>),
             ),
         )
         .add_event::<ToggleCameraEvent>()
@@ -107,6 +109,13 @@ fn start_background_audio(asset_server: Res<AssetServer>, audio: Res<Audio>) {
         .looped();
 }
 
+// The seasons of the sky follow the tilt the planet's climate was baked with.
+fn match_sky_to_planet(planet: Res<Planetoid>, mut skies: Query<&mut SkyCenter>) {
+    for mut sky in skies.iter_mut() {
+        sky.planet_tilt_degrees = planet.axial_tilt;
+    }
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -142,7 +151,7 @@ fn setup(
         SkyCenter {
             sun: sun_id,
             latitude_degrees: 51.5,    // e.g., London's approximate latitude
-            planet_tilt_degrees: 23.5, // Earth's axial tilt
+            planet_tilt_degrees: EARTH_AXIAL_TILT_DEGREES, // Replaced by the tilt of the planet once it loads
             year_fraction: 0.25,       // e.g., Summer Solstice
             cycle_duration_secs: 12000.0, // 60-second day/night cycle
             current_cycle_time: 6000.0, // Start at midnight
@@ -156,29 +165,6 @@ fn setup(
         }, // Optional
     ));
 
-    // Plane
-    let plane_size: f32 = 128.0;
-    let plane_thickness: f32 = 0.005;
-
-    commands.spawn((
-        RigidBody::Static,
-        Collider::cuboid(plane_size, plane_thickness, plane_size),
-        Transform::from_xyz(0.0, 2.0, 0.0),
-        MeshMaterial3d(extended_materials.add(ExtendedMaterial {
-            base: StandardMaterial {
-                base_color: SKY_400.into(),
-                ..default()
-            },
-            extension: BlockoutMaterialExt::default(),
-        })),
-        Mesh3d(generate_plane_mesh(
-            &mut meshes,
-            plane_size,
-            plane_size,
-            1.0 / 16.0,
-        )),
-    ));
-
     // spawn a cube with physics and a material
     commands.spawn((
         RigidBody::Dynamic,
@@ -190,6 +176,7 @@ fn setup(
             ..default()
         })),
         Transform::from_xyz(2.0, 25.0, 2.0),
+        GridCell::default(),
     ));
 
     // spawn a cube with physics and a material
@@ -211,6 +198,7 @@ fn setup(
             extension: BlockoutMaterialExt::default(),
         })),
         Transform::from_xyz(4.0, (mini_plateform_cube_size / 2.0) + 2.0, 8.0),
+        GridCell::default(),
     ));
 
     // spawn a cube with physics and a material
@@ -232,6 +220,7 @@ fn setup(
             extension: BlockoutMaterialExt::default(),
         })),
         Transform::from_xyz(8.0, (small_plateform_cube_size / 2.0) + 2.0, 8.0),
+        GridCell::default(),
     ));
 
     // spawn a cube with physics and a material
@@ -253,6 +242,7 @@ fn setup(
             extension: BlockoutMaterialExt::default(),
         })),
         Transform::from_xyz(16.0, (medium_plateform_cube_size / 2.0) + 2.0, 8.0),
+        GridCell::default(),
     ));
 
     // spawn a cube with physics and a material
@@ -274,5 +264,6 @@ fn setup(
             extension: BlockoutMaterialExt::default(),
         })),
         Transform::from_xyz(24.0, (large_plateform_cube_size / 2.0) + 2.0, 8.0),
+        GridCell::default(),
     ));
 }
@@ -0,0 +1,126 @@
+use avian3d::{interpolation::TranslationEasingState, prelude::Position};
+use bevy::{
+    app::{App, Plugin, PostUpdate},
+    ecs::{hierarchy::ChildOf, schedule::IntoScheduleConfigs},
+    log::info,
+    math::{DVec3, I64Vec3, Vec3},
+    prelude::{Component, Has, Query, ResMut, Resource, Transform, TransformSystem, Without},
+};
+
+use crate::physics::GravityField;
+
+// The length of one side of a grid cell in world units, the focus is kept within half a cell of the origin.
+// A whole number so the voxel coordinates of a cell are exact.
+pub const GRID_CELL_SIZE_I64: i64 = 1024;
+pub const GRID_CELL_SIZE_F64: f64 = GRID_CELL_SIZE_I64 as f64;
+
+/// The cell of the large world grid a `Transform` is relative to.
+///
+/// A `Transform` is only precise near its origin, so the absolute position of an entity is the center of its
+/// cell plus its translation, and the cell of every shifted entity follows the [`FloatingOrigin`].
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct GridCell(pub I64Vec3);
+
+impl GridCell {
+    /// The cell whose center is closest to the absolute position.
+    pub fn containing(position: DVec3) -> Self {
+        GridCell((position / GRID_CELL_SIZE_F64).round().as_i64vec3())
+    }
+
+    /// The absolute position of the center of the cell.
+    pub fn center(&self) -> DVec3 {
+        self.0.as_dvec3() * GRID_CELL_SIZE_F64
+    }
+
+    /// The absolute position of a translation relative to this cell.
+    pub fn absolute(&self, translation: Vec3) -> DVec3 {
+        self.center() + translation.as_dvec3()
+    }
+
+    /// The translation relative to this cell of an absolute position.
+    pub fn relative(&self, position: DVec3) -> Vec3 {
+        (position - self.center()).as_vec3()
+    }
+
+    /// The absolute coordinate of the voxel containing a translation relative to this cell.
+    pub fn voxel(&self, translation: Vec3) -> I64Vec3 {
+        self.0 * GRID_CELL_SIZE_I64 + translation.floor().as_i64vec3()
+    }
+}
+
+/// The cell the rendered and simulated world is centered on.
+///
+/// Systems working in absolute coordinates, like the terrain, convert through this cell.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FloatingOrigin {
+    pub cell: GridCell,
+}
+
+/// Marks the entity the origin follows, the player.
+#[derive(Component)]
+pub struct FloatingOriginFocus;
+
+pub struct FloatingOriginPlugin;
+
+impl Plugin for FloatingOriginPlugin {
+    fn build(&self, app: &mut App) {
+        // The shift happens before the transforms are propagated, so the frame is rendered and the next frame is
+        // simulated with the new origin.
+        app.init_resource::<FloatingOrigin>().add_systems(
+            PostUpdate,
+            recenter_floating_origin.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// Shifts every root entity with a [`GridCell`] by whole cells once the focus leaves the cell at the origin.
+/// The physics position is shifted with the transform, and a radial gravity field moves with the world.
+/// Interpolated entities have the start and end of their easing shifted too, otherwise the next frame would ease
+/// them back towards where they were before the shift and they would snap by a cell.
+pub fn recenter_floating_origin(
+    mut origin: ResMut<FloatingOrigin>,
+    mut gravity: ResMut<GravityField>,
+    mut cells: Query<
+        (
+            &mut Transform,
+            &mut GridCell,
+            Option<&mut Position>,
+            Option<&mut TranslationEasingState>,
+            Has<FloatingOriginFocus>,
+        ),
+        Without<ChildOf>,
+    >,
+) {
+    let Some(focus) = cells
+        .iter()
+        .find_map(|(transform, _, _, _, is_focus)| is_focus.then_some(transform.translation))
+    else {
+        return;
+    };
+    let shift: I64Vec3 = GridCell::containing(focus.as_dvec3()).0;
+    if shift == I64Vec3::ZERO {
+        return;
+    }
+
+    let offset: Vec3 = (shift.as_dvec3() * GRID_CELL_SIZE_F64).as_vec3();
+    for (mut transform, mut cell, position, easing, _) in cells.iter_mut() {
+        transform.translation -= offset;
+        cell.0 += shift;
+        if let Some(mut position) = position {
+            position.0 -= offset;
+        }
+        if let Some(mut easing) = easing {
+            if let Some(start) = easing.start.as_mut() {
+                *start -= offset;
+            }
+            if let Some(end) = easing.end.as_mut() {
+                *end -= offset;
+            }
+        }
+    }
+    if let GravityField::Radial { center, strength } = *gravity {
+        *gravity = GravityField::Radial { center: center - offset, strength };
+    }
+    origin.cell.0 += shift;
+    info!("Moved the floating origin to cell {}", origin.cell.0);
+}
@@ -0,0 +1,86 @@
+use avian3d::prelude::{Gravity, GravityScale, LinearVelocity, PhysicsLayer, Position, RigidBody};
+use bevy::{
+    app::{App, FixedUpdate, Plugin},
+    ecs::schedule::{common_conditions::resource_changed, IntoScheduleConfigs},
+    math::Vec3,
+    prelude::{Query, Res, ResMut, Resource},
+    time::Time,
+};
+
+/// The collision layers used by the game, a collider is on the default layer unless given `CollisionLayers`.
+#[derive(PhysicsLayer, Default, Clone, Copy, Debug)]
+pub enum GameLayer {
+    #[default]
+    Default,
+    Terrain,
+}
+
+/// The gravity acting on every dynamic body.
+///
+/// Uniform gravity is handed to avian's [`Gravity`], radial gravity pulls towards the center and is applied
+/// to the velocity of each body by [`apply_radial_gravity`], as avian only supports a single direction.
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub enum GravityField {
+    Uniform(Vec3),
+    Radial { center: Vec3, strength: f32 },
+}
+
+impl Default for GravityField {
+    fn default() -> Self {
+        GravityField::Uniform(Vec3::NEG_Y * 9.81)
+    }
+}
+
+impl GravityField {
+    pub fn acceleration_at(&self, position: Vec3) -> Vec3 {
+        match self {
+            GravityField::Uniform(acceleration) => *acceleration,
+            GravityField::Radial { center, strength } => (*center - position).normalize_or_zero() * *strength,
+        }
+    }
+
+    /// The direction opposite to gravity at the position, world up when there is no gravity.
+    pub fn up_at(&self, position: Vec3) -> Vec3 {
+        (-self.acceleration_at(position)).try_normalize().unwrap_or(Vec3::Y)
+    }
+}
+
+pub struct GamePhysicsPlugin;
+
+impl Plugin for GamePhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GravityField>().add_systems(
+            FixedUpdate,
+            (
+                sync_avian_gravity.run_if(resource_changed::<GravityField>),
+                apply_radial_gravity,
+            ),
+        );
+    }
+}
+
+fn sync_avian_gravity(field: Res<GravityField>, mut gravity: ResMut<Gravity>) {
+    gravity.0 = match *field {
+        GravityField::Uniform(acceleration) => acceleration,
+        GravityField::Radial { .. } => Vec3::ZERO,
+    };
+}
+
+/// Accelerates every dynamic body towards the center of a radial gravity field, scaled by its [`GravityScale`].
+pub fn apply_radial_gravity(
+    field: Res<GravityField>,
+    time: Res<Time>,
+    mut bodies: Query<(&RigidBody, &Position, &mut LinearVelocity, Option<&GravityScale>)>,
+) {
+    if !matches!(*field, GravityField::Radial { .. }) {
+        return;
+    }
+    let delta: f32 = time.delta_secs();
+    for (rigid_body, position, mut linear_velocity, gravity_scale) in bodies.iter_mut() {
+        if !rigid_body.is_dynamic() {
+            continue;
+        }
+        let scale: f32 = gravity_scale.map_or(1.0, |scale| scale.0);
+        linear_velocity.0 += field.acceleration_at(position.0) * scale * delta;
+    }
+}
@@ -17,6 +17,11 @@ pub struct PlayerControlConfig {
     pub (crate) gamepad_look_sensitivity: f32,
     pub (crate) enable_view_bobbing: bool,
     pub (crate) crouched_height_factor: f32,
+    // The player swims once its center is this far below the surface of the water.
+    pub (crate) swim_depth: f32,
+    pub (crate) swim_speed_factor: f32,
+    // The force pushing the player up through the water while jump is held.
+    pub (crate) swim_up_force: f32,
 }
 
 impl Default for PlayerControlConfig {
@@ -37,6 +42,9 @@ impl Default for PlayerControlConfig {
             gamepad_look_sensitivity: 0.0012, // This value was made up by me!
             enable_view_bobbing: true,
             crouched_height_factor: 0.80,
+            swim_depth: 0.5,
+            swim_speed_factor: 0.5,
+            swim_up_force: 150.0,
         }
     }
 }
\ No newline at end of file
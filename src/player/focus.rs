@@ -1,7 +1,7 @@
 use bevy::{
     prelude::*,
 };
-use crate::input::Input;
+use crate::{input::Input, physics::GravityField};
 
 use super::{Player};
 
@@ -31,17 +31,25 @@ pub fn camera_look_system(
 }
 
 // This function and many of its helpers are ripped from, bevy_fly_cam.
+// The yaw turns around the local up of the player, which is the gravity up once the player is aligned to it.
 pub fn player_rotation_system(
     mut player_query: Query<&mut Transform, With<Player>>,
     input: Res<Input>,
 ) {
     for mut player_transform in player_query.iter_mut() {
-        let (mut player_yaw, player_pitch, player_roll) =
-            player_transform.rotation.to_euler(EulerRot::default());
+        player_transform.rotate_local_y(-(input.direction.x).to_radians());
+    }
+}
 
-        player_yaw -= (input.direction.x).to_radians();
-        //info!("YAW: {}", player_yaw);
-        player_transform.rotation =
-            Quat::from_euler(EulerRot::default(), player_yaw, player_pitch, player_roll);
+// Turns the player so its up matches the gravity up, using the shortest rotation so the facing direction is kept.
+// The downward ride height ray is in the local space of the player, so it follows as well.
+pub fn align_player_to_gravity(
+    mut player_query: Query<&mut Transform, With<Player>>,
+    gravity: Res<GravityField>,
+) {
+    for mut player_transform in player_query.iter_mut() {
+        let up: Vec3 = gravity.up_at(player_transform.translation);
+        let alignment: Quat = Quat::from_rotation_arc(player_transform.up().as_vec3(), up);
+        player_transform.rotation = (alignment * player_transform.rotation).normalize();
     }
 }
@@ -7,16 +7,16 @@ use actions::{
     },
 };
 use avian3d::prelude::*;
-use bevy::{log::info, prelude::*};
+use bevy::{log::info, math::DVec3, prelude::*};
 
 use crate::{
-    camera::{smooth_camera, GameCamera}, input::{Input}, player::{
+    camera::{smooth_camera, GameCamera}, input::{Input}, origin::{FloatingOrigin, FloatingOriginFocus, GridCell}, player::{
         debug::{create_player_debug, update_debug_is_moving, update_debug_is_sprinting, update_debug_linear_velocity, update_debug_movement_speed_current, update_debug_movement_speed_target, update_debug_movement_vector_current, update_debug_movement_vector_decay, update_debug_movement_vector_target, update_debug_position, update_debug_rotation}, focus::player_rotation_system
-    }, utils::InterpolatedValue
+    }, terrain::{planet::Planetoid, water::Buoyancy}, utils::InterpolatedValue
 };
 use body::Body;
 use config::PlayerControlConfig;
-use focus::{camera_look_system, Focus};
+use focus::{align_player_to_gravity, camera_look_system, Focus};
 use motion::{
     compute_motion, Motion
 };
@@ -49,6 +49,7 @@ impl Plugin for PlayerPlugin {
         app.add_systems(
             FixedUpdate,
             (
+                align_player_to_gravity,
                 update_player_stance,
                 camera_look_system,
                 player_rotation_system,
@@ -78,6 +79,10 @@ impl Plugin for PlayerPlugin {
             )
                 .chain(),
         );
+        app.add_systems(
+            Update,
+            place_player_on_planet.run_if(resource_added::<Planetoid>),
+        );
         app.add_event::<FootstepEvent>();
         info!("Initialized Player plugin");
     }
@@ -109,6 +114,7 @@ pub struct PlayerBundle {
     mass: Mass,
     locked_axes: LockedAxes,
     gravity_scale: GravityScale,
+    buoyancy: Buoyancy,
     transform: Transform,
     rigid_body: RigidBody,
 }
@@ -129,6 +135,12 @@ pub fn spawn_player(
                 external_force: ExternalForce::new([0.0, 0.0, 0.0].into()),
                 external_impulse: ExternalImpulse::new([0.0, 0.0, 0.0].into()),
                 gravity_scale: GravityScale(1.0),
+                // Slightly lighter than water, so the player floats with the head above the surface.
+                buoyancy: Buoyancy {
+                    relative_density: 0.9,
+                    half_height: 1.0,
+                    drag: 2.0,
+                },
                 transform: Transform::from_xyz(0.0, 16.0, 0.0),
                 downward_ray: RayCaster::new(Vec3::ZERO, Dir3::NEG_Y),
                 ray_hits: RayHits::default(),
@@ -181,6 +193,8 @@ pub fn spawn_player(
                 ..default()
             })),
             TransformInterpolation,
+            GridCell::default(),
+            FloatingOriginFocus,
             Player,
         ))
         .with_children(|parent| {
@@ -224,3 +238,17 @@ fn attached_camera_system(
         }
     }
 }
+
+// The player spawns near the origin, which is deep inside a planet, so move it above the highest possible surface.
+fn place_player_on_planet(
+    planet: Res<Planetoid>,
+    origin: Res<FloatingOrigin>,
+    mut player_query: Query<(&mut Transform, &mut LinearVelocity), With<Player>>,
+) {
+    for (mut player_transform, mut linear_vel) in player_query.iter_mut() {
+        let surface: f64 = (planet.radius() + planet.relief) as f64 + 2.0;
+        player_transform.translation = origin.cell.relative(DVec3::Y * surface);
+        linear_vel.0 = Vec3::ZERO;
+        info!("Placed player on the surface of {}", planet.name);
+    }
+}
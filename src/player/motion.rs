@@ -68,6 +68,11 @@ pub fn compute_motion(
         }
     }
 
+    // Swimming is slower than walking.
+    if stance.current == StanceType::Swimming {
+        motion.movement_speed.target *= player_config.swim_speed_factor;
+    }
+
     // Apply lineaer interpolation to move the speed transition.
     motion.movement_speed.current = exp_decay(
         motion.movement_speed.current,
@@ -116,12 +121,15 @@ pub fn compute_motion(
     // * APPLY MOVEMENT_VECTOR TO PLAYER TRANSFORM LINEAR VELOCITY
 
     // We don't need to lerp here just setting the real value to as we already lerp the current_movement_vector and current_movement_speed.
+    // The player is aligned to gravity, so its up is the gravity up and movement happens in the plane tangent to it.
+    let up: Vec3 = player_transform.up().as_vec3();
+
+    let is_grounded_or_swimming: bool =
+        stance.current == StanceType::Standing || stance.current == StanceType::Swimming;
 
-    if stance.current == StanceType::Standing {
-        motion.linear_velocity_interp.target.x =
-            motion.movement_vector.current.x * motion.movement_speed.current;
-        motion.linear_velocity_interp.target.z =
-            motion.movement_vector.current.z * motion.movement_speed.current;
+    if is_grounded_or_swimming {
+        motion.linear_velocity_interp.target =
+            motion.movement_vector.current.reject_from_normalized(up) * motion.movement_speed.current;
     }
 
     motion.linear_velocity_interp.current = exp_decay::<Vec3>(
@@ -131,16 +139,13 @@ pub fn compute_motion(
         time.delta_secs(),
     );
 
-    if stance.current == StanceType::Standing {
-        linear_vel.x = motion.linear_velocity_interp.current.x;
-        linear_vel.z = motion.linear_velocity_interp.current.z;
+    if is_grounded_or_swimming {
+        // Keep the velocity along the up vector, the ride height spring or the water controls it.
+        let vertical_vel: Vec3 = up * linear_vel.dot(up);
+        linear_vel.0 = vertical_vel + motion.linear_velocity_interp.current;
     } else {
-        linear_vel.x += input.movement.x
-            * motion.linear_velocity_interp.current.x
-            * movement_scale
-            * time.delta().as_secs_f32();
-        linear_vel.z += input.movement.z
-            * motion.linear_velocity_interp.current.z
+        linear_vel.0 += motion.linear_velocity_interp.current
+            * input.movement.length().min(1.0)
             * movement_scale
             * time.delta().as_secs_f32();
     }
@@ -163,17 +168,23 @@ pub fn apply_spring_force(
     external_force: &mut ExternalForce,
     ray_length: f32,
     ride_height: f32,
+    up: Vec3,
 ) {
     // Find the diference between how close the capsule is to the surface beneath it.
     // Compute this value by subtracting the ray length from the set ride height
     // to find the diference in position.
     let spring_offset: f32 = f32::abs(ray_length) - ride_height;
     let spring_force: f32 =
-        (spring_offset * config.ride_spring_strength) - (-linear_vel.y * config.ride_spring_damper);
+        (spring_offset * config.ride_spring_strength) - (-linear_vel.dot(up) * config.ride_spring_damper);
 
     /* Now we apply our spring force vector in the direction to return the bodies distance from the ground towards RIDE_HEIGHT. */
     external_force.clear();
-    external_force.apply_force(Vec3::from((0.0, -spring_force, 0.0)));
+    external_force.apply_force(up * -spring_force);
+}
+
+/// Pushes the player up through the water while the jump button is held.
+pub fn apply_swim_force(config: &Res<PlayerControlConfig>, external_force: &mut ExternalForce, up: Vec3) {
+    external_force.apply_force(up * config.swim_up_force);
 }
 
 pub fn apply_jump_force(
@@ -183,6 +194,7 @@ pub fn apply_jump_force(
     linear_vel: &mut LinearVelocity,
     ray_length: f32,
     body: &Body,
+    up: Vec3,
 ) {
     // Apply the stance cooldown now that we are jumping.
     stance.lockout = player_config.stance_lockout;
@@ -199,21 +211,14 @@ pub fn apply_jump_force(
 
     // remove any previous impulse on the object.
     external_impulse.clear();
-    // find the movement vector in the x and z direction.
+    // find the direction halfway between the movement direction and up.
     let normalized_midpoint_movement_vector: Vec3 = linear_vel
         .normalize_or_zero()
-        .mul_add(Vec3::ONE, Vec3::Y)
+        .mul_add(Vec3::ONE, up)
         .normalize_or_zero();
 
     // apply the jump force.
-    external_impulse.apply_impulse(
-        Vec3::from((
-            normalized_midpoint_movement_vector.x * dynamic_jump_strength,
-            normalized_midpoint_movement_vector.y * dynamic_jump_strength,
-            normalized_midpoint_movement_vector.z * dynamic_jump_strength,
-        ))
-        .into(),
-    );
+    external_impulse.apply_impulse(normalized_midpoint_movement_vector * dynamic_jump_strength);
 
     info!(
         "\tJumped with {}/{} due to distance to ground, jump_factor {}, of ray length: {}",
@@ -233,7 +238,7 @@ pub fn apply_jump_force(
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// let ray_length = 3.0;
 /// let jump_force_factor = compute_clamped_jump_force_factor(ray_length);
 /// println!("Jump Force Factor: {}", jump_force_factor);
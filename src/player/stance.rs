@@ -1,11 +1,11 @@
 use super::Player;
 use super::{
     actions::step::{FootstepDirection, FootstepEvent},
-    motion::{apply_jump_force, apply_spring_force},
+    motion::{apply_jump_force, apply_spring_force, apply_swim_force},
 };
 use super::{body::Body, PlayerColliderFlag};
 use crate::utils::{exp_decay, InterpolatedValue};
-use crate::{player::config::PlayerControlConfig};
+use crate::{origin::FloatingOrigin, physics::GravityField, player::config::PlayerControlConfig, terrain::water::Water};
 use avian3d::prelude::*;
 use bevy::{
     ecs::entity::Entity,
@@ -17,6 +17,7 @@ use bevy::{
     math::Vec3,
     prelude::{Component, EventWriter, KeyCode, Query, Res, With},
     time::Time,
+    transform::components::Transform,
 };
 
 #[derive(Debug, PartialEq, Clone)]
@@ -26,6 +27,8 @@ pub enum StanceType {
     Standing,
     Landing,
     Jumping,
+    // Deep enough in water to float, the ride height spring is off so the player does not stand on the bottom.
+    Swimming,
 }
 
 #[derive(Component)]
@@ -42,9 +45,13 @@ pub fn update_player_stance(
     time: Res<Time>,
     keys: Res<ButtonInput<KeyCode>>,
     config: Res<PlayerControlConfig>,
+    gravity: Res<GravityField>,
+    origin: Res<FloatingOrigin>,
+    water: Option<Res<Water>>,
     gamepad_query: Query<(Entity, &Gamepad)>,
     mut query: Query<
         (
+            &Transform,
             &mut LinearVelocity,
             &mut ExternalForce,
             &mut ExternalImpulse,
@@ -66,6 +73,7 @@ pub fn update_player_stance(
     }
 
     for (
+        transform,
         mut linear_vel,
         mut external_force,
         mut external_impulse,
@@ -79,6 +87,9 @@ pub fn update_player_stance(
         stance.lockout -= time.delta_secs();
         stance.lockout = f32::clamp(stance.lockout, 0.0, 1.0);
 
+        // The spring and the jump push along the gravity up, which is not world up on a planet.
+        let up: Vec3 = gravity.up_at(transform.translation);
+
         // Compute the ray_length to a hit, if we don't hit anything we assume the ground is infinitly far away.
         let mut ride_height: f32 = stance.ride_height.current;
         let mut ray_length: f32 = f32::INFINITY;
@@ -97,9 +108,14 @@ pub fn update_player_stance(
         if let Ok((_entity, gamepad)) = gamepad_query.single() {
             pad = Some(gamepad);
         }
+        // How far the center of the player is below the surface of the water, the player swims once it is deep enough.
+        let water_depth: f32 = water
+            .as_ref()
+            .map_or(f32::NEG_INFINITY, |water| water.depth_at(origin.cell.absolute(transform.translation)) as f32);
+
         // Compute the next stance for the player.
         let next_stance: StanceType =
-            determine_next_stance(&keys, pad, &config, &mut stance, ray_length, ride_height);
+            determine_next_stance(&keys, pad, &config, &mut stance, ray_length, ride_height, water_depth);
 
         // handle footstep sound event when the state has changed and only then.
         if next_stance != stance.current {
@@ -129,6 +145,7 @@ pub fn update_player_stance(
                     &mut external_force,
                     ray_length,
                     ride_height,
+                    up,
                 );
             }
             StanceType::Standing => {
@@ -144,6 +161,7 @@ pub fn update_player_stance(
                     &mut external_force,
                     ray_length,
                     ride_height,
+                    up,
                 );
             }
             StanceType::Airborne => {
@@ -158,7 +176,8 @@ pub fn update_player_stance(
                 external_force.clear();
                 // check if the stance has changed.
                 if stance.current != StanceType::Jumping {
-                    linear_vel.y = 0.0; // clear the jump velocity.
+                    let vertical_vel: Vec3 = up * linear_vel.dot(up);
+                    linear_vel.0 -= vertical_vel; // clear the jump velocity.
                     apply_jump_force(
                         &config,
                         &mut stance,
@@ -166,9 +185,18 @@ pub fn update_player_stance(
                         &mut linear_vel,
                         ray_length,
                         &body,
+                        up,
                     );
                 }
             }
+            StanceType::Swimming => {
+                // Gravity and the buoyancy of the water keep the player afloat.
+                next_gravity_scale = 1.0;
+                external_force.clear();
+                if is_jump_pressed(&keys, pad) {
+                    apply_swim_force(&config, &mut external_force, up);
+                }
+            }
         }
 
         // Lerp current_ride_height to target_ride_height, this target_ride_height changes depending on the stance. Standing, Crouching, and Prone.
@@ -194,21 +222,19 @@ fn determine_next_stance(
     stance: &mut Stance,
     ray_length: f32,
     ride_height: f32,
+    water_depth: f32,
 ) -> StanceType {
     let is_locked_out: bool = stance.lockout > 0.0;
     let previous_stance: StanceType = stance.current.clone();
     let mut next_stance: StanceType = stance.current.clone();
 
-    let mut jump_pressed: bool = keys.pressed(KeyCode::Space);
+    let jump_pressed: bool = is_jump_pressed(keys, gamepad);
 
-    if let Some(g) = gamepad {
-        if jump_pressed == false {
-            jump_pressed = g.pressed(GamepadButton::North);
-        }
-    }
-
-    // If your locked in you cannot change state.
-    if !is_locked_out {
+    // The water takes over from any other stance, and the player leaves it in whichever stance fits the ground.
+    if water_depth > config.swim_depth {
+        next_stance = StanceType::Swimming;
+    } else if !is_locked_out {
+        // If your locked in you cannot change state.
         if ray_length > ride_height + config.ray_length_offset {
             next_stance = StanceType::Airborne;
         } else if previous_stance == StanceType::Standing && stance.lockout <= 0.0 && jump_pressed {
@@ -233,10 +259,14 @@ fn determine_next_stance(
     return next_stance;
 }
 
+fn is_jump_pressed(keys: &Res<ButtonInput<KeyCode>>, gamepad: Option<&Gamepad>) -> bool {
+    keys.pressed(KeyCode::Space) || gamepad.is_some_and(|g| g.pressed(GamepadButton::North))
+}
+
 pub fn lock_angular_velocity(mut query: Query<(&mut AngularVelocity, &Stance), With<Player>>) {
     for (mut angular_velocity, stance) in &mut query {
         match stance.current {
-            StanceType::Standing | StanceType::Landing => {
+            StanceType::Standing | StanceType::Landing | StanceType::Swimming => {
                 angular_velocity.0 = Vec3::ZERO;
             }
             _ => (),
@@ -0,0 +1,132 @@
+use std::{env, fs};
+
+use bevy::{
+    log::info,
+    prelude::{Res, Resource},
+};
+use serde::Deserialize;
+
+// The settings file is optional and read from the working directory.
+const SETTINGS_PATH: &str = "settings.ron";
+
+/// The seed every random part of the world is derived from, the same seed always generates the same world.
+///
+/// The seed is read from the `--seed` command line argument, then from the `seed` field of `settings.ron`,
+/// for example `(seed: Some("1234"))`, and defaults to 0. A seed which is not a number is hashed, so seeds
+/// like `--seed banana` can be shared too.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct WorldSeed(pub u64);
+
+#[derive(Deserialize, Default)]
+struct SettingsFile {
+    seed: Option<String>,
+}
+
+impl WorldSeed {
+    pub fn from_text(text: &str) -> Self {
+        match text.trim().parse::<u64>() {
+            Ok(seed) => WorldSeed(seed),
+            Err(_) => WorldSeed(fnv1a(text.trim())),
+        }
+    }
+
+    /// Reads the seed from the command line, then the settings file.
+    /// This runs before the app is built, as the seed of the `GlobalRng` is derived from it.
+    pub fn resolve() -> Self {
+        seed_argument(env::args().skip(1))
+            .or_else(|| read_settings_file().seed)
+            .map_or(WorldSeed::default(), |text| WorldSeed::from_text(&text))
+    }
+
+    /// Derives an independent seed for the labelled part of the world, such as a noise layer or a terrain feature.
+    /// Each label gets an unrelated seed, so adding a new layer never changes the seeds of the existing ones.
+    pub fn derive(&self, label: &str) -> u64 {
+        splitmix64(self.0 ^ fnv1a(label))
+    }
+
+    /// The derived seed truncated to 32 bits, which is what the noise functions take.
+    pub fn derive_u32(&self, label: &str) -> u32 {
+        (self.derive(label) >> 32) as u32
+    }
+}
+
+// Returns the value of `--seed <value>` or `--seed=<value>`.
+fn seed_argument(mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix("--seed=") {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+fn read_settings_file() -> SettingsFile {
+    let Ok(text) = fs::read_to_string(SETTINGS_PATH) else {
+        return SettingsFile::default();
+    };
+    ron::from_str(&text).unwrap_or_else(|error| {
+        // The logger is not set up yet when the settings are read.
+        eprintln!("Failed to parse {}, using the default settings: {}", SETTINGS_PATH, error);
+        SettingsFile::default()
+    })
+}
+
+pub fn log_world_seed(seed: Res<WorldSeed>) {
+    info!("World seed: {}", seed.0);
+}
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// One step of the SplitMix64 generator, this is also a good hash for mixing 64 bit values.
+pub fn splitmix64(value: u64) -> u64 {
+    let mut z: u64 = value.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A small deterministic random number generator for the headless world generation,
+/// which runs outside of the app and so cannot use the `GlobalRng`.
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let value: u64 = splitmix64(self.state);
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        value
+    }
+
+    /// A value in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A value in `[min, max)`.
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// An index in `[0, len)`, `len` must not be zero.
+    pub fn below(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
+
+// The FNV-1a hash, used instead of the standard library hasher as its output is stable across Rust versions.
+fn fnv1a(text: &str) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
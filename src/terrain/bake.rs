@@ -0,0 +1,461 @@
+use std::{
+    f64::consts::PI,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use bevy::math::{DVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::seed::WorldSeed;
+
+use super::{
+    climate::{ClimateMaps, ClimateSettings, EARTH_AXIAL_TILT_DEGREES},
+    erosion::{erode_hydraulic, erode_thermal, Heightfield, HydraulicErosionSettings, ThermalErosionSettings},
+    simulation::{
+        Crust, CrustType, PlanetoidSimulation, ABYSSAL_PLAIN_ELEVATION, MAX_CONTINENTAL_ALTITUDE,
+        OCEANIC_TRENCH_ELEVATION, SEA_LEVEL,
+    },
+};
+
+// The files written to the bake directory.
+pub const ELEVATION_FILE: &str = "elevation.f32";
+pub const ELEVATION_PREVIEW_FILE: &str = "elevation.png";
+pub const BIOME_FILE: &str = "biomes.png";
+pub const LAYER_FILE: &str = "layers.ron";
+pub const CLIMATE_FILE: &str = "climate.ron";
+pub const MANIFEST_FILE: &str = "planet.ron";
+
+const ELEVATION_MAGIC: [u8; 4] = *b"VELV";
+// Bump this when the layout changes, files of another version are rejected rather than misread.
+const ELEVATION_VERSION: u16 = 1;
+// magic + version + width + height.
+const ELEVATION_HEADER_LENGTH: usize = 4 + 2 + 4 + 4;
+
+// The thickness of the layers of the terrane stacks, in km.
+const OCEANIC_CRUST_THICKNESS: f32 = 7.0;
+const CONTINENTAL_CRUST_THICKNESS: f32 = 35.0;
+// Marine sediment settles on the ocean floor as it ages.
+const MARINE_SEDIMENT_PER_YEAR: f32 = 5.0e-9;
+const MAX_MARINE_SEDIMENT: f32 = 1.0;
+// Low continental basins fill with the sediment eroded from the highlands around them.
+const MAX_BASIN_SEDIMENT: f32 = 2.0;
+const BASIN_ELEVATION: f32 = 2.0;
+// Folded crust was buried and transformed by the pressure of the orogeny.
+const METAMORPHIC_THICKNESS: f32 = 2.0;
+
+pub struct BakeSettings {
+    // The width of the equirectangular maps, the height is half of it.
+    pub width: u32,
+    // The erosion passes run on the elevation map before anything is derived from it, None skips a pass.
+    pub hydraulic: Option<HydraulicErosionSettings>,
+    pub thermal: Option<ThermalErosionSettings>,
+    pub climate: ClimateSettings,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            width: 2048,
+            hydraulic: None,
+            thermal: None,
+            climate: ClimateSettings::default(),
+        }
+    }
+}
+
+/// The large scale biome of a pixel of the biome map, the pixel value is the index.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum BakedBiome {
+    DeepOcean = 0,
+    Ocean = 1,
+    Shelf = 2,
+    Lowland = 3,
+    Highland = 4,
+    Mountain = 5,
+    IceCap = 6,
+}
+
+impl BakedBiome {
+    fn classify(elevation: f32, latitude: f64) -> Self {
+        if elevation > SEA_LEVEL && latitude.abs() > 70.0_f64.to_radians() {
+            return BakedBiome::IceCap;
+        }
+        match elevation {
+            e if e < ABYSSAL_PLAIN_ELEVATION + 1.0 => BakedBiome::DeepOcean,
+            e if e < -0.5 => BakedBiome::Ocean,
+            e if e < SEA_LEVEL => BakedBiome::Shelf,
+            e if e < 0.5 => BakedBiome::Lowland,
+            e if e < 2.0 => BakedBiome::Highland,
+            _ => BakedBiome::Mountain,
+        }
+    }
+}
+
+/// The kind of rock of a layer of a terrane stack.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u16)]
+pub enum Sediment {
+    Basalt = 0,
+    Granite = 1,
+    MarineSediment = 2,
+    Sandstone = 3,
+    Metamorphic = 4,
+}
+
+impl Sediment {
+    pub fn from_index(index: u16) -> Option<Self> {
+        match index {
+            0 => Some(Sediment::Basalt),
+            1 => Some(Sediment::Granite),
+            2 => Some(Sediment::MarineSediment),
+            3 => Some(Sediment::Sandstone),
+            4 => Some(Sediment::Metamorphic),
+            _ => None,
+        }
+    }
+}
+
+/// The rock beneath a vertex of the simulation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Terranes {
+    // The elevation of the surface in km.
+    pub height: f32,
+    // The sediment type and thickness in km of each layer, ordered from the surface down.
+    pub stack: Vec<(u16, f32)>,
+}
+
+/// The layers of every vertex of the simulation, the vertices are those of an icosphere with the same subdivisions.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayerFile {
+    pub subdivisions: u32,
+    pub terranes: Vec<Terranes>,
+}
+
+/// The climate of every vertex of the simulation, see [`ClimateMaps`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClimateFile {
+    pub subdivisions: u32,
+    pub axial_tilt: f32,
+    // How much colder it gets with the altitude, in °C per km.
+    pub lapse_rate: f32,
+    // The elevation of each vertex the climate was computed at, in km.
+    pub elevation: Vec<f32>,
+    // In °C.
+    pub temperature: Vec<f32>,
+    // From 0 to 1.
+    pub moisture: Vec<f32>,
+    // In mm per year.
+    pub precipitation: Vec<f32>,
+    // Along the surface in m/s.
+    pub wind: Vec<[f32; 3]>,
+    pub ocean_currents: Vec<[f32; 3]>,
+}
+
+/// Describes a bake, with the seeds needed to generate the same planet again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BakeManifest {
+    pub world_seed: u64,
+    pub simulation_seed: u64,
+    pub simulated_years: f64,
+    // In km.
+    pub radius: f32,
+    pub width: u32,
+    pub height: u32,
+    // The range of the elevation map in km, the preview maps this range to the full range of its pixels.
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+    pub sea_level: f32,
+    // In degrees, the sky of the planet uses it for its seasons.
+    #[serde(default = "default_axial_tilt")]
+    pub axial_tilt: f32,
+}
+
+fn default_axial_tilt() -> f32 {
+    EARTH_AXIAL_TILT_DEGREES
+}
+
+/// An equirectangular map of the elevation in km, the columns go around the Y axis and the rows from north to south.
+///
+/// The file starts with the magic bytes, the format version, the width and the height,
+/// followed by the elevation of each pixel row by row, everything little endian.
+pub struct ElevationMap {
+    pub width: u32,
+    pub height: u32,
+    pub elevations: Vec<f32>,
+}
+
+impl fmt::Debug for ElevationMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ElevationMap({}x{})", self.width, self.height)
+    }
+}
+
+impl ElevationMap {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < ELEVATION_HEADER_LENGTH || bytes[0..4] != ELEVATION_MAGIC {
+            return Err("not an elevation map".to_owned());
+        }
+        let version: u16 = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != ELEVATION_VERSION {
+            return Err(format!("unsupported elevation map version {}", version));
+        }
+        let width: u32 = u32::from_le_bytes(bytes[6..10].try_into().unwrap());
+        let height: u32 = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+        let expected: usize = ELEVATION_HEADER_LENGTH + width as usize * height as usize * 4;
+        if width == 0 || height == 0 || bytes.len() != expected {
+            return Err(format!("the elevation map is {} bytes, expected {}", bytes.len(), expected));
+        }
+        let elevations: Vec<f32> = bytes[ELEVATION_HEADER_LENGTH..]
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            elevations,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(ELEVATION_HEADER_LENGTH + self.elevations.len() * 4);
+        bytes.extend_from_slice(&ELEVATION_MAGIC);
+        bytes.extend_from_slice(&ELEVATION_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        for elevation in self.elevations.iter() {
+            bytes.extend_from_slice(&elevation.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The elevation in km in the direction from the center of the planet, bilinearly interpolated.
+    pub fn sample(&self, direction: DVec3) -> f64 {
+        let (longitude, latitude) = to_longitude_latitude(direction);
+        let x: f64 = (longitude + PI) / (2.0 * PI) * self.width as f64 - 0.5;
+        let y: f64 = (PI / 2.0 - latitude) / PI * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        // The map wraps around the planet horizontally and stops at the poles vertically.
+        let pixel = |x: f64, y: f64| -> f64 {
+            let column: u32 = (x as i64).rem_euclid(self.width as i64) as u32;
+            let row: u32 = (y as i64).clamp(0, self.height as i64 - 1) as u32;
+            self.elevations[(row * self.width + column) as usize] as f64
+        };
+        let top: f64 = pixel(x0, y0) * (1.0 - tx) + pixel(x0 + 1.0, y0) * tx;
+        let bottom: f64 = pixel(x0, y0 + 1.0) * (1.0 - tx) + pixel(x0 + 1.0, y0 + 1.0) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+fn to_longitude_latitude(direction: DVec3) -> (f64, f64) {
+    let direction: DVec3 = direction.normalize_or(DVec3::Y);
+    (direction.z.atan2(direction.x), direction.y.clamp(-1.0, 1.0).asin())
+}
+
+fn from_longitude_latitude(longitude: f64, latitude: f64) -> DVec3 {
+    DVec3::new(
+        latitude.cos() * longitude.cos(),
+        latitude.sin(),
+        latitude.cos() * longitude.sin(),
+    )
+}
+
+/// Writes the state of the simulation to the directory, creating it if needed:
+/// - the elevation map as raw f32, which the density graph samples with the `Elevation` node,
+/// - a 16 bit grayscale preview of the elevation,
+/// - an 8 bit map of the [`BakedBiome`] index of each pixel,
+/// - the terrane stacks of each vertex of the simulation,
+/// - the [`ClimateMaps`] of each vertex of the simulation,
+/// - a manifest with the seeds and the ranges of the maps.
+pub fn bake_planetoid_simulation_state(
+    simulation: &PlanetoidSimulation,
+    world_seed: &WorldSeed,
+    settings: &BakeSettings,
+    directory: &Path,
+) -> io::Result<BakeManifest> {
+    fs::create_dir_all(directory)?;
+    let width: u32 = settings.width.max(2);
+    let height: u32 = width / 2;
+
+    let mut elevation_map: ElevationMap = bake_elevation(simulation, width, height);
+    if settings.hydraulic.is_some() || settings.thermal.is_some() {
+        // The cells shrink towards the poles, the erosion treats them all as wide as at the equator.
+        let mut field = Heightfield::from_elevation_map(elevation_map, simulation.radius);
+        if let Some(hydraulic) = settings.hydraulic.as_ref() {
+            erode_hydraulic(&mut field, hydraulic, world_seed.derive("erosion"));
+        }
+        if let Some(thermal) = settings.thermal.as_ref() {
+            erode_thermal(&mut field, thermal);
+        }
+        elevation_map = field.into_elevation_map();
+    }
+    fs::write(directory.join(ELEVATION_FILE), elevation_map.to_bytes())?;
+
+    // The preview covers the whole range the simulation allows, so previews of different bakes can be compared.
+    let preview: Vec<u8> = elevation_map
+        .elevations
+        .iter()
+        .flat_map(|elevation| {
+            let normalized: f32 = (elevation - OCEANIC_TRENCH_ELEVATION) / (MAX_CONTINENTAL_ALTITUDE - OCEANIC_TRENCH_ELEVATION);
+            ((normalized.clamp(0.0, 1.0) * u16::MAX as f32) as u16).to_be_bytes()
+        })
+        .collect();
+    write_png(&directory.join(ELEVATION_PREVIEW_FILE), width, height, png::BitDepth::Sixteen, &preview)?;
+
+    let biomes: Vec<u8> = elevation_map
+        .elevations
+        .iter()
+        .enumerate()
+        .map(|(index, elevation)| {
+            let row: u32 = index as u32 / width;
+            let latitude: f64 = PI / 2.0 - (row as f64 + 0.5) / height as f64 * PI;
+            BakedBiome::classify(*elevation, latitude) as u8
+        })
+        .collect();
+    write_png(&directory.join(BIOME_FILE), width, height, png::BitDepth::Eight, &biomes)?;
+
+    let layers = LayerFile {
+        subdivisions: simulation.sphere.subdivisions,
+        terranes: simulation.crust.iter().map(terranes_for).collect(),
+    };
+    fs::write(directory.join(LAYER_FILE), ron::ser::to_string(&layers).map_err(io::Error::other)?)?;
+
+    let climate: ClimateMaps = ClimateMaps::simulate(simulation, &settings.climate);
+    let climate = ClimateFile {
+        subdivisions: simulation.sphere.subdivisions,
+        axial_tilt: settings.climate.axial_tilt_degrees,
+        lapse_rate: settings.climate.lapse_rate,
+        elevation: (0..simulation.sphere.len()).map(|vertex| simulation.elevation(vertex)).collect(),
+        temperature: climate.temperature,
+        moisture: climate.moisture,
+        precipitation: climate.precipitation,
+        wind: climate.wind.iter().map(|wind| wind.to_array()).collect(),
+        ocean_currents: climate.ocean_currents.iter().map(|current| current.to_array()).collect(),
+    };
+    fs::write(directory.join(CLIMATE_FILE), ron::ser::to_string(&climate).map_err(io::Error::other)?)?;
+
+    let manifest = BakeManifest {
+        world_seed: world_seed.0,
+        simulation_seed: simulation.seed,
+        simulated_years: simulation.age,
+        radius: simulation.radius,
+        width,
+        height,
+        min_elevation: elevation_map.elevations.iter().copied().fold(f32::INFINITY, f32::min),
+        max_elevation: elevation_map.elevations.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        sea_level: SEA_LEVEL,
+        axial_tilt: settings.climate.axial_tilt_degrees,
+    };
+    let text: String = ron::ser::to_string_pretty(&manifest, ron::ser::PrettyConfig::default()).map_err(io::Error::other)?;
+    fs::write(directory.join(MANIFEST_FILE), text)?;
+    Ok(manifest)
+}
+
+// Interpolates the elevation of the vertices around each pixel, weighting each vertex by how close it is
+// relative to the spacing of the vertices so the map has no visible triangles or cells.
+fn bake_elevation(simulation: &PlanetoidSimulation, width: u32, height: u32) -> ElevationMap {
+    let sphere = &simulation.sphere;
+    let spacing: f32 = sphere.spacing();
+    let mut elevations: Vec<f32> = Vec::with_capacity((width * height) as usize);
+    // Neighbouring pixels are close on the sphere, so the search for the nearest vertex starts at the previous one.
+    let mut nearest: u32 = 0;
+    for row in 0..height {
+        let latitude: f64 = PI / 2.0 - (row as f64 + 0.5) / height as f64 * PI;
+        for column in 0..width {
+            let longitude: f64 = (column as f64 + 0.5) / width as f64 * 2.0 * PI - PI;
+            let direction: Vec3 = from_longitude_latitude(longitude, latitude).as_vec3();
+            nearest = sphere.nearest(direction, nearest);
+
+            let mut total: f32 = 0.0;
+            let mut weights: f32 = 0.0;
+            for vertex in std::iter::once(nearest).chain(sphere.neighbours[nearest as usize].iter().copied()) {
+                let angle: f32 = sphere.vertices[vertex as usize].angle_between(direction);
+                let weight: f32 = (1.0 - angle / spacing).max(0.0);
+                total += simulation.crust[vertex as usize].elevation * weight;
+                weights += weight;
+            }
+            elevations.push(if weights > 0.0 { total / weights } else { simulation.crust[nearest as usize].elevation });
+        }
+    }
+    ElevationMap {
+        width,
+        height,
+        elevations,
+    }
+}
+
+fn terranes_for(crust: &Crust) -> Terranes {
+    let mut stack: Vec<(u16, f32)> = Vec::new();
+    match crust.crust_type {
+        CrustType::Oceanic => {
+            let sediment: f32 = (crust.age * MARINE_SEDIMENT_PER_YEAR).min(MAX_MARINE_SEDIMENT);
+            if sediment > 0.0 {
+                stack.push((Sediment::MarineSediment as u16, sediment));
+            }
+            stack.push((Sediment::Basalt as u16, OCEANIC_CRUST_THICKNESS));
+        }
+        CrustType::Continental => {
+            let basin: f32 = (1.0 - crust.elevation / BASIN_ELEVATION).clamp(0.0, 1.0) * MAX_BASIN_SEDIMENT;
+            if basin > 0.0 {
+                stack.push((Sediment::Sandstone as u16, basin));
+            }
+            if crust.fold_direction != Vec3::ZERO {
+                stack.push((Sediment::Metamorphic as u16, METAMORPHIC_THICKNESS));
+            }
+            stack.push((Sediment::Granite as u16, CONTINENTAL_CRUST_THICKNESS));
+        }
+    }
+    Terranes {
+        height: crust.elevation,
+        stack,
+    }
+}
+
+fn write_png(path: &Path, width: u32, height: u32, depth: png::BitDepth, data: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elevation_map() -> ElevationMap {
+        ElevationMap {
+            width: 3,
+            height: 2,
+            elevations: vec![-4.5, -0.25, 0.0, 0.5, 2.75, 8.0],
+        }
+    }
+
+    #[test]
+    fn elevation_map_round_trip() {
+        let map: ElevationMap = elevation_map();
+        let read: ElevationMap = ElevationMap::from_bytes(&map.to_bytes()).unwrap();
+        assert_eq!((read.width, read.height), (map.width, map.height));
+        assert_eq!(read.elevations, map.elevations);
+    }
+
+    #[test]
+    fn truncated_elevation_map_is_rejected() {
+        let bytes: Vec<u8> = elevation_map().to_bytes();
+        assert!(ElevationMap::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ElevationMap::from_bytes(&bytes[..ELEVATION_HEADER_LENGTH - 1]).is_err());
+    }
+
+    #[test]
+    fn elevation_map_of_another_version_is_rejected() {
+        let mut bytes: Vec<u8> = elevation_map().to_bytes();
+        bytes[4..6].copy_from_slice(&(ELEVATION_VERSION + 1).to_le_bytes());
+        assert!(ElevationMap::from_bytes(&bytes).is_err());
+    }
+}
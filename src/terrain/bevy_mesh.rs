@@ -2,32 +2,169 @@
 This module contains mesh builders to produce [Bevy](https://bevyengine.org/) meshes.
 */
 
+/// The material ids of a vertex, four ids packed into the bytes of a u32 from the lowest byte.
+/// The vertices of a triangle share their ids, so the weights blend between the materials across the triangle.
+pub const ATTRIBUTE_MATERIAL_IDS: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_MaterialIds", 1_946_572_301, VertexFormat::Uint32);
+/// How much of each of the four materials of [`ATTRIBUTE_MATERIAL_IDS`] the vertex shows, they add up to 1.
+pub const ATTRIBUTE_MATERIAL_WEIGHTS: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_MaterialWeights", 1_946_572_302, VertexFormat::Float32x4);
+/// The position textures are projected from along each axis, the world position less the texture origin of the chunk.
+/// It stays small far from the world origin and does not change when the floating origin moves.
+pub const ATTRIBUTE_TRIPLANAR_POSITION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_TriplanarPosition", 1_946_572_303, VertexFormat::Float32x3);
+
+/// The texture origin of a chunk is a multiple of this, textures tile across chunks when their size divides it.
+pub const TRIPLANAR_PERIOD: f32 = 256.0;
+// Soil whose normal is within 40° of up is grass, steeper soil stays bare.
+const GRASS_MIN_UP_DOT: f32 = 0.766;
+// Soil steeper than 55° slides off and shows the rock under it.
+const CLIFF_MAX_UP_DOT: f32 = 0.574;
+// Snow settles on surfaces within 60° of up.
+const SNOW_MIN_UP_DOT: f32 = 0.5;
+// The height of the snow line above sea level.
+const SNOW_LINE_KM: f32 = 3.0;
+// Only the grid points this many cells below the surface get a material. A vertex is between a point inside and a
+// point outside so the deeper points never need one, and the depth is expensive to sample.
+const MATERIAL_DEPTH_CELLS: f32 = 8.0;
+
+/// A grid point of the terrain, the density with the material of the rock there.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MaterialVoxel {
+    pub density: f32,
+    pub material: VoxelMaterial,
+    // The rock under the soil when the material is soil, shown where the surface is too steep for soil.
+    pub bedrock: VoxelMaterial,
+}
+
+impl VoxelData for MaterialVoxel {
+    type Density = f32;
+
+    fn density(&self) -> f32 {
+        self.density
+    }
+}
+
+/// The density field with the edits layered on top when there are any, and the material of the points near the
+/// surface. This is what the chunks are meshed from.
+///
+/// The block is sampled relative to the base, so its f32 positions stay small far from the world origin.
+pub struct MaterialField<'a> {
+    pub field: &'a DensityField,
+    pub edits: Option<&'a Octree<Voxel>>,
+    // The absolute position of the origin of the block.
+    pub base: DVec3,
+    // The distance between the regular grid points of the block.
+    pub cell_size: f32,
+}
+
+impl DataField<MaterialVoxel, f32> for MaterialField<'_> {
+    fn get_data(&mut self, x: f32, y: f32, z: f32) -> MaterialVoxel {
+        let position: DVec3 = self.base + DVec3::new(x as f64, y as f64, z as f64);
+        let procedural: f64 = self.field.sample(position);
+        let delta: f32 = self.edits.map_or(0.0, |voxels| sample_density_delta(voxels, position));
+        let density: f32 = procedural as f32 + delta;
+        let material = if density >= THRESHOLD && density <= MATERIAL_DEPTH_CELLS * self.cell_size {
+            self.field.material_for(position, density as f64)
+        } else {
+            VoxelMaterial::Air
+        };
+        let bedrock = match material {
+            VoxelMaterial::Soil => self.field.strata.bedrock_at(position),
+            material => material,
+        };
+        MaterialVoxel { density, material, bedrock }
+    }
+}
+
+/// How the builder decides what the surface shows from its height and slope.
+#[derive(Clone, Copy, Debug)]
+pub struct SurfaceRules {
+    // The radius of the sea level sphere of a planet centered on the world origin. On flat terrain sea level is at
+    // y = 0 and up is +Y.
+    pub planet_radius: Option<f32>,
+    // The height above sea level in world units the surface is covered in snow from.
+    pub snow_line: f32,
+}
+
+impl Default for SurfaceRules {
+    fn default() -> Self {
+        Self {
+            planet_radius: None,
+            snow_line: f32::INFINITY,
+        }
+    }
+}
+
+impl SurfaceRules {
+    pub fn for_field(field: &DensityField) -> Self {
+        let units_per_km: f32 = field.planet().map_or(FLAT_TERRAIN_UNITS_PER_KM, |planet| planet.elevation_scale);
+        Self {
+            planet_radius: field.planet().map(|planet| planet.radius()),
+            snow_line: SNOW_LINE_KM * units_per_km,
+        }
+    }
+
+    /// The material the surface shows at the absolute position, from the material of the voxel inside it.
+    /// Soil facing the sky is grass, steep soil shows the bedrock, and anything flat enough above the snow line is snow.
+    pub fn surface_material(&self, voxel: &MaterialVoxel, position: DVec3, normal: Vec3) -> VoxelMaterial {
+        let (up, height) = match self.planet_radius {
+            Some(radius) => (position.normalize_or(DVec3::Y).as_vec3(), (position.length() - radius as f64) as f32),
+            None => (Vec3::Y, position.y as f32),
+        };
+        let up_dot: f32 = normal.dot(up);
+        if height >= self.snow_line && up_dot >= SNOW_MIN_UP_DOT {
+            return VoxelMaterial::Snow;
+        }
+        match voxel.material {
+            VoxelMaterial::Soil if up_dot < CLIFF_MAX_UP_DOT => voxel.bedrock,
+            VoxelMaterial::Soil if up_dot >= GRASS_MIN_UP_DOT => VoxelMaterial::Grass,
+            material => material,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct BevyMeshBuilder {
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
+    pub materials: Vec<VoxelMaterial>,
     pub triangle_indices: Vec<u32>,
     vertices: usize,
+    // The absolute position the vertex positions are relative to.
+    pub base: DVec3,
+    // What the triplanar positions are measured from relative to the base, at a multiple of [`TRIPLANAR_PERIOD`].
+    pub texture_origin: Vec3,
+    pub surface: SurfaceRules,
 }
 
-/// A simple bevy mesh builder that:
-///  - only populates position/normal attributes
-///  - only looks at density of the VoxelData
+/// A bevy mesh builder that:
+///  - populates position/normal attributes
+///  - carries the material of the VoxelData to the vertices, with the ids and weights to blend them
+///  - adds the triplanar position textures are projected from, there are no UV coordinates
 impl BevyMeshBuilder {
     /**
-    Build a Bevy mesh, producing a triangle list mesh with positions and normals
-    from our mesh, but UV coordinates all set to 0
+    Build a Bevy mesh, producing a triangle list mesh with positions, normals, material ids and weights
+    and triplanar positions from our mesh
     */
-    pub fn build(self) -> Mesh {
+    pub fn build(mut self) -> Mesh {
+        let (material_ids, material_weights) = self.blend_materials();
+        let triplanar_positions: Vec<[f32; 3]> = self
+            .positions
+            .iter()
+            .map(|position| (Vec3::from(*position) - self.texture_origin).to_array())
+            .collect();
         let mut bevy_mesh: BevyMesh = Mesh::new(TriangleList, RenderAssetUsages::RENDER_WORLD);
         bevy_mesh.insert_indices(Indices::U32(self.triangle_indices));
         bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
         bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        bevy_mesh.insert_attribute(ATTRIBUTE_MATERIAL_IDS, material_ids);
+        bevy_mesh.insert_attribute(ATTRIBUTE_MATERIAL_WEIGHTS, material_weights);
+        bevy_mesh.insert_attribute(ATTRIBUTE_TRIPLANAR_POSITION, triplanar_positions);
         return bevy_mesh;
     }
     /**
-    Convert to a Bevy mesh lines list, with positions and normals
-    from our mesh, but UV coordinates all set to 0.
+    Convert to a Bevy mesh lines list, with positions and normals from our mesh.
     Lines shared between 2 triangles are repeated, for implementation simplicity.
     */
     pub fn build_wireframe(self) -> Mesh {
@@ -43,16 +180,70 @@ impl BevyMeshBuilder {
         bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
         return bevy_mesh;
     }
+
+    // Gives the vertices of each triangle the same material ids, with the whole weight on the material of the vertex,
+    // so the weights blend between the materials across the triangle. A vertex shared by triangles with different ids
+    // is copied for each of them.
+    fn blend_materials(&mut self) -> (Vec<u32>, Vec<[f32; 4]>) {
+        let mut ids: Vec<Option<u32>> = vec![None; self.vertices];
+        let mut weights: Vec<[f32; 4]> = vec![[0.0; 4]; self.vertices];
+        let mut copies: HashMap<(u32, u32), u32> = HashMap::new();
+        for corner in 0..self.triangle_indices.len() {
+            let triangle: usize = corner - corner % 3;
+            let packed: u32 = pack_materials([0, 1, 2].map(|i| {
+                self.materials[self.triangle_indices[triangle + i] as usize]
+            }));
+            let vertex: u32 = self.triangle_indices[corner];
+            let index: u32 = match ids[vertex as usize] {
+                None => {
+                    ids[vertex as usize] = Some(packed);
+                    weights[vertex as usize] = material_weights(self.materials[vertex as usize], packed);
+                    vertex
+                }
+                Some(existing) if existing == packed => vertex,
+                Some(_) => match copies.get(&(vertex, packed)) {
+                    Some(copy) => *copy,
+                    None => {
+                        let copy: u32 = self.positions.len() as u32;
+                        let material: VoxelMaterial = self.materials[vertex as usize];
+                        self.positions.push(self.positions[vertex as usize]);
+                        self.normals.push(self.normals[vertex as usize]);
+                        self.materials.push(material);
+                        ids.push(Some(packed));
+                        weights.push(material_weights(material, packed));
+                        copies.insert((vertex, packed), copy);
+                        copy
+                    }
+                },
+            };
+            self.triangle_indices[corner] = index;
+        }
+        self.vertices = self.positions.len();
+        (ids.into_iter().map(|packed| packed.unwrap_or(0)).collect(), weights)
+    }
+}
+
+// The distinct materials of a triangle in ascending order, the unused slots repeat the first id with no weight.
+fn pack_materials(materials: [VoxelMaterial; 3]) -> u32 {
+    let mut ids: Vec<u8> = materials.iter().map(|material| *material as u8).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.resize(4, ids[0]);
+    u32::from_le_bytes([ids[0], ids[1], ids[2], ids[3]])
+}
+
+fn material_weights(material: VoxelMaterial, packed: u32) -> [f32; 4] {
+    let slot: usize = packed.to_le_bytes().iter().position(|id| *id == material as u8).unwrap_or(0);
+    let mut weights: [f32; 4] = [0.0; 4];
+    weights[slot] = 1.0;
+    weights
 }
 
-impl<V> MeshBuilder<V, f32> for BevyMeshBuilder
-where
-    V: VoxelData<Density = f32>,
-{
+impl MeshBuilder<MaterialVoxel, f32> for BevyMeshBuilder {
     fn add_vertex_between(
         &mut self,
-        point_a: GridPoint<V, f32>,
-        point_b: GridPoint<V, f32>,
+        point_a: GridPoint<MaterialVoxel, f32>,
+        point_b: GridPoint<MaterialVoxel, f32>,
         interp_toward_b: f32,
     ) -> VertexIndex {
         let position = point_a
@@ -65,8 +256,26 @@ where
         let gradient_z =
             point_a.gradient.2 + interp_toward_b * (point_b.gradient.2 - point_a.gradient.2);
         let normal = f32::gradients_to_normal(gradient_x, gradient_y, gradient_z);
+        // The vertex shows the material of the point on the inside of the surface.
+        let (inside, outside) = if point_a.voxel_data.density >= point_b.voxel_data.density {
+            (point_a.voxel_data, point_b.voxel_data)
+        } else {
+            (point_b.voxel_data, point_a.voxel_data)
+        };
+        let voxel: MaterialVoxel = match (inside.material, outside.material) {
+            (VoxelMaterial::Air, VoxelMaterial::Air) => MaterialVoxel {
+                material: VoxelMaterial::Soil,
+                bedrock: VoxelMaterial::Soil,
+                ..inside
+            },
+            (VoxelMaterial::Air, _) => outside,
+            _ => inside,
+        };
+        let absolute: DVec3 = self.base + DVec3::new(position.x as f64, position.y as f64, position.z as f64);
+        let material: VoxelMaterial = self.surface.surface_material(&voxel, absolute, Vec3::from(normal));
         self.positions.push([position.x, position.y, position.z]);
         self.normals.push(normal);
+        self.materials.push(material);
         let index = self.vertices;
         self.vertices += 1;
         return VertexIndex(index);
@@ -92,6 +301,16 @@ use bevy::render::mesh::Mesh;
 use bevy::render::mesh::VertexAttributeValues;
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::render_resource::PrimitiveTopology::{LineList, TriangleList};
+use std::collections::HashMap;
+use bevy::math::DVec3;
+use bevy::render::mesh::MeshVertexAttribute;
+use bevy::render::render_resource::VertexFormat;
+use super::biome::FLAT_TERRAIN_UNITS_PER_KM;
+use super::density_graph::DensityField;
+use super::editing::sample_density_delta;
+use super::octree::Octree;
+use super::strata::VoxelMaterial;
+use super::Voxel;
 use transvoxel::mesh_builder::GridPoint;
 use transvoxel::mesh_builder::MeshBuilder;
 use transvoxel::mesh_builder::VertexIndex;
@@ -106,44 +325,34 @@ use transvoxel::{
     voxel_source::WorldMappingVoxelSource,
 };
 
-pub fn mesh_for_model(
-    model: &Model,
-    wireframe: bool,
-    block: &Block<f32>,
-    transition_sides: &TransitionSides,
-) -> BevyMesh {
-    let mut models_map = models_map();
-    let field = models_map.get_mut(model).unwrap().as_mut();
-    field_model(field, wireframe, block, transition_sides)
-}
-
-pub fn inside_grid_points(
-    model: &Model,
-    block: &Block<f32>,
-    transition_sides: &TransitionSides,
-) -> Vec<(f32, f32, f32)> {
-    let mut models_map = models_map();
-    let field = models_map.get_mut(model).unwrap().as_mut();
-    inside_grid_points_for_field(field, block, transition_sides)
-}
-
-fn field_model(
-    field: &mut dyn DataField<f32, f32>,
+/// Meshes the block from the density field, with the edits layered on top of it when there are any.
+/// The block and the vertices are relative to the absolute base. The triplanar positions are measured from the
+/// nearest multiple of the period below the base, so they do not change when the floating origin moves.
+pub fn mesh_for_field(
+    field: &DensityField,
     wireframe: bool,
     block: &Block<f32>,
+    base: DVec3,
     transition_sides: &TransitionSides,
+    edits: Option<&Octree<Voxel>>,
 ) -> BevyMesh {
+    let mut field = MaterialField {
+        field,
+        edits,
+        base,
+        cell_size: block.dims.size / block.subdivisions as f32,
+    };
+    let builder = BevyMeshBuilder {
+        base,
+        texture_origin: -base.rem_euclid(DVec3::splat(TRIPLANAR_PERIOD as f64)).as_vec3(),
+        surface: SurfaceRules::for_field(field.field),
+        ..Default::default()
+    };
     let source = WorldMappingVoxelSource {
-        field: field,
+        field: &mut field,
         block: &block,
     };
-    let builder = extract(
-        source,
-        &block,
-        THRESHOLD,
-        *transition_sides,
-        BevyMeshBuilder::default(),
-    );
+    let builder = extract(source, &block, THRESHOLD, *transition_sides, builder);
     if wireframe {
         builder.build_wireframe()
     } else {
@@ -151,6 +360,14 @@ fn field_model(
     }
 }
 
+pub fn inside_grid_points(
+    field: &mut dyn DataField<f32, f32>,
+    block: &Block<f32>,
+    transition_sides: &TransitionSides,
+) -> Vec<(f32, f32, f32)> {
+    inside_grid_points_for_field(field, block, transition_sides)
+}
+
 fn inside_grid_points_for_field(
     field: &mut dyn DataField<f32, f32>,
     block: &Block<f32>,
@@ -373,108 +590,6 @@ fn append_f2(dest: &mut Vec<[f32; 2]>, src: &VertexAttributeValues) -> () {
 
 */
 
-use std::collections::HashMap;
-
-use noise::{Fbm, NoiseFn, Perlin};
-use std::slice::Iter;
 use transvoxel::voxel_source::DataField;
 
-#[derive(PartialEq, Debug, Copy, Clone, Hash, Eq)]
-pub enum Model {
-    Sphere,
-    Quadrant,
-    Plane,
-    Wave,
-    Noise,
-}
-
-pub fn models_map() -> HashMap<Model, Box<dyn DataField<f32, f32>>> {
-    let mut fields: HashMap<Model, Box<dyn DataField<f32, f32>>> = HashMap::new();
-    fields.insert(
-        Model::Sphere,
-        Box::new(Sphere {
-            cx: 5f32,
-            cy: 5f32,
-            cz: 5f32,
-            r: 2f32,
-        }),
-    );
-    fields.insert(
-        Model::Quadrant,
-        Box::new(Sphere {
-            cx: 0f32,
-            cy: 0f32,
-            cz: 0f32,
-            r: 6f32,
-        }),
-    );
-    fields.insert(Model::Plane, Box::new(ObliquePlane {}));
-    fields.insert(Model::Wave, Box::new(Wave {}));
-    fields.insert(Model::Noise, Box::new(Noise::new()));
-    return fields;
-}
-
 pub const THRESHOLD: f32 = 0.;
-
-impl Model {
-    pub fn iterator() -> Iter<'static, Model> {
-        static MODELS: [Model; 5] = [
-            Model::Sphere,
-            Model::Quadrant,
-            Model::Plane,
-            Model::Wave,
-            Model::Noise,
-        ];
-        MODELS.iter()
-    }
-}
-
-struct Sphere {
-    pub cx: f32,
-    pub cy: f32,
-    pub cz: f32,
-    pub r: f32,
-}
-
-impl DataField<f32, f32> for Sphere {
-    fn get_data(&mut self, x: f32, y: f32, z: f32) -> f32 {
-        let distance_from_center = ((x - self.cx) * (x - self.cx)
-            + (y - self.cy) * (y - self.cy)
-            + (z - self.cz) * (z - self.cz))
-            .sqrt();
-        let d = 1f32 - distance_from_center / self.r;
-        d
-    }
-}
-
-struct ObliquePlane {}
-impl DataField<f32, f32> for ObliquePlane {
-    #[allow(unused_variables)]
-    fn get_data(&mut self, x: f32, y: f32, z: f32) -> f32 {
-        2f32 + z - 2f32 * y
-    }
-}
-
-struct Wave {}
-impl DataField<f32, f32> for Wave {
-    fn get_data(&mut self, x: f32, y: f32, z: f32) -> f32 {
-        2.0 * ((x * 1.0).sin() + 0.5 * (z * 0.5).cos()) + 5.0 - y
-    }
-}
-
-struct Noise {
-    f: Box<dyn NoiseFn<f64, 3>>,
-}
-impl Noise {
-    pub fn new() -> Self {
-        Self {
-            f: Box::new(Fbm::<Perlin>::new(0)),
-        }
-    }
-}
-impl DataField<f32, f32> for Noise {
-    fn get_data(&mut self, x: f32, y: f32, z: f32) -> f32 {
-        let distrub = self.f.get([x as f64, y as f64, z as f64]) as f32;
-        2f32 - 2f32 * (y - 3.0 - 3.0 * distrub)
-    }
-}
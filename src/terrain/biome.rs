@@ -0,0 +1,349 @@
+use std::{fmt, sync::Arc};
+
+use bevy::{
+    asset::{io::Reader, Asset, AssetEvent, AssetLoader, AssetServer, Assets, Handle, LoadContext},
+    log::{info, warn},
+    math::{DVec3, Vec3},
+    prelude::{Commands, EventReader, Res, ResMut, Resource},
+    reflect::TypePath,
+};
+use serde::Deserialize;
+
+use super::{
+    bake::ClimateFile,
+    climate::{sea_level_temperature, ClimateSettings},
+    config::TerrainConfig,
+    icosphere::Icosphere,
+    mesh_queue::ChunkMeshQueue,
+    planet::Planetoid,
+    TerrainData,
+};
+
+// Without a baked climate a position gets the climate of its latitude, at this latitude on flat terrain.
+const FLAT_TERRAIN_LATITUDE_DEGREES: f32 = 45.0;
+// The moisture of a position without a baked climate.
+const UNBAKED_MOISTURE: f32 = 0.5;
+// A world unit is a metre on flat terrain.
+pub(crate) const FLAT_TERRAIN_UNITS_PER_KM: f32 = 1000.0;
+
+/// The climate at a point of the surface, what a biome is chosen by.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Climate {
+    // The annual mean temperature, in °C.
+    pub temperature: f32,
+    // The moisture of the air relative to the most it holds, from 0 to 1.
+    pub moisture: f32,
+    // Above sea level, in km.
+    pub elevation: f32,
+}
+
+/// The values a biome covers, a bound which is left out is unbounded.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct ClimateRange {
+    #[serde(default = "unbounded_min")]
+    pub min: f32,
+    #[serde(default = "unbounded_max")]
+    pub max: f32,
+}
+
+impl Default for ClimateRange {
+    fn default() -> Self {
+        Self {
+            min: f32::NEG_INFINITY,
+            max: f32::INFINITY,
+        }
+    }
+}
+
+impl ClimateRange {
+    // The minimum is inclusive and the maximum exclusive, so ranges meeting at a value do not overlap.
+    pub fn contains(&self, value: f32) -> bool {
+        value >= self.min && value < self.max
+    }
+}
+
+fn unbounded_min() -> f32 {
+    f32::NEG_INFINITY
+}
+
+fn unbounded_max() -> f32 {
+    f32::INFINITY
+}
+
+fn default_color() -> (f32, f32, f32) {
+    (1.0, 0.0, 1.0)
+}
+
+/// A row of a biome table, with what the systems which decorate the biome need to know about it.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BiomeDefinition {
+    pub id: u8,
+    pub name: String,
+    #[serde(default)]
+    pub temperature: ClimateRange,
+    #[serde(default)]
+    pub moisture: ClimateRange,
+    #[serde(default)]
+    pub elevation: ClimateRange,
+    // The color of the biome on maps and debug views, in linear RGB.
+    #[serde(default = "default_color")]
+    pub color: (f32, f32, f32),
+    // How densely plants grow, from 0 for bare ground to 1.
+    #[serde(default)]
+    pub vegetation_density: f32,
+    // The ambient sound played in the biome, relative to the assets directory.
+    #[serde(default)]
+    pub ambience: Option<String>,
+}
+
+/// Maps climates to biomes, loaded from a `.biomes.ron` file.
+///
+/// The rows are checked in order and the first one whose ranges all contain the climate is the biome,
+/// so narrow biomes go before the broad ones they are carved out of. A climate no row covers is the fallback biome.
+///
+/// ```ron
+/// (
+///     fallback: 1,
+///     biomes: [
+///         (id: 0, name: "Ocean", elevation: (max: 0.0)),
+///         (id: 1, name: "Grassland", vegetation_density: 0.3),
+///     ],
+/// )
+/// ```
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct BiomeTable {
+    pub fallback: u8,
+    pub biomes: Vec<BiomeDefinition>,
+}
+
+impl BiomeTable {
+    pub fn get(&self, id: u8) -> Option<&BiomeDefinition> {
+        self.biomes.iter().find(|biome| biome.id == id)
+    }
+
+    pub fn classify(&self, climate: &Climate) -> &BiomeDefinition {
+        self.biomes
+            .iter()
+            .find(|biome| {
+                biome.temperature.contains(climate.temperature)
+                    && biome.moisture.contains(climate.moisture)
+                    && biome.elevation.contains(climate.elevation)
+            })
+            .unwrap_or_else(|| self.fallback())
+    }
+
+    /// The biome of each vertex of the planet, indexed like the vertices of its climate.
+    pub fn classify_planet(&self, climate: &PlanetClimate) -> Vec<u8> {
+        (0..climate.sphere.len())
+            .map(|vertex| self.classify(&climate.vertex(vertex)).id)
+            .collect()
+    }
+
+    // The loader checks the fallback is one of the rows.
+    fn fallback(&self) -> &BiomeDefinition {
+        self.get(self.fallback).unwrap_or(&self.biomes[0])
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.get(self.fallback).is_none() {
+            return Err(format!("the fallback biome {} is not in the table", self.fallback));
+        }
+        for (index, biome) in self.biomes.iter().enumerate() {
+            if self.biomes[..index].iter().any(|other| other.id == biome.id) {
+                return Err(format!("the biome id {} is used more than once", biome.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct BiomeTableLoader;
+
+#[derive(Debug)]
+pub enum BiomeTableLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl fmt::Display for BiomeTableLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BiomeTableLoaderError::Io(error) => write!(f, "could not read the biome table: {}", error),
+            BiomeTableLoaderError::Ron(error) => write!(f, "could not parse the biome table: {}", error),
+            BiomeTableLoaderError::Invalid(error) => write!(f, "the biome table is invalid: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BiomeTableLoaderError {}
+
+impl From<std::io::Error> for BiomeTableLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        BiomeTableLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for BiomeTableLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        BiomeTableLoaderError::Ron(error)
+    }
+}
+
+impl AssetLoader for BiomeTableLoader {
+    type Asset = BiomeTable;
+    type Settings = ();
+    type Error = BiomeTableLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let table: BiomeTable = ron::de::from_bytes::<BiomeTable>(&bytes)?;
+        table.validate().map_err(BiomeTableLoaderError::Invalid)?;
+        Ok(table)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["biomes.ron"]
+    }
+}
+
+/// The baked climate of a planet, with the sphere its vertices are on.
+pub struct PlanetClimate {
+    pub sphere: Arc<Icosphere>,
+    pub file: ClimateFile,
+}
+
+impl fmt::Debug for PlanetClimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PlanetClimate({} vertices)", self.sphere.len())
+    }
+}
+
+impl PlanetClimate {
+    pub fn new(sphere: Arc<Icosphere>, file: ClimateFile) -> Result<Self, String> {
+        let lengths = [file.temperature.len(), file.moisture.len(), file.elevation.len()];
+        if lengths.iter().any(|length| *length != sphere.len()) {
+            return Err(format!(
+                "the climate has {:?} values, the sphere of {} subdivisions has {} vertices",
+                lengths,
+                sphere.subdivisions,
+                sphere.len()
+            ));
+        }
+        Ok(Self { sphere, file })
+    }
+
+    pub fn vertex(&self, vertex: usize) -> Climate {
+        Climate {
+            temperature: self.file.temperature[vertex],
+            moisture: self.file.moisture[vertex],
+            elevation: self.file.elevation[vertex],
+        }
+    }
+
+    /// The climate of the vertex nearest to the direction from the center of the planet, with the temperature
+    /// corrected for how far the elevation is above or below the vertex.
+    pub fn at(&self, direction: Vec3, elevation: f32) -> Climate {
+        let vertex: usize = self.sphere.nearest_vertex(direction.normalize_or(Vec3::Y)) as usize;
+        let climate: Climate = self.vertex(vertex);
+        Climate {
+            temperature: climate.temperature - self.file.lapse_rate * (elevation - climate.elevation),
+            elevation,
+            ..climate
+        }
+    }
+}
+
+/// The biome table the terrain is decorated with and the baked climate of the planet, if it has one.
+/// A clone shares the table and the climate, the meshing tasks take one to decorate the chunks.
+#[derive(Resource, Clone)]
+pub struct TerrainBiomes {
+    handle: Handle<BiomeTable>,
+    pub table: Option<Arc<BiomeTable>>,
+    pub climate: Option<Arc<PlanetClimate>>,
+}
+
+impl TerrainBiomes {
+    /// Whether the biome table is still loading. A table which failed to load is not waited on, the props are then
+    /// scattered as if every biome were fully vegetated.
+    pub fn is_loading(&self, asset_server: &AssetServer) -> bool {
+        self.table.is_none() && !asset_server.load_state(&self.handle).is_failed()
+    }
+
+    /// The climate at an absolute position. Without a baked climate it is estimated from the latitude on a planet,
+    /// or from a temperate latitude on flat terrain, and the elevation.
+    pub fn climate_at(&self, position: DVec3, planet: Option<&Planetoid>) -> Climate {
+        let Some(planet) = planet else {
+            let elevation: f32 = position.y as f32 / FLAT_TERRAIN_UNITS_PER_KM;
+            return estimate_climate(FLAT_TERRAIN_LATITUDE_DEGREES.to_radians(), elevation, &ClimateSettings::default());
+        };
+        let elevation: f32 = (position.length() as f32 - planet.radius()) / planet.elevation_scale;
+        let direction: Vec3 = position.as_vec3().normalize_or(Vec3::Y);
+        match self.climate.as_ref() {
+            Some(climate) => climate.at(direction, elevation),
+            None => {
+                let settings = ClimateSettings {
+                    axial_tilt_degrees: planet.axial_tilt,
+                    ..Default::default()
+                };
+                estimate_climate(direction.y.clamp(-1.0, 1.0).asin(), elevation, &settings)
+            }
+        }
+    }
+
+    /// The biome at an absolute position, None until the table has loaded.
+    pub fn biome_at(&self, position: DVec3, planet: Option<&Planetoid>) -> Option<&BiomeDefinition> {
+        let table = self.table.as_ref()?;
+        Some(table.classify(&self.climate_at(position, planet)))
+    }
+}
+
+fn estimate_climate(latitude: f32, elevation: f32, settings: &ClimateSettings) -> Climate {
+    let temperature: f32 = sea_level_temperature(latitude, settings.axial_tilt_degrees.to_radians(), settings);
+    Climate {
+        temperature: temperature - settings.lapse_rate * elevation.max(0.0),
+        moisture: UNBAKED_MOISTURE,
+        elevation,
+    }
+}
+
+pub fn load_biome_table(mut commands: Commands, asset_server: Res<AssetServer>, config: Res<TerrainConfig>) {
+    commands.insert_resource(TerrainBiomes {
+        handle: asset_server.load(config.biome_table.clone()),
+        table: None,
+        climate: None,
+    });
+}
+
+/// Replaces the biome table once it has loaded and again whenever the file changes, remeshing every loaded chunk so
+/// its props are scattered by the new table.
+pub fn update_biome_table(
+    mut events: EventReader<AssetEvent<BiomeTable>>,
+    tables: Res<Assets<BiomeTable>>,
+    mut biomes: ResMut<TerrainBiomes>,
+    terrain: Res<TerrainData>,
+    mut mesh_queue: ResMut<ChunkMeshQueue>,
+) {
+    for event in events.read() {
+        if !event.is_loaded_with_dependencies(&biomes.handle) && !event.is_modified(&biomes.handle) {
+            continue;
+        }
+        let Some(table) = tables.get(&biomes.handle) else {
+            warn!("The biome table changed but is not loaded");
+            continue;
+        };
+        biomes.table = Some(Arc::new(table.clone()));
+        let chunks = terrain.chunk_keys();
+        for key in chunks.iter() {
+            mesh_queue.request(*key);
+        }
+        info!("Loaded {} biome(s), remeshing {} chunk(s)", table.biomes.len(), chunks.len());
+    }
+}
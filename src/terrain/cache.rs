@@ -0,0 +1,98 @@
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+
+/// A cache shared by the meshing tasks, for values which are expensive to build but can be built again.
+///
+/// Once it holds its capacity, the least recently used quarter of the entries are evicted at once, so the cost of
+/// finding them is spread over the inserts in between. Values are built without holding the lock, two tasks may both
+/// build the same value but the values are deterministic so either one can be kept.
+pub struct BoundedCache<K, V> {
+    capacity: usize,
+    entries: Mutex<CacheEntries<K, V>>,
+}
+
+struct CacheEntries<K, V> {
+    // Each value with the tick it was last used at.
+    values: HashMap<K, (V, u64)>,
+    tick: u64,
+}
+
+impl<K, V> CacheEntries<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let tick: u64 = self.tick;
+        self.values.get_mut(key).map(|(value, used)| {
+            *used = tick;
+            value.clone()
+        })
+    }
+
+    fn insert(&mut self, key: K, value: V, capacity: usize) {
+        if self.values.len() >= capacity && !self.values.contains_key(&key) {
+            let mut ticks: Vec<u64> = self.values.values().map(|(_, used)| *used).collect();
+            let evicted: usize = (capacity / 4).max(1).min(ticks.len() - 1);
+            let (_, oldest_kept, _) = ticks.select_nth_unstable(evicted);
+            let oldest_kept: u64 = *oldest_kept;
+            self.values.retain(|_, (_, used)| *used >= oldest_kept);
+        }
+        self.tick += 1;
+        self.values.insert(key, (value, self.tick));
+    }
+}
+
+impl<K, V> BoundedCache<K, V>
+where
+    K: Eq + Hash + Copy,
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(CacheEntries {
+                values: HashMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    /// The value of the key, built and inserted when it is not cached.
+    pub fn get_or_insert_with(&self, key: K, build: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.entries.lock().unwrap().get(&key) {
+            return value;
+        }
+        let value: V = build();
+        self.entries.lock().unwrap().insert(key, value.clone(), self.capacity);
+        value
+    }
+
+    /// The values of all of the keys, looked up under a single lock. The missing values are built, then inserted
+    /// under a second one.
+    pub fn get_many<const N: usize>(&self, keys: [K; N], build: impl Fn(K) -> V) -> [V; N] {
+        let mut cached: [Option<V>; N] = {
+            let mut entries = self.entries.lock().unwrap();
+            keys.map(|key| entries.get(&key))
+        };
+        if cached.iter().all(Option::is_some) {
+            return cached.map(|value| value.unwrap());
+        }
+
+        let built: Vec<(K, V)> = keys
+            .iter()
+            .zip(cached.iter_mut())
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, value)| {
+                let built: V = build(*key);
+                *value = Some(built.clone());
+                (*key, built)
+            })
+            .collect();
+        let mut entries = self.entries.lock().unwrap();
+        for (key, value) in built {
+            entries.insert(key, value, self.capacity);
+        }
+        cached.map(|value| value.unwrap())
+    }
+}
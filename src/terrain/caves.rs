@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use bevy::math::{DVec3, IVec3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+use crate::seed::{splitmix64, SplitMix64};
+
+use super::{cache::BoundedCache, planet::Planetoid};
+
+// Caves carved out of the terrain by the density graph. Both kinds only depend on the absolute position and the seed,
+// so every chunk samples the same caves and they line up across chunk borders. The worm caves are tunnels along
+// splines, generated for each cell of a grid and cached. The cheese caves are caverns where 3D noise is above a
+// threshold. Both get more frequent with the depth below sea level.
+
+// Worm cells kept for reuse, each sample reads 27 of them so this holds the neighbourhoods of many chunks.
+const MAX_CACHED_CELLS: usize = 4096;
+// The samples of the spline between two control points of a worm.
+const SPLINE_SAMPLES: usize = 4;
+// How much a worm turns at each control point, and how much of its climb or dive it keeps.
+const WORM_TURN: f64 = 0.7;
+const WORM_CLIMB: f64 = 0.4;
+// How much the radius of a worm varies from one control point to the next, as a fraction of its radius.
+const WORM_RADIUS_VARIATION: f64 = 0.4;
+
+/// How far the position is below sea level in world units, which is the plane y = 0 on flat terrain.
+pub fn depth_below_sea_level(position: DVec3, planet: Option<&Planetoid>) -> f64 {
+    match planet {
+        Some(planet) => planet.radius() as f64 - position.length(),
+        None => -position.y,
+    }
+}
+
+// Up at the position, away from the center of a planet or +Y on flat terrain.
+fn up_at(position: DVec3, planet: Option<&Planetoid>) -> DVec3 {
+    match planet {
+        Some(_) => position.normalize_or(DVec3::Y),
+        None => DVec3::Y,
+    }
+}
+
+// From 0 at sea level to 1 at the deep depth and below.
+fn depth_fraction(position: DVec3, planet: Option<&Planetoid>, deep: f64) -> f64 {
+    (depth_below_sea_level(position, planet) / deep.max(1.0)).clamp(0.0, 1.0)
+}
+
+// A tunnel, the spline through its control points sampled as a polyline with a radius at each point.
+struct Worm {
+    points: Vec<DVec3>,
+    radii: Vec<f64>,
+    // The bounds of the tunnel including its radius.
+    min: DVec3,
+    max: DVec3,
+}
+
+impl Worm {
+    // The radius less the distance to the center line, positive inside of the tunnel.
+    fn sample(&self, p: DVec3) -> f64 {
+        let mut density: f64 = f64::NEG_INFINITY;
+        for index in 1..self.points.len() {
+            let (a, b) = (self.points[index - 1], self.points[index]);
+            let ab: DVec3 = b - a;
+            let t: f64 = ((p - a).dot(ab) / ab.length_squared().max(1.0e-9)).clamp(0.0, 1.0);
+            let radius: f64 = self.radii[index - 1] + (self.radii[index] - self.radii[index - 1]) * t;
+            density = density.max(radius - p.distance(a + ab * t));
+        }
+        density
+    }
+}
+
+/// Tunnels along seeded splines. Each cell of the grid starts a number of worms which grows with the depth of the
+/// cell, and a worm is no longer than a cell so only the cells around a position can reach it.
+pub struct WormCaves {
+    seed: u64,
+    cell_size: f64,
+    worms_per_cell: f64,
+    deep_worms_per_cell: f64,
+    deep: f64,
+    radius: f64,
+    segments: usize,
+    planet: Option<Planetoid>,
+    cells: BoundedCache<IVec3, Arc<Vec<Worm>>>,
+}
+
+impl WormCaves {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        seed: u64,
+        cell_size: f32,
+        worms_per_cell: f32,
+        deep_worms_per_cell: f32,
+        deep: f32,
+        radius: f32,
+        segments: usize,
+        planet: Option<&Planetoid>,
+    ) -> Self {
+        Self {
+            seed,
+            cell_size: cell_size as f64,
+            worms_per_cell: worms_per_cell as f64,
+            deep_worms_per_cell: deep_worms_per_cell as f64,
+            deep: deep as f64,
+            radius: radius as f64,
+            segments: segments.max(1),
+            planet: planet.cloned(),
+            cells: BoundedCache::new(MAX_CACHED_CELLS),
+        }
+    }
+
+    /// Positive inside of the tunnels, the distance to the nearest wall times -1 outside of them up to a cell away.
+    pub fn sample(&self, p: DVec3) -> f64 {
+        let cell: IVec3 = (p / self.cell_size).floor().as_ivec3();
+        // The whole neighbourhood is looked up at once, so a sample takes the lock once rather than for every cell.
+        let neighbourhood: [IVec3; 27] =
+            std::array::from_fn(|i| cell + IVec3::new(i as i32 % 3, i as i32 / 3 % 3, i as i32 / 9) - IVec3::ONE);
+        let mut density: f64 = -self.cell_size;
+        for worms in self.cells.get_many(neighbourhood, |cell| Arc::new(self.generate(cell))) {
+            for worm in worms.iter() {
+                if p.cmpge(worm.min).all() && p.cmple(worm.max).all() {
+                    density = density.max(worm.sample(p));
+                }
+            }
+        }
+        density
+    }
+
+    fn generate(&self, cell: IVec3) -> Vec<Worm> {
+        let [x, y, z] = cell.to_array().map(|coordinate| coordinate as u32 as u64);
+        let hash: u64 = splitmix64(x ^ splitmix64(y ^ splitmix64(z)));
+        let mut rng = SplitMix64::new(splitmix64(self.seed ^ hash));
+        let origin: DVec3 = cell.as_dvec3() * self.cell_size;
+
+        // The fraction of the expected number of worms is the chance of one more.
+        let fraction: f64 = depth_fraction(origin + DVec3::splat(self.cell_size / 2.0), self.planet.as_ref(), self.deep);
+        let expected: f64 = self.worms_per_cell + (self.deep_worms_per_cell - self.worms_per_cell) * fraction;
+        let count: usize = expected.floor() as usize + usize::from((rng.next_f32() as f64) < expected.fract());
+
+        (0..count).map(|_| self.generate_worm(&mut rng, origin)).collect()
+    }
+
+    fn generate_worm(&self, rng: &mut SplitMix64, origin: DVec3) -> Worm {
+        let unit = |rng: &mut SplitMix64| {
+            DVec3::new(
+                rng.range_f32(-1.0, 1.0) as f64,
+                rng.range_f32(-1.0, 1.0) as f64,
+                rng.range_f32(-1.0, 1.0) as f64,
+            )
+        };
+        let mut point: DVec3 = origin + (unit(rng) + DVec3::ONE) * (self.cell_size / 2.0);
+        let mut direction: DVec3 = unit(rng).normalize_or(DVec3::X);
+        // The control points are a random walk which turns a little at each step and stays mostly level.
+        // The walk with the widest radius stays within a cell of where it starts.
+        let widest: f64 = self.radius * (1.0 + WORM_RADIUS_VARIATION);
+        let step: f64 = (self.cell_size - 2.0 * widest).max(0.0) / self.segments as f64;
+        let mut controls: Vec<(DVec3, f64)> = Vec::with_capacity(self.segments + 1);
+        for _ in 0..=self.segments {
+            let radius: f64 = self.radius * (1.0 + WORM_RADIUS_VARIATION * rng.range_f32(-1.0, 1.0) as f64);
+            controls.push((point, radius.max(0.5)));
+            let up: DVec3 = up_at(point, self.planet.as_ref());
+            direction = (direction + unit(rng) * WORM_TURN).normalize_or(direction);
+            direction = (direction - up * direction.dot(up) * (1.0 - WORM_CLIMB)).normalize_or(direction);
+            point += direction * step;
+        }
+
+        // A Catmull-Rom spline through the control points, the first and last are repeated to end the spline on them.
+        let mut points: Vec<DVec3> = Vec::with_capacity(self.segments * SPLINE_SAMPLES + 1);
+        let mut radii: Vec<f64> = Vec::with_capacity(points.capacity());
+        let control = |index: isize| controls[index.clamp(0, self.segments as isize) as usize];
+        for segment in 0..self.segments as isize {
+            let [p0, p1, p2, p3] = [-1, 0, 1, 2].map(|offset| control(segment + offset).0);
+            let (r1, r2) = (control(segment).1, control(segment + 1).1);
+            for sample in 0..SPLINE_SAMPLES {
+                let t: f64 = sample as f64 / SPLINE_SAMPLES as f64;
+                points.push(catmull_rom(p0, p1, p2, p3, t));
+                radii.push(r1 + (r2 - r1) * t);
+            }
+        }
+        let (last, last_radius) = controls[self.segments];
+        points.push(last);
+        radii.push(last_radius);
+
+        let margin: DVec3 = DVec3::splat(radii.iter().copied().fold(0.0, f64::max));
+        let min: DVec3 = points.iter().copied().fold(DVec3::INFINITY, DVec3::min) - margin;
+        let max: DVec3 = points.iter().copied().fold(DVec3::NEG_INFINITY, DVec3::max) + margin;
+        Worm { points, radii, min, max }
+    }
+}
+
+fn catmull_rom(p0: DVec3, p1: DVec3, p2: DVec3, p3: DVec3, t: f64) -> DVec3 {
+    let t2: f64 = t * t;
+    let t3: f64 = t2 * t;
+    0.5 * (2.0 * p1 + (p2 - p0) * t + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2 + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Caverns where 3D noise is above a threshold, which falls from the threshold at sea level to the deep threshold
+/// at the deep depth so caverns are larger and more common further down.
+pub struct CheeseCaves {
+    noise: Fbm<Perlin>,
+    threshold: f64,
+    deep_threshold: f64,
+    deep: f64,
+    amplitude: f64,
+    planet: Option<Planetoid>,
+}
+
+impl CheeseCaves {
+    pub fn new(
+        seed: u32,
+        frequency: f32,
+        threshold: f32,
+        deep_threshold: f32,
+        deep: f32,
+        amplitude: f32,
+        planet: Option<&Planetoid>,
+    ) -> Self {
+        Self {
+            noise: Fbm::<Perlin>::new(seed).set_frequency(frequency as f64).set_octaves(3),
+            threshold: threshold as f64,
+            deep_threshold: deep_threshold as f64,
+            deep: deep as f64,
+            amplitude: amplitude as f64,
+            planet: planet.cloned(),
+        }
+    }
+
+    /// Positive inside of the caverns, the noise above the threshold times the amplitude.
+    pub fn sample(&self, p: DVec3) -> f64 {
+        let fraction: f64 = depth_fraction(p, self.planet.as_ref(), self.deep);
+        let threshold: f64 = self.threshold + (self.deep_threshold - self.threshold) * fraction;
+        (self.noise.get(p.to_array()) - threshold) * self.amplitude
+    }
+}
@@ -1,58 +1,154 @@
-use bevy::{asset::Assets, color::palettes::css::WHITE, log::info, pbr::{MeshMaterial3d, StandardMaterial}, prelude::{Commands, Component, Mesh, Mesh3d, ResMut, Transform}, utils::default};
-use transvoxel::{prelude::Block, transition_sides};
+use avian3d::prelude::RigidBody;
+use bevy::{
+    asset::Handle,
+    log::debug,
+    math::DVec3,
+    prelude::{Commands, Component, Mesh, Res, ResMut, Resource, Transform},
+};
+use transvoxel::{prelude::Block, transition_sides::TransitionSides};
 
-use crate::utils::{format_value_f32};
-use super::{bevy_mesh::{mesh_for_model, Model}, CHUNK_SIZE_F32, CHUNK_SIZE_I32};
+use super::{
+    bevy_mesh::mesh_for_field,
+    config::TerrainConfig,
+    density_graph::DensityField,
+    editing::is_chunk_edited,
+    lod::{select_lod_chunks, ChunkKey},
+    mesh_queue::ChunkMeshQueue,
+    octree::Octree,
+    planet::Planetoid,
+    region::RegionStore,
+    splat::TerrainSplatMaterial,
+    LODPostionTracker, LoadedChunk, TerrainData, Voxel, CHUNK_SIZE_I32,
+};
+use crate::{origin::FloatingOrigin, utils::format_value_vec3};
 
-// A unit struct to help identify the FPS UI component, since there may be many Text components
+// A component to identify terrain chunk entities and the chunk they were built for.
 #[derive(Component)]
-struct Chunk;
-
-pub fn build_chunk_mesh(cx: i32, cy: i32, cz: i32) -> Mesh {
-    let block: Block<f32> = Block::from(
-        [
-            cx as f32 * CHUNK_SIZE_F32,
-            cy as f32 * CHUNK_SIZE_F32,
-            cz as f32 * CHUNK_SIZE_F32,
-        ],
-        CHUNK_SIZE_F32,
-        CHUNK_SIZE_I32 as usize,
-    );
-    let transition_sides = transition_sides::no_side();
-    mesh_for_model(&Model::Noise, false, &block, &transition_sides)
+pub struct Chunk {
+    pub key: ChunkKey,
+}
+
+// The material shared by every terrain chunk, the meshes carry the material of each vertex for it to shade.
+#[derive(Resource)]
+pub struct TerrainMaterial(pub Handle<TerrainSplatMaterial>);
+
+/// Builds the mesh for the chunk, every chunk is meshed with the same number of subdivisions regardless of its size.
+/// The transition sides are the faces which border a chunk one level coarser and need transition cells to avoid cracks.
+/// The edits are only passed for chunks which contain edited voxels, as sampling them is much slower than the procedural field.
+pub fn build_chunk_mesh(
+    key: ChunkKey,
+    transition_sides: TransitionSides,
+    field: &DensityField,
+    edits: Option<&Octree<Voxel>>,
+) -> Mesh {
+    // The block starts at the minimum corner of the chunk, so the vertices are relative to it and stay precise
+    // however far the chunk is from the world origin.
+    let block: Block<f32> = Block::from([0.0, 0.0, 0.0], key.size(), CHUNK_SIZE_I32 as usize);
+    mesh_for_field(field, false, &block, key.base(), &transition_sides, edits)
+}
+
+// Returns true when the mesh produced no triangles, this happens for chunks that are entirely solid or entirely empty.
+pub fn is_mesh_empty(mesh: &Mesh) -> bool {
+    mesh.indices().map_or(true, |indices| indices.is_empty())
 }
 
-pub fn create_voxel_mesh(
+/// Selects the chunks for each ring of detail around the tracked chunk, spawning the new chunks and retiring the chunks which are no longer selected.
+/// New chunks and chunks whose transition sides changed are queued for meshing.
+/// The saved edits of a chunk are loaded before it is queued, and regions no chunk overlaps anymore are written back.
+/// On a planet only the chunks near its surface are loaded, as the chunks deep inside or high above it have no mesh.
+///
+/// The chunk mesh is built relative to the minimum corner of the chunk, the chunk entity is placed at that corner
+/// relative to the floating origin and is shifted with the rest of the world.
+pub fn update_loaded_chunks(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut terrain: ResMut<TerrainData>,
+    mut mesh_queue: ResMut<ChunkMeshQueue>,
+    mut regions: ResMut<RegionStore>,
+    tracked_pos: Res<LODPostionTracker>,
+    config: Res<TerrainConfig>,
+    planet: Option<Res<Planetoid>>,
+    origin: Res<FloatingOrigin>,
 ) {
-    let radius = 4;
-    let length = radius;
-    let start_x = -radius;
-    let start_z = -radius;
-
-    for i in start_x..length {
-        for j in start_z..length {
-            let x = i;
-            let z = j;
-            info!(
-                "Building Voxel Mesh @[{}, {}, {}]",
-                format_value_f32(x as f32, None, true),
-                format_value_f32(0.0, None, true),
-                format_value_f32(z as f32, None, true)
-            );
-            let bevy_mesh = build_chunk_mesh(x, 0, z);
-            // This object does not alter the transform as the transvoxel mesh using this information to sample the noise fields.
-            
-            commands.spawn((
-                Mesh3d(meshes.add(bevy_mesh)),
-                MeshMaterial3d(materials.add(StandardMaterial {
-                    base_color: WHITE.into(),
-                    ..default()
-                })),
-                Transform::from_xyz(0.0, 0.0, 0.0),
-            ));
+    // Use the center of the tracked chunk so the selection only changes when the tracked chunk does.
+    let center: DVec3 = tracked_pos.center();
+    let mut selected = select_lod_chunks(center, &config, planet.is_some());
+    if let Some(planet) = planet.as_deref() {
+        // Edited chunks are kept even away from the surface, so tunnels and towers do not disappear.
+        selected.retain(|key, _| planet.intersects_shell(key) || is_chunk_edited(&terrain.voxels, key));
+    }
+
+    // Retire every chunk which is no longer selected, its mesh stays visible until the chunks replacing it are meshed.
+    let unloaded: Vec<ChunkKey> = terrain
+        .chunk_keys()
+        .into_iter()
+        .filter(|key| !selected.contains_key(key))
+        .collect();
+    for key in unloaded.iter() {
+        if let Some(chunk) = terrain.remove_chunk(key) {
+            mesh_queue.cancel(*key);
+            terrain.retiring.push((*key, chunk.entity));
+        }
+    }
+
+    // Spawn the newly selected chunks, and remesh the chunks whose neighbours changed level.
+    let mut spawned: usize = 0;
+    for (key, transition_sides) in selected {
+        match terrain.chunk_mut(&key) {
+            Some(chunk) => {
+                if chunk.transition_sides != transition_sides {
+                    chunk.transition_sides = transition_sides;
+                    mesh_queue.request(key);
+                }
+            }
+            None => {
+                // The mesh and collider are attached once the meshing task has finished.
+                let entity = commands
+                    .spawn((
+                        Chunk { key },
+                        Transform::from_translation(origin.cell.relative(key.base())),
+                        origin.cell,
+                        RigidBody::Static,
+                    ))
+                    .id();
+                terrain.insert_chunk(
+                    key,
+                    LoadedChunk {
+                        entity,
+                        transition_sides,
+                    },
+                );
+                regions.load_chunk(&key, &mut terrain.voxels);
+                mesh_queue.request(key);
+                spawned += 1;
+            }
         }
     }
-}
\ No newline at end of file
+
+    regions.unload_unused(&mut terrain);
+
+    debug!(
+        "Loaded {} chunk(s) and retired {} chunk(s) around {}, {} chunk(s) waiting on a mesh",
+        spawned,
+        unloaded.len(),
+        format_value_vec3(center.as_vec3(), None, true),
+        mesh_queue.len()
+    );
+}
+
+/// Despawns the retired chunks once no chunk overlapping them is waiting on a mesh, this avoids holes while the level of detail changes.
+pub fn despawn_retired_chunks(
+    mut commands: Commands,
+    mut terrain: ResMut<TerrainData>,
+    mesh_queue: Res<ChunkMeshQueue>,
+) {
+    if terrain.retiring.is_empty() {
+        return;
+    }
+    terrain.retiring.retain(|(key, entity)| {
+        if mesh_queue.is_overlapping_pending(key) {
+            return true;
+        }
+        commands.entity(*entity).despawn();
+        false
+    });
+}
@@ -0,0 +1,319 @@
+use std::{f32::consts::PI, time::Instant};
+
+use bevy::{
+    log::info,
+    math::{Quat, Vec3},
+};
+
+use super::simulation::{CrustType, PlanetoidSimulation};
+
+// A coarse annual mean climate on the vertices of the simulation, computed from its current state. The climate
+// changes far quicker than the plates move, so it is derived from the final planet rather than stepped with it.
+//
+// Winds follow the three cells of the atmospheric circulation in each hemisphere:
+//     0° to 30° - the trade winds blow from the east towards the equator, where the air rises and rains.
+//     30° to 60° - the westerlies blow from the west towards the poles.
+//     60° to 90° - the polar easterlies blow from the east towards the equator.
+// The air is dry where it sinks, around 30° and at the poles, and wet where it rises, at the equator and around 60°.
+// Surface currents are pushed by the wind, deflected by the rotation of the planet and turned aside by the coasts,
+// and carry the warmth of the tropics towards the poles. Air picks up moisture over the oceans and drops it as it
+// travels inland, most of all where it is forced up mountains, which leaves a rain shadow on their far side.
+// The temperature follows the sunlight the latitude receives over a year, which depends on the axial tilt,
+// and falls with the altitude.
+
+/// The axial tilt of the Earth in degrees, the default for planets which do not set their own.
+pub const EARTH_AXIAL_TILT_DEGREES: f32 = 23.5;
+
+// The points in the year the sunlight is averaged over.
+const SEASON_SAMPLES: usize = 24;
+
+pub struct ClimateSettings {
+    pub axial_tilt_degrees: f32,
+    // The annual mean temperature at sea level on the equator of a planet without tilt, in °C.
+    pub equator_temperature: f32,
+    // The coldest the annual mean temperature gets at sea level, in °C.
+    pub min_temperature: f32,
+    // How much colder it gets with the altitude, in °C per km.
+    pub lapse_rate: f32,
+    // How many vertices far the wind and the currents carry moisture and heat.
+    pub iterations: u32,
+    // The fraction of the moisture the air can hold which it picks up over warm water each vertex.
+    pub evaporation: f32,
+    // The fraction of its moisture the air drops each vertex where it neither rises nor sinks.
+    pub rain_rate: f32,
+    // The extra fraction the air drops per km it is forced to rise.
+    pub orographic_rate: f32,
+    // The fraction of the rain on land which evaporates back into the air.
+    pub recycling: f32,
+    // The precipitation in mm per year of air saturated with moisture that drops all of it.
+    pub max_precipitation: f32,
+    // The strongest prevailing wind in m/s.
+    pub wind_speed: f32,
+    // The strongest surface current in m/s.
+    pub current_speed: f32,
+}
+
+impl Default for ClimateSettings {
+    fn default() -> Self {
+        Self {
+            axial_tilt_degrees: EARTH_AXIAL_TILT_DEGREES,
+            equator_temperature: 28.0,
+            min_temperature: -50.0,
+            lapse_rate: 6.5,
+            iterations: 64,
+            evaporation: 0.2,
+            rain_rate: 0.05,
+            orographic_rate: 1.5,
+            recycling: 0.4,
+            max_precipitation: 6000.0,
+            wind_speed: 8.0,
+            current_speed: 0.5,
+        }
+    }
+}
+
+/// The climate of each vertex of a [`PlanetoidSimulation`], indexed like its crust.
+pub struct ClimateMaps {
+    // The prevailing wind along the surface, in m/s.
+    pub wind: Vec<Vec3>,
+    // The surface current along the surface in m/s, zero on land.
+    pub ocean_currents: Vec<Vec3>,
+    // The annual mean temperature at the surface, in °C.
+    pub temperature: Vec<f32>,
+    // The moisture of the air relative to the most it holds, from 0 to 1.
+    pub moisture: Vec<f32>,
+    // In mm per year.
+    pub precipitation: Vec<f32>,
+}
+
+impl ClimateMaps {
+    pub fn simulate(simulation: &PlanetoidSimulation, settings: &ClimateSettings) -> Self {
+        let start: Instant = Instant::now();
+        let sphere = &simulation.sphere;
+        let tilt: f32 = settings.axial_tilt_degrees.to_radians();
+        let is_ocean: Vec<bool> = (0..sphere.len())
+            .map(|vertex| simulation.crust_type(vertex) == CrustType::Oceanic && simulation.elevation(vertex) < 0.0)
+            .collect();
+        let latitudes: Vec<f32> = sphere.vertices.iter().map(|vertex| vertex.y.clamp(-1.0, 1.0).asin()).collect();
+
+        let wind: Vec<Vec3> = sphere
+            .vertices
+            .iter()
+            .zip(latitudes.iter())
+            .map(|(vertex, latitude)| prevailing_wind(*vertex, *latitude) * settings.wind_speed)
+            .collect();
+        let ocean_currents: Vec<Vec3> = surface_currents(simulation, &wind, &is_ocean, settings);
+
+        // The sea surface starts at the temperature of its latitude and is carried along the currents.
+        let sea_level_temperature: Vec<f32> = latitudes
+            .iter()
+            .map(|latitude| sea_level_temperature(*latitude, tilt, settings))
+            .collect();
+        let mut temperature: Vec<f32> = sea_level_temperature.clone();
+        let current_upstream: Vec<Vec<(u32, f32)>> = upstream_weights(simulation, &ocean_currents);
+        for _ in 0..settings.iterations {
+            temperature = (0..sphere.len())
+                .map(|vertex| {
+                    if !is_ocean[vertex] {
+                        return sea_level_temperature[vertex];
+                    }
+                    // The water keeps some of its own temperature, the sun keeps pulling it back to the latitude's.
+                    let carried: f32 = weighted(&current_upstream[vertex], &temperature);
+                    0.2 * sea_level_temperature[vertex] + 0.8 * carried
+                })
+                .collect();
+        }
+        for vertex in 0..sphere.len() {
+            if !is_ocean[vertex] {
+                temperature[vertex] -= settings.lapse_rate * simulation.elevation(vertex).max(0.0);
+            }
+        }
+
+        let (moisture, precipitation) =
+            carry_moisture(simulation, &wind, &is_ocean, &latitudes, &temperature, settings);
+
+        info!(
+            "Simulated the climate of {} vertices with a {}° axial tilt in {:?}",
+            sphere.len(),
+            settings.axial_tilt_degrees,
+            start.elapsed()
+        );
+        Self {
+            wind,
+            ocean_currents,
+            temperature,
+            moisture,
+            precipitation,
+        }
+    }
+}
+
+// The direction the planet turns towards, the planet spins around +Y.
+fn east(vertex: Vec3) -> Vec3 {
+    Vec3::Y.cross(vertex).try_normalize().unwrap_or(Vec3::X)
+}
+
+fn north(vertex: Vec3) -> Vec3 {
+    vertex.cross(east(vertex))
+}
+
+// The wind of the circulation cell the latitude is in, at most one long.
+fn prevailing_wind(vertex: Vec3, latitude: f32) -> Vec3 {
+    let band: f32 = latitude.abs() / (PI / 6.0);
+    // The winds are strongest in the middle of each cell and calm where the cells meet.
+    let strength: f32 = (band.fract() * PI).sin();
+    let (eastward, poleward) = if band as u32 == 1 { (1.0, 0.5) } else { (-1.0, -0.5) };
+    let northward: f32 = poleward * latitude.signum();
+    (east(vertex) * eastward + north(vertex) * northward).normalize() * (0.25 + 0.75 * strength)
+}
+
+// The annual mean sunlight relative to the sunlight on the equator of a planet without tilt, from the daily mean
+// insolation averaged over the declinations of the sun through the year.
+fn annual_insolation(latitude: f32, tilt: f32) -> f32 {
+    let mut total: f32 = 0.0;
+    for sample in 0..SEASON_SAMPLES {
+        let declination: f32 = tilt * (2.0 * PI * (sample as f32 + 0.5) / SEASON_SAMPLES as f32).sin();
+        // The hour angle of the sunset, zero in the polar night and pi in the midnight sun.
+        let sunset: f32 = (-latitude.tan() * declination.tan()).clamp(-1.0, 1.0).acos();
+        total += sunset * latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * sunset.sin();
+    }
+    total / SEASON_SAMPLES as f32
+}
+
+// The surface radiates like a black body, so the temperature grows with the fourth root of the sunlight.
+pub(crate) fn sea_level_temperature(latitude: f32, tilt: f32, settings: &ClimateSettings) -> f32 {
+    const ZERO_CELSIUS: f32 = 273.15;
+    let sunlight: f32 = annual_insolation(latitude, tilt).max(0.0);
+    let kelvin: f32 = (settings.equator_temperature + ZERO_CELSIUS) * sunlight.powf(0.25);
+    (kelvin - ZERO_CELSIUS).max(settings.min_temperature)
+}
+
+// The wind drags the surface water at an angle, to the right in the north and to the left in the south,
+// and the coasts turn the water aside. A few rounds of smoothing join the flows into gyres.
+fn surface_currents(
+    simulation: &PlanetoidSimulation,
+    wind: &[Vec3],
+    is_ocean: &[bool],
+    settings: &ClimateSettings,
+) -> Vec<Vec3> {
+    let sphere = &simulation.sphere;
+    let mut currents: Vec<Vec3> = (0..sphere.len())
+        .map(|vertex| {
+            if !is_ocean[vertex] {
+                return Vec3::ZERO;
+            }
+            let normal: Vec3 = sphere.vertices[vertex];
+            let angle: f32 = -(PI / 4.0) * normal.y.signum();
+            Quat::from_axis_angle(normal, angle) * wind[vertex]
+        })
+        .collect();
+
+    for _ in 0..4 {
+        currents = (0..sphere.len())
+            .map(|vertex| {
+                if !is_ocean[vertex] {
+                    return Vec3::ZERO;
+                }
+                let normal: Vec3 = sphere.vertices[vertex];
+                let neighbours = &sphere.neighbours[vertex];
+                let mut current: Vec3 = currents[vertex];
+                for &neighbour in neighbours.iter() {
+                    current += currents[neighbour as usize];
+                }
+                current /= (neighbours.len() + 1) as f32;
+                for &neighbour in neighbours.iter().filter(|neighbour| !is_ocean[**neighbour as usize]) {
+                    let towards: Vec3 = (sphere.vertices[neighbour as usize] - normal).normalize();
+                    current -= towards * current.dot(towards).max(0.0);
+                }
+                current.reject_from_normalized(normal)
+            })
+            .collect();
+    }
+
+    let strongest: f32 = currents.iter().map(|current| current.length()).fold(0.0, f32::max);
+    if strongest > 0.0 {
+        currents.iter_mut().for_each(|current| *current *= settings.current_speed / strongest);
+    }
+    currents
+}
+
+// The neighbours each vertex receives from when things move along the flow, weighted by how directly the flow comes
+// from them. A vertex without a flow only receives from itself.
+fn upstream_weights(simulation: &PlanetoidSimulation, flow: &[Vec3]) -> Vec<Vec<(u32, f32)>> {
+    let sphere = &simulation.sphere;
+    (0..sphere.len())
+        .map(|vertex| {
+            let Some(direction) = flow[vertex].try_normalize() else {
+                return vec![(vertex as u32, 1.0)];
+            };
+            let position: Vec3 = sphere.vertices[vertex];
+            let mut weights: Vec<(u32, f32)> = sphere.neighbours[vertex]
+                .iter()
+                .filter_map(|&neighbour| {
+                    let from: Vec3 = (position - sphere.vertices[neighbour as usize]).normalize();
+                    let weight: f32 = from.dot(direction);
+                    (weight > 0.0).then_some((neighbour, weight * weight))
+                })
+                .collect();
+            let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+            if total <= 0.0 {
+                return vec![(vertex as u32, 1.0)];
+            }
+            weights.iter_mut().for_each(|(_, weight)| *weight /= total);
+            weights
+        })
+        .collect()
+}
+
+fn weighted(weights: &[(u32, f32)], values: &[f32]) -> f32 {
+    weights.iter().map(|(vertex, weight)| values[*vertex as usize] * weight).sum()
+}
+
+// Carries the moisture along the wind until it settles, returning the moisture and the precipitation.
+fn carry_moisture(
+    simulation: &PlanetoidSimulation,
+    wind: &[Vec3],
+    is_ocean: &[bool],
+    latitudes: &[f32],
+    temperature: &[f32],
+    settings: &ClimateSettings,
+) -> (Vec<f32>, Vec<f32>) {
+    let sphere = &simulation.sphere;
+    let upstream: Vec<Vec<(u32, f32)>> = upstream_weights(simulation, wind);
+    // Rising air rains the most, at the equator and around 60°, sinking air around 30° and at the poles the least.
+    let circulation: Vec<f32> = latitudes.iter().map(|latitude| 0.6 + 0.4 * (6.0 * latitude).cos()).collect();
+    // Warm water evaporates the most, frozen water barely at all.
+    let evaporation: Vec<f32> = temperature
+        .iter()
+        .map(|temperature| settings.evaporation * ((temperature + 5.0) / 30.0).clamp(0.05, 1.0))
+        .collect();
+
+    let mut moisture: Vec<f32> = is_ocean.iter().map(|ocean| if *ocean { 0.5 } else { 0.0 }).collect();
+    let mut rain: Vec<f32> = vec![0.0; sphere.len()];
+    for _ in 0..settings.iterations.max(1) {
+        let mut carried: Vec<f32> = Vec::with_capacity(sphere.len());
+        for vertex in 0..sphere.len() {
+            let incoming: f32 = weighted(&upstream[vertex], &moisture);
+            // The climb from the vertices the air comes from, in km.
+            let climb: f32 = upstream[vertex]
+                .iter()
+                .map(|(from, weight)| {
+                    (simulation.elevation(vertex).max(0.0) - simulation.elevation(*from as usize).max(0.0)) * weight
+                })
+                .sum::<f32>()
+                .max(0.0);
+            let rate: f32 = (settings.rain_rate * circulation[vertex] + settings.orographic_rate * climb).min(1.0);
+            let fallen: f32 = incoming * rate;
+            rain[vertex] = fallen;
+            carried.push(if is_ocean[vertex] {
+                (incoming - fallen + evaporation[vertex] * (1.0 - incoming)).min(1.0)
+            } else {
+                incoming - fallen * (1.0 - settings.recycling)
+            });
+        }
+        moisture = carried;
+    }
+
+    let precipitation: Vec<f32> = rain.iter().map(|rain| rain * settings.max_precipitation).collect();
+    (moisture, precipitation)
+}
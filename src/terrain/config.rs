@@ -0,0 +1,60 @@
+use bevy::prelude::Resource;
+
+#[derive(Resource)]
+pub struct TerrainConfig {
+    // The number of chunks of the coarsest level loaded in each direction along the x and z axis around the camera.
+    pub (crate) view_radius: i32,
+    // The number of chunks of the coarsest level loaded above and below the camera.
+    pub (crate) vertical_view_radius: i32,
+    // The number of levels of detail, each level doubles the size of a chunk.
+    pub (crate) lod_levels: u8,
+    // A chunk is split into finer chunks while the camera is closer than this many chunk lengths of its level.
+    pub (crate) lod_ring_radius: f32,
+    // The maximum number of chunk meshing tasks running on the async compute pool at once.
+    pub (crate) max_mesh_tasks_in_flight: usize,
+    // The maximum number of finished chunk meshes attached to their chunk each frame.
+    pub (crate) mesh_tasks_applied_per_frame: usize,
+    // Chunks up to and including this level of detail get a collider, 0 limits colliders to the nearest ring.
+    pub (crate) collider_max_lod: u8,
+    // The maximum distance from the camera at which the terrain can be edited.
+    pub (crate) edit_reach: f32,
+    // The directory the worlds are saved in, relative to the working directory, each world seed gets its own directory.
+    pub (crate) save_directory: String,
+    // The density graph the terrain is generated from, relative to the assets directory.
+    pub (crate) density_graph: String,
+    // The biome table the terrain is decorated with, relative to the assets directory.
+    pub (crate) biome_table: String,
+    // The number of cells along each side of the grid the lakes around the camera are filled on.
+    pub (crate) lake_cells: usize,
+    // The distance between the columns of the lake grid.
+    pub (crate) lake_cell_size: f32,
+    // Basins shallower than this stay dry.
+    pub (crate) min_lake_depth: f32,
+    // How far above and below sea level the surface of flat terrain is searched for, planets use their relief.
+    pub (crate) flat_relief: f32,
+    // How far the sea surface reaches from the camera, it is only drawn up to the horizon on a planet.
+    pub (crate) sea_extent: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            view_radius: 3,
+            vertical_view_radius: 1,
+            lod_levels: 4,
+            lod_ring_radius: 2.0,
+            max_mesh_tasks_in_flight: 8,
+            mesh_tasks_applied_per_frame: 4,
+            collider_max_lod: 0,
+            edit_reach: 12.0,
+            save_directory: "saves".to_owned(),
+            density_graph: "terrain/hills.density.ron".to_owned(),
+            biome_table: "terrain/default.biomes.ron".to_owned(),
+            lake_cells: 64,
+            lake_cell_size: 4.0,
+            min_lake_depth: 0.5,
+            flat_relief: 128.0,
+            sea_extent: 4096.0,
+        }
+    }
+}
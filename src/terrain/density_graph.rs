@@ -0,0 +1,891 @@
+use std::{cell::RefCell, collections::HashMap, fmt, sync::Arc};
+
+use bevy::{
+    asset::{io::Reader, Asset, AssetEvent, AssetLoader, AssetServer, Assets, Handle, LoadContext},
+    log::{info, warn},
+    math::{DVec3, Vec3},
+    prelude::{Commands, EventReader, Res, ResMut, Resource},
+    reflect::TypePath,
+};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti};
+use serde::Deserialize;
+use transvoxel::voxel_source::DataField;
+
+use crate::{origin::FloatingOrigin, physics::GravityField, seed::WorldSeed};
+
+use super::{
+    bake::{ClimateFile, ElevationMap, LayerFile},
+    biome::{PlanetClimate, TerrainBiomes},
+    caves::{CheeseCaves, WormCaves},
+    config::TerrainConfig,
+    erosion::{ErosionTiles, HydraulicErosionSettings, ThermalErosionSettings},
+    icosphere::Icosphere,
+    mesh_queue::ChunkMeshQueue,
+    planet::Planetoid,
+    strata::{PlanetStrata, Strata, VoxelMaterial},
+    LODPostionTracker, TerrainData,
+};
+
+/// A node of a density graph, the density is positive inside of the terrain and negative outside.
+///
+/// Graphs are written in RON, for example a plane with hills on top of it:
+///
+/// ```ron
+/// (
+///     root: Add([
+///         Plane(normal: (0.0, 1.0, 0.0), height: 0.0),
+///         Fbm(seed: 0, frequency: 0.05, amplitude: 8.0),
+///     ]),
+/// )
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+pub enum DensityNode {
+    Constant(f32),
+
+    // Primitives, each is positive inside of the shape and grows with the distance to its surface.
+    Sphere {
+        center: (f32, f32, f32),
+        radius: f32,
+    },
+    // The normal points out of the terrain, its length scales how quickly the density grows below the plane.
+    Plane {
+        normal: (f32, f32, f32),
+        height: f32,
+    },
+    Box {
+        center: (f32, f32, f32),
+        half_extents: (f32, f32, f32),
+    },
+
+    // Noise sources, each returns values roughly between -amplitude and amplitude.
+    // The seed of a noise node picks a layer, the noise itself is seeded from the world seed and the layer.
+    Perlin {
+        seed: u32,
+        #[serde(default = "default_frequency")]
+        frequency: f32,
+        #[serde(default = "default_amplitude")]
+        amplitude: f32,
+    },
+    Fbm {
+        seed: u32,
+        #[serde(default = "default_frequency")]
+        frequency: f32,
+        #[serde(default = "default_amplitude")]
+        amplitude: f32,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f32,
+        #[serde(default = "default_persistence")]
+        persistence: f32,
+    },
+    Ridged {
+        seed: u32,
+        #[serde(default = "default_frequency")]
+        frequency: f32,
+        #[serde(default = "default_amplitude")]
+        amplitude: f32,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f32,
+    },
+
+    // The elevation of a baked planet in km times the scale, sampled in the direction of the position from the world origin.
+    // The map is a path in the assets folder to the `elevation.f32` of a bake, and is reloaded along with the graph.
+    Elevation {
+        map: String,
+        #[serde(default = "default_amplitude")]
+        scale: f32,
+    },
+
+    // The input eroded by water and scree, for flat terrain. The input is sampled on the XZ plane as a height, so the
+    // node goes on top of a plane, for example Add([Plane(...), Erosion(seed: 0, input: Fbm(...))]).
+    // The height is eroded in tiles of cells as it is sampled, a pass is skipped when its settings are left out.
+    Erosion {
+        seed: u32,
+        #[serde(default = "default_erosion_tile_size")]
+        tile_size: f32,
+        #[serde(default = "default_erosion_cell_size")]
+        cell_size: f32,
+        #[serde(default)]
+        hydraulic: Option<HydraulicErosionSettings>,
+        #[serde(default)]
+        thermal: Option<ThermalErosionSettings>,
+        input: Box<DensityNode>,
+    },
+
+    // Caves, each is positive inside of the caves and grows with the distance to their walls. They are carved out of
+    // the terrain with Min([terrain, Negate(caves)]), and get more common from sea level down to the deep depth, which
+    // is below y = 0 on flat terrain. The depth of the rock layers is measured as if the caves were not there.
+    // Tunnels along seeded splines, each cell of the grid starts the expected number of worms between the two counts.
+    WormCaves {
+        seed: u32,
+        #[serde(default = "default_worm_cell_size")]
+        cell_size: f32,
+        #[serde(default = "default_worms_per_cell")]
+        worms_per_cell: f32,
+        #[serde(default = "default_deep_worms_per_cell")]
+        deep_worms_per_cell: f32,
+        #[serde(default = "default_cave_depth")]
+        deep: f32,
+        #[serde(default = "default_worm_radius")]
+        radius: f32,
+        #[serde(default = "default_worm_segments")]
+        segments: usize,
+    },
+    // Caverns where 3D noise is above the threshold, which falls to the deep threshold with depth.
+    CheeseCaves {
+        seed: u32,
+        #[serde(default = "default_cheese_frequency")]
+        frequency: f32,
+        #[serde(default = "default_cheese_threshold")]
+        threshold: f32,
+        #[serde(default = "default_deep_cheese_threshold")]
+        deep_threshold: f32,
+        #[serde(default = "default_cave_depth")]
+        deep: f32,
+        #[serde(default = "default_cheese_amplitude")]
+        amplitude: f32,
+    },
+
+    // Domain operations change the position the input is sampled at.
+    Translate {
+        offset: (f32, f32, f32),
+        input: Box<DensityNode>,
+    },
+    // Samples the input at the position divided by the factor, which makes its features larger.
+    Scale {
+        factor: f32,
+        input: Box<DensityNode>,
+    },
+    // Offsets the position by the warp node, sampled once for each axis at decorrelated positions.
+    DomainWarp {
+        warp: Box<DensityNode>,
+        input: Box<DensityNode>,
+    },
+
+    // Combinators.
+    Add(Vec<DensityNode>),
+    Multiply(Vec<DensityNode>),
+    // The intersection of the inputs.
+    Min(Vec<DensityNode>),
+    // The union of the inputs.
+    Max(Vec<DensityNode>),
+    // A union which blends the surfaces where they are closer than the smoothness.
+    SmoothUnion {
+        smoothness: f32,
+        a: Box<DensityNode>,
+        b: Box<DensityNode>,
+    },
+    // An intersection which blends the surfaces where they are closer than the smoothness.
+    SmoothIntersection {
+        smoothness: f32,
+        a: Box<DensityNode>,
+        b: Box<DensityNode>,
+    },
+    Negate(Box<DensityNode>),
+    Clamp {
+        min: f32,
+        max: f32,
+        input: Box<DensityNode>,
+    },
+
+    // The planet described by the graph, the surface node is sampled on the sea level sphere and gives the height of
+    // the surface above sea level. It should stay within the relief of the planet, as chunks outside of it are not loaded.
+    Planet {
+        surface: Box<DensityNode>,
+    },
+}
+
+fn default_frequency() -> f32 {
+    Fbm::<Perlin>::DEFAULT_FREQUENCY as f32
+}
+
+fn default_amplitude() -> f32 {
+    1.0
+}
+
+fn default_erosion_tile_size() -> f32 {
+    64.0
+}
+
+// One height per voxel.
+fn default_erosion_cell_size() -> f32 {
+    1.0
+}
+
+fn default_worm_cell_size() -> f32 {
+    96.0
+}
+
+fn default_worms_per_cell() -> f32 {
+    0.3
+}
+
+fn default_deep_worms_per_cell() -> f32 {
+    1.5
+}
+
+fn default_cave_depth() -> f32 {
+    256.0
+}
+
+fn default_worm_radius() -> f32 {
+    3.0
+}
+
+fn default_worm_segments() -> usize {
+    6
+}
+
+fn default_cheese_frequency() -> f32 {
+    0.015
+}
+
+fn default_cheese_threshold() -> f32 {
+    0.55
+}
+
+fn default_deep_cheese_threshold() -> f32 {
+    0.3
+}
+
+fn default_cheese_amplitude() -> f32 {
+    32.0
+}
+
+fn default_octaves() -> usize {
+    Fbm::<Perlin>::DEFAULT_OCTAVE_COUNT
+}
+
+fn default_lacunarity() -> f32 {
+    Fbm::<Perlin>::DEFAULT_LACUNARITY as f32
+}
+
+fn default_persistence() -> f32 {
+    Fbm::<Perlin>::DEFAULT_PERSISTENCE as f32
+}
+
+impl DensityNode {
+    fn children(&self) -> Vec<&DensityNode> {
+        match self {
+            DensityNode::Translate { input, .. }
+            | DensityNode::Scale { input, .. }
+            | DensityNode::Negate(input)
+            | DensityNode::Clamp { input, .. }
+            | DensityNode::Erosion { input, .. } => vec![input],
+            DensityNode::Planet { surface } => vec![surface],
+            DensityNode::DomainWarp { warp, input } => vec![warp, input],
+            DensityNode::SmoothUnion { a, b, .. } | DensityNode::SmoothIntersection { a, b, .. } => vec![a, b],
+            DensityNode::Add(inputs) | DensityNode::Multiply(inputs) | DensityNode::Min(inputs) | DensityNode::Max(inputs) => {
+                inputs.iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // The paths of the elevation maps used by this node and the nodes below it.
+    fn elevation_maps<'a>(&'a self, paths: &mut Vec<&'a str>) {
+        if let DensityNode::Elevation { map, .. } = self {
+            paths.push(map);
+        }
+        for child in self.children() {
+            child.elevation_maps(paths);
+        }
+    }
+}
+
+/// A density graph loaded from a `.density.ron` file, with the planet the terrain is generated on if there is one.
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct DensityGraph {
+    #[serde(default)]
+    pub planet: Option<Planetoid>,
+    // Whether everything below sea level is flooded. Graphs whose caves reach below sea level leave it out, the lakes
+    // fill the basins of the surface either way.
+    #[serde(default)]
+    pub sea: bool,
+    pub root: DensityNode,
+    // The elevation maps used by the graph by path, read by the loader.
+    #[serde(skip)]
+    pub elevation_maps: HashMap<String, Arc<ElevationMap>>,
+    // The baked climate of the planet, read by the loader.
+    #[serde(skip)]
+    pub climate: Option<Arc<PlanetClimate>>,
+    // The baked rock layers of the planet, read by the loader.
+    #[serde(skip)]
+    pub strata: Option<Arc<PlanetStrata>>,
+}
+
+#[derive(Default)]
+pub struct DensityGraphLoader;
+
+#[derive(Debug)]
+pub enum DensityGraphLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Map(String),
+    Climate(String),
+    Layers(String),
+}
+
+impl fmt::Display for DensityGraphLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DensityGraphLoaderError::Io(error) => write!(f, "could not read the density graph: {}", error),
+            DensityGraphLoaderError::Ron(error) => write!(f, "could not parse the density graph: {}", error),
+            DensityGraphLoaderError::Map(error) => write!(f, "could not load an elevation map: {}", error),
+            DensityGraphLoaderError::Climate(error) => write!(f, "could not load the climate of the planet: {}", error),
+            DensityGraphLoaderError::Layers(error) => write!(f, "could not load the layers of the planet: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for DensityGraphLoaderError {}
+
+impl From<std::io::Error> for DensityGraphLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        DensityGraphLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for DensityGraphLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        DensityGraphLoaderError::Ron(error)
+    }
+}
+
+impl AssetLoader for DensityGraphLoader {
+    type Asset = DensityGraph;
+    type Settings = ();
+    type Error = DensityGraphLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut graph: DensityGraph = ron::de::from_bytes::<DensityGraph>(&bytes)?;
+
+        // Reading the maps through the load context makes the graph reload when a map is baked again.
+        let mut paths: Vec<&str> = Vec::new();
+        graph.root.elevation_maps(&mut paths);
+        let paths: Vec<String> = paths.into_iter().map(str::to_owned).collect();
+        for path in paths {
+            if graph.elevation_maps.contains_key(&path) {
+                continue;
+            }
+            let bytes: Vec<u8> = load_context
+                .read_asset_bytes(path.clone())
+                .await
+                .map_err(|error| DensityGraphLoaderError::Map(format!("{}: {}", path, error)))?;
+            let map: ElevationMap =
+                ElevationMap::from_bytes(&bytes).map_err(|error| DensityGraphLoaderError::Map(format!("{}: {}", path, error)))?;
+            graph.elevation_maps.insert(path, Arc::new(map));
+        }
+
+        // The climate and the layers of a bake are on the same sphere, which is only built once.
+        let mut spheres: HashMap<u32, Arc<Icosphere>> = HashMap::new();
+        let mut sphere = |subdivisions: u32| -> Arc<Icosphere> {
+            spheres
+                .entry(subdivisions)
+                .or_insert_with(|| Arc::new(Icosphere::new(subdivisions)))
+                .clone()
+        };
+
+        if let Some(path) = graph.planet.as_ref().and_then(|planet| planet.climate.clone()) {
+            let error = |error: String| DensityGraphLoaderError::Climate(format!("{}: {}", path, error));
+            let bytes: Vec<u8> = load_context
+                .read_asset_bytes(path.clone())
+                .await
+                .map_err(|e| error(e.to_string()))?;
+            let file: ClimateFile = ron::de::from_bytes::<ClimateFile>(&bytes).map_err(|e| error(e.to_string()))?;
+            let climate = PlanetClimate::new(sphere(file.subdivisions), file).map_err(error)?;
+            graph.climate = Some(Arc::new(climate));
+        }
+        if let Some(path) = graph.planet.as_ref().and_then(|planet| planet.layers.clone()) {
+            let error = |error: String| DensityGraphLoaderError::Layers(format!("{}: {}", path, error));
+            let bytes: Vec<u8> = load_context
+                .read_asset_bytes(path.clone())
+                .await
+                .map_err(|e| error(e.to_string()))?;
+            let file: LayerFile = ron::de::from_bytes::<LayerFile>(&bytes).map_err(|e| error(e.to_string()))?;
+            let strata = PlanetStrata::new(sphere(file.subdivisions), file.terranes).map_err(error)?;
+            graph.strata = Some(Arc::new(strata));
+        }
+        Ok(graph)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["density.ron"]
+    }
+}
+
+// The node after its noise functions were built, so sampling never allocates.
+enum CompiledNode {
+    Constant(f64),
+    Sphere { center: DVec3, radius: f64 },
+    Plane { normal: DVec3, height: f64 },
+    Box { center: DVec3, half_extents: DVec3 },
+    Perlin { noise: Perlin, frequency: f64, amplitude: f64 },
+    Fbm { noise: Fbm<Perlin>, amplitude: f64 },
+    Elevation { map: Arc<ElevationMap>, scale: f64 },
+    Ridged { noise: RidgedMulti<Perlin>, amplitude: f64 },
+    Erosion(Arc<ErosionNode>),
+    WormCaves(WormCaves),
+    CheeseCaves(CheeseCaves),
+    Translate { offset: DVec3, input: Box<CompiledNode> },
+    Scale { factor: f64, input: Box<CompiledNode> },
+    DomainWarp { warp: Box<CompiledNode>, input: Box<CompiledNode> },
+    Add(Vec<CompiledNode>),
+    Multiply(Vec<CompiledNode>),
+    Min(Vec<CompiledNode>),
+    Max(Vec<CompiledNode>),
+    SmoothUnion { smoothness: f64, a: Box<CompiledNode>, b: Box<CompiledNode> },
+    SmoothIntersection { smoothness: f64, a: Box<CompiledNode>, b: Box<CompiledNode> },
+    Negate(Box<CompiledNode>),
+    Clamp { min: f64, max: f64, input: Box<CompiledNode> },
+    Planet { planet: Planetoid, surface: Box<CompiledNode> },
+}
+
+// An erosion node with its input. The graph with the caves and the graph without them share it, so its tiles are only
+// eroded once. The input keeps any caves in both, a height sampled at y = 0 has no use for them anyway.
+struct ErosionNode {
+    tiles: ErosionTiles,
+    input: CompiledNode,
+}
+
+// The warp node is sampled at these offsets for the y and z axis so the three axes are not correlated.
+const WARP_OFFSET_Y: DVec3 = DVec3::new(31.7, -12.3, 47.1);
+const WARP_OFFSET_Z: DVec3 = DVec3::new(-53.9, 27.5, -8.6);
+// Halving the step this many times finds the surface to within a thousandth of the step.
+const SURFACE_BISECTIONS: usize = 10;
+
+fn to_dvec3(value: (f32, f32, f32)) -> DVec3 {
+    DVec3::new(value.0 as f64, value.1 as f64, value.2 as f64)
+}
+
+struct CompileContext<'a> {
+    world_seed: &'a WorldSeed,
+    planet: Option<&'a Planetoid>,
+    elevation_maps: &'a HashMap<String, Arc<ElevationMap>>,
+    // Compiles the caves as nothing, for the surface the depth of the rock layers is measured from.
+    without_caves: bool,
+    // The erosion nodes compiled so far by the address of their node, both compiles of a graph walk the same nodes.
+    erosion: &'a RefCell<HashMap<usize, Arc<ErosionNode>>>,
+}
+
+// Two noise nodes with the same seed in one graph produce the same noise, which is what lets a layer be reused.
+fn noise_seed(world_seed: &WorldSeed, seed: u32) -> u32 {
+    world_seed.derive_u32(&format!("density_graph/{}", seed))
+}
+
+impl CompiledNode {
+    fn compile(node: &DensityNode, context: &CompileContext) -> Result<CompiledNode, String> {
+        let compile_box =
+            |node: &DensityNode| -> Result<Box<CompiledNode>, String> { Ok(Box::new(CompiledNode::compile(node, context)?)) };
+        let compile_all = |nodes: &Vec<DensityNode>| -> Result<Vec<CompiledNode>, String> {
+            nodes.iter().map(|node| CompiledNode::compile(node, context)).collect()
+        };
+        Ok(match node {
+            DensityNode::Constant(value) => CompiledNode::Constant(*value as f64),
+            DensityNode::Sphere { center, radius } => CompiledNode::Sphere {
+                center: to_dvec3(*center),
+                radius: *radius as f64,
+            },
+            DensityNode::Plane { normal, height } => CompiledNode::Plane {
+                normal: to_dvec3(*normal),
+                height: *height as f64,
+            },
+            DensityNode::Box { center, half_extents } => CompiledNode::Box {
+                center: to_dvec3(*center),
+                half_extents: to_dvec3(*half_extents),
+            },
+            DensityNode::Perlin { seed, frequency, amplitude } => CompiledNode::Perlin {
+                noise: Perlin::new(noise_seed(context.world_seed, *seed)),
+                frequency: *frequency as f64,
+                amplitude: *amplitude as f64,
+            },
+            DensityNode::Fbm { seed, frequency, amplitude, octaves, lacunarity, persistence } => CompiledNode::Fbm {
+                noise: Fbm::<Perlin>::new(noise_seed(context.world_seed, *seed))
+                    .set_frequency(*frequency as f64)
+                    .set_octaves(*octaves)
+                    .set_lacunarity(*lacunarity as f64)
+                    .set_persistence(*persistence as f64),
+                amplitude: *amplitude as f64,
+            },
+            DensityNode::Ridged { seed, frequency, amplitude, octaves, lacunarity } => CompiledNode::Ridged {
+                noise: RidgedMulti::<Perlin>::new(noise_seed(context.world_seed, *seed))
+                    .set_frequency(*frequency as f64)
+                    .set_octaves(*octaves)
+                    .set_lacunarity(*lacunarity as f64),
+                amplitude: *amplitude as f64,
+            },
+            DensityNode::Elevation { map, scale } => match context.elevation_maps.get(map) {
+                Some(elevation_map) => CompiledNode::Elevation {
+                    map: elevation_map.clone(),
+                    scale: *scale as f64,
+                },
+                None => return Err(format!("the elevation map {} was not loaded", map)),
+            },
+            DensityNode::Erosion { seed, tile_size, cell_size, hydraulic, thermal, input } => {
+                let address: usize = node as *const DensityNode as usize;
+                if let Some(erosion) = context.erosion.borrow().get(&address) {
+                    return Ok(CompiledNode::Erosion(erosion.clone()));
+                }
+                if *cell_size <= 0.0 {
+                    return Err("the cell size of an Erosion node must be positive".to_owned());
+                }
+                let erosion = Arc::new(ErosionNode {
+                    tiles: ErosionTiles::new(
+                        context.world_seed.derive(&format!("density_graph/erosion/{}", seed)),
+                        *tile_size,
+                        *cell_size,
+                        hydraulic.clone(),
+                        thermal.clone(),
+                    ),
+                    input: CompiledNode::compile(input, context)?,
+                });
+                context.erosion.borrow_mut().insert(address, erosion.clone());
+                CompiledNode::Erosion(erosion)
+            }
+            // Nothing is inside of the caves, which carves nothing out of the terrain.
+            DensityNode::WormCaves { .. } | DensityNode::CheeseCaves { .. } if context.without_caves => {
+                CompiledNode::Constant(f64::NEG_INFINITY)
+            }
+            DensityNode::WormCaves { seed, cell_size, worms_per_cell, deep_worms_per_cell, deep, radius, segments } => {
+                if *cell_size <= 0.0 {
+                    return Err("the cell size of a WormCaves node must be positive".to_owned());
+                }
+                CompiledNode::WormCaves(WormCaves::new(
+                    context.world_seed.derive(&format!("density_graph/worm_caves/{}", seed)),
+                    *cell_size,
+                    *worms_per_cell,
+                    *deep_worms_per_cell,
+                    *deep,
+                    *radius,
+                    *segments,
+                    context.planet,
+                ))
+            }
+            DensityNode::CheeseCaves { seed, frequency, threshold, deep_threshold, deep, amplitude } => {
+                CompiledNode::CheeseCaves(CheeseCaves::new(
+                    context.world_seed.derive_u32(&format!("density_graph/cheese_caves/{}", seed)),
+                    *frequency,
+                    *threshold,
+                    *deep_threshold,
+                    *deep,
+                    *amplitude,
+                    context.planet,
+                ))
+            }
+            DensityNode::Translate { offset, input } => CompiledNode::Translate {
+                offset: to_dvec3(*offset),
+                input: compile_box(input)?,
+            },
+            DensityNode::Scale { factor, input } => CompiledNode::Scale {
+                factor: *factor as f64,
+                input: compile_box(input)?,
+            },
+            DensityNode::DomainWarp { warp, input } => CompiledNode::DomainWarp {
+                warp: compile_box(warp)?,
+                input: compile_box(input)?,
+            },
+            DensityNode::Add(inputs) => CompiledNode::Add(compile_all(inputs)?),
+            DensityNode::Multiply(inputs) => CompiledNode::Multiply(compile_all(inputs)?),
+            DensityNode::Min(inputs) => CompiledNode::Min(compile_all(inputs)?),
+            DensityNode::Max(inputs) => CompiledNode::Max(compile_all(inputs)?),
+            DensityNode::SmoothUnion { smoothness, a, b } => CompiledNode::SmoothUnion {
+                smoothness: *smoothness as f64,
+                a: compile_box(a)?,
+                b: compile_box(b)?,
+            },
+            DensityNode::SmoothIntersection { smoothness, a, b } => CompiledNode::SmoothIntersection {
+                smoothness: *smoothness as f64,
+                a: compile_box(a)?,
+                b: compile_box(b)?,
+            },
+            DensityNode::Planet { surface } => {
+                let Some(planet) = context.planet else {
+                    return Err("the Planet node needs the graph to describe a planet".to_owned());
+                };
+                CompiledNode::Planet {
+                    planet: planet.clone(),
+                    surface: compile_box(surface)?,
+                }
+            }
+            DensityNode::Negate(input) => CompiledNode::Negate(compile_box(input)?),
+            DensityNode::Clamp { min, max, input } => CompiledNode::Clamp {
+                min: *min as f64,
+                max: *max as f64,
+                input: compile_box(input)?,
+            },
+        })
+    }
+
+    fn sample(&self, p: DVec3) -> f64 {
+        match self {
+            CompiledNode::Constant(value) => *value,
+            CompiledNode::Sphere { center, radius } => radius - p.distance(*center),
+            CompiledNode::Plane { normal, height } => height - p.dot(*normal),
+            CompiledNode::Box { center, half_extents } => {
+                let q: DVec3 = (p - *center).abs() - *half_extents;
+                let outside: f64 = q.max(DVec3::ZERO).length();
+                let inside: f64 = q.max_element().min(0.0);
+                -(outside + inside)
+            }
+            CompiledNode::Perlin { noise, frequency, amplitude } => {
+                noise.get((p * *frequency).to_array()) * amplitude
+            }
+            CompiledNode::Fbm { noise, amplitude } => noise.get(p.to_array()) * amplitude,
+            CompiledNode::Ridged { noise, amplitude } => noise.get(p.to_array()) * amplitude,
+            CompiledNode::Elevation { map, scale } => map.sample(p) * scale,
+            CompiledNode::Erosion(erosion) => {
+                erosion.tiles.sample(p.x, p.z, |x, z| erosion.input.sample(DVec3::new(x, 0.0, z)))
+            }
+            CompiledNode::WormCaves(caves) => caves.sample(p),
+            CompiledNode::CheeseCaves(caves) => caves.sample(p),
+            CompiledNode::Translate { offset, input } => input.sample(p - *offset),
+            CompiledNode::Scale { factor, input } => input.sample(p / *factor),
+            CompiledNode::DomainWarp { warp, input } => {
+                let offset = DVec3::new(
+                    warp.sample(p),
+                    warp.sample(p + WARP_OFFSET_Y),
+                    warp.sample(p + WARP_OFFSET_Z),
+                );
+                input.sample(p + offset)
+            }
+            CompiledNode::Add(inputs) => inputs.iter().map(|input| input.sample(p)).sum(),
+            CompiledNode::Multiply(inputs) => inputs.iter().map(|input| input.sample(p)).product(),
+            CompiledNode::Min(inputs) => inputs.iter().map(|input| input.sample(p)).fold(f64::INFINITY, f64::min),
+            CompiledNode::Max(inputs) => inputs
+                .iter()
+                .map(|input| input.sample(p))
+                .fold(f64::NEG_INFINITY, f64::max),
+            CompiledNode::SmoothUnion { smoothness, a, b } => smooth_max(a.sample(p), b.sample(p), *smoothness),
+            CompiledNode::SmoothIntersection { smoothness, a, b } => {
+                -smooth_max(-a.sample(p), -b.sample(p), *smoothness)
+            }
+            CompiledNode::Negate(input) => -input.sample(p),
+            CompiledNode::Clamp { min, max, input } => input.sample(p).clamp(*min, *max),
+            CompiledNode::Planet { planet, surface } => {
+                planet.radius() as f64 + surface.sample(planet.surface_point(p)) - p.length()
+            }
+        }
+    }
+}
+
+// The polynomial smooth maximum, equal to the maximum when the values are further apart than the smoothness.
+fn smooth_max(a: f64, b: f64, smoothness: f64) -> f64 {
+    if smoothness <= 0.0 {
+        return a.max(b);
+    }
+    let h: f64 = (smoothness - (a - b).abs()).max(0.0) / smoothness;
+    a.max(b) + h * h * smoothness / 4.0
+}
+
+/// A density graph compiled for sampling, it can be shared with the meshing tasks.
+pub struct DensityField {
+    root: CompiledNode,
+    // The graph without its caves.
+    surface: CompiledNode,
+    pub strata: Strata,
+    // The planet the graph describes, centered on the world origin.
+    planet: Option<Planetoid>,
+    sea: bool,
+}
+
+impl DensityField {
+    pub fn compile(graph: &DensityGraph, world_seed: &WorldSeed) -> Result<Self, String> {
+        let erosion = RefCell::new(HashMap::new());
+        let context = CompileContext {
+            world_seed,
+            planet: graph.planet.as_ref(),
+            elevation_maps: &graph.elevation_maps,
+            without_caves: false,
+            erosion: &erosion,
+        };
+        let surface_context = CompileContext {
+            without_caves: true,
+            ..context
+        };
+        Ok(Self {
+            root: CompiledNode::compile(&graph.root, &context)?,
+            surface: CompiledNode::compile(&graph.root, &surface_context)?,
+            strata: Strata::new(world_seed, graph.planet.as_ref(), graph.strata.clone()),
+            planet: graph.planet.clone(),
+            sea: graph.sea,
+        })
+    }
+
+    pub fn sample(&self, position: DVec3) -> f64 {
+        self.root.sample(position)
+    }
+
+    /// How far the position is below the surface in world units, negative above it. The surface is the procedural
+    /// surface without the caves, and its density is divided by its gradient, as graphs can grow the density faster or
+    /// slower than the distance.
+    pub fn depth_at(&self, position: DVec3) -> f64 {
+        let gradient = DVec3::new(
+            self.surface.sample(position + DVec3::X) - self.surface.sample(position - DVec3::X),
+            self.surface.sample(position + DVec3::Y) - self.surface.sample(position - DVec3::Y),
+            self.surface.sample(position + DVec3::Z) - self.surface.sample(position - DVec3::Z),
+        ) / 2.0;
+        self.surface.sample(position) / gradient.length().max(1.0e-3)
+    }
+
+    /// The height of the procedural surface without the caves along the column through the base, searched downwards
+    /// from the top to the bottom in steps and refined by bisection. The height is the top when the whole column is
+    /// solid and the bottom when it is all air.
+    pub fn surface_height(&self, base: DVec3, up: DVec3, bottom: f64, top: f64, step: f64) -> f64 {
+        let density = |height: f64| self.surface.sample(base + up * height);
+        if density(top) > 0.0 {
+            return top;
+        }
+        let mut above: f64 = top;
+        while above > bottom {
+            let below: f64 = (above - step.max(1.0e-3)).max(bottom);
+            if density(below) > 0.0 {
+                let (mut solid, mut air) = (below, above);
+                for _ in 0..SURFACE_BISECTIONS {
+                    let middle: f64 = (solid + air) / 2.0;
+                    if density(middle) > 0.0 {
+                        solid = middle;
+                    } else {
+                        air = middle;
+                    }
+                }
+                return (solid + air) / 2.0;
+            }
+            above = below;
+        }
+        bottom
+    }
+
+    /// The material at the absolute position from its density including the edits. The depth is measured from the
+    /// procedural surface, so digging uncovers the layers below it and the walls of caves are rock, and terrain built
+    /// above that surface is soil.
+    pub fn material_for(&self, position: DVec3, density: f64) -> VoxelMaterial {
+        if density <= 0.0 {
+            return VoxelMaterial::Air;
+        }
+        let depth: f64 = self.depth_at(position);
+        if depth <= 0.0 {
+            return VoxelMaterial::Soil;
+        }
+        self.strata.material_at(position, depth)
+    }
+
+    /// Whether the graph is flooded up to sea level.
+    pub fn has_sea(&self) -> bool {
+        self.sea
+    }
+
+    pub fn planet(&self) -> Option<&Planetoid> {
+        self.planet.as_ref()
+    }
+
+    /// The direction away from the ground at the absolute position.
+    pub fn up(&self, position: Vec3) -> Vec3 {
+        if self.planet.is_some() {
+            position.normalize_or(Vec3::Y)
+        } else {
+            Vec3::Y
+        }
+    }
+
+    /// Wraps the field so transvoxel can sample it, at positions relative to the absolute base.
+    pub fn sampler(&self, base: DVec3) -> DensitySampler<'_> {
+        DensitySampler { field: self, base }
+    }
+}
+
+pub struct DensitySampler<'a> {
+    field: &'a DensityField,
+    base: DVec3,
+}
+
+impl DataField<f32, f32> for DensitySampler<'_> {
+    fn get_data(&mut self, x: f32, y: f32, z: f32) -> f32 {
+        self.field.sample(self.base + DVec3::new(x as f64, y as f64, z as f64)) as f32
+    }
+}
+
+/// The density graph the terrain is generated from, the field is None until the graph has loaded.
+#[derive(Resource)]
+pub struct TerrainDensity {
+    handle: Handle<DensityGraph>,
+    pub field: Option<Arc<DensityField>>,
+}
+
+impl TerrainDensity {
+    /// The material of the terrain at the absolute position including the edits, None until the graph has loaded.
+    pub fn material_at(&self, terrain: &TerrainData, position: DVec3) -> Option<VoxelMaterial> {
+        let field = self.field.as_ref()?;
+        Some(terrain.material_at(field, position))
+    }
+}
+
+pub fn load_density_graph(mut commands: Commands, asset_server: Res<AssetServer>, config: Res<TerrainConfig>) {
+    commands.insert_resource(TerrainDensity {
+        handle: asset_server.load(config.density_graph.clone()),
+        field: None,
+    });
+}
+
+/// Compiles the density graph once it has loaded and again whenever the file changes, remeshing every loaded chunk.
+/// The [`Planetoid`] resource is replaced by the planet of the graph, gravity is pointed at its center, and the chunks
+/// are selected again as the planet decides which chunks are loaded. The biomes use the baked climate of the planet.
+pub fn update_density_field(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<DensityGraph>>,
+    graphs: Res<Assets<DensityGraph>>,
+    mut density: ResMut<TerrainDensity>,
+    terrain: Res<TerrainData>,
+    mut mesh_queue: ResMut<ChunkMeshQueue>,
+    mut tracked_pos: ResMut<LODPostionTracker>,
+    world_seed: Res<WorldSeed>,
+    origin: Res<FloatingOrigin>,
+    mut biomes: ResMut<TerrainBiomes>,
+) {
+    for event in events.read() {
+        if !event.is_loaded_with_dependencies(&density.handle) && !event.is_modified(&density.handle) {
+            continue;
+        }
+        let Some(graph) = graphs.get(&density.handle) else {
+            continue;
+        };
+        let field = match DensityField::compile(graph, &world_seed) {
+            Ok(field) => field,
+            Err(error) => {
+                warn!("Failed to compile the terrain density graph, keeping the previous one: {}", error);
+                continue;
+            }
+        };
+        density.field = Some(Arc::new(field));
+        biomes.climate = graph.climate.clone();
+        match graph.planet.as_ref() {
+            Some(planet) => {
+                info!("Generating the planet {}, {} units across", planet.name, planet.diameter);
+                commands.insert_resource(planet.clone());
+                commands.insert_resource(GravityField::Radial {
+                    center: origin.cell.relative(DVec3::ZERO),
+                    strength: planet.surface_gravity,
+                });
+            }
+            None => {
+                commands.remove_resource::<Planetoid>();
+                commands.insert_resource(GravityField::default());
+            }
+        }
+        tracked_pos.set_changed();
+        let chunks = terrain.chunk_keys();
+        for key in chunks.iter() {
+            mesh_queue.request(*key);
+        }
+        info!("Compiled the terrain density graph, remeshing {} chunk(s)", chunks.len());
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use bevy::{
+    input::{gamepad::Gamepad, ButtonInput},
+    log::{info, warn},
+    math::{DVec3, IVec3, Vec3},
+    prelude::{Entity, Event, EventReader, EventWriter, GlobalTransform, KeyCode, Query, Res, ResMut, Resource, With},
+};
+
+use crate::{camera::GameCamera, config::Bindings, origin::FloatingOrigin, utils::format_value_vec3};
+
+use super::{
+    config::TerrainConfig,
+    density_graph::{DensityField, TerrainDensity},
+    lod::ChunkKey,
+    mesh_queue::ChunkMeshQueue,
+    octree::Octree,
+    planet::Planetoid,
+    region::RegionStore,
+    is_within_world, TerrainData, Voxel, CHUNK_SIZE_I32,
+};
+
+// Edits are clamped so repeated brush strokes can not grow the density without bound.
+const MAX_DENSITY_DELTA: f32 = 64.0;
+// Voxels whose delta falls below this are removed from the octree to keep it sparse.
+const MIN_DENSITY_DELTA: f32 = 0.001;
+// The rate at which the density of the terrain grows with depth, the flatten brush uses it to build its target plane.
+const FLATTEN_DENSITY_GRADIENT: f32 = 2.0;
+// The smooth and flatten brushes move straight to their target at this strength, weaker strokes only part of the way.
+const FULL_BLEND_STRENGTH: f32 = 8.0;
+// Meshing samples the density field up to this many voxels outside of a chunk to compute normals,
+// so chunks this close to an edit are remeshed as well.
+const EDIT_MESH_MARGIN: i32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BrushKind {
+    Sphere,
+    Cube,
+    // Moves the density towards the average of the neighbouring voxels.
+    Smooth,
+    // Moves the density towards the plane through the center of the brush facing up, which is level on a planet too.
+    Flatten,
+}
+
+impl BrushKind {
+    pub fn next(&self) -> BrushKind {
+        match self {
+            BrushKind::Sphere => BrushKind::Cube,
+            BrushKind::Cube => BrushKind::Smooth,
+            BrushKind::Smooth => BrushKind::Flatten,
+            BrushKind::Flatten => BrushKind::Sphere,
+        }
+    }
+}
+
+/// The brush used when the player digs or builds.
+#[derive(Resource)]
+pub struct TerrainBrush {
+    pub kind: BrushKind,
+    pub radius: f32,
+    // The density added at the center of the brush for each stroke, the smooth and flatten brushes use it as a blend factor.
+    pub strength: f32,
+}
+
+impl Default for TerrainBrush {
+    fn default() -> Self {
+        Self {
+            kind: BrushKind::Sphere,
+            radius: 3.0,
+            strength: 4.0,
+        }
+    }
+}
+
+/// A single brush stroke, a negative strength removes density and a positive strength adds it.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct TerrainEdit {
+    // The absolute world position, not relative to the floating origin. Kept in f64 so brushes far from the world
+    // origin land on the voxel they were aimed at.
+    pub center: DVec3,
+    pub kind: BrushKind,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+/// Samples the density added by edits at an absolute position, interpolating between the eight surrounding voxels.
+/// The fraction within the voxel is taken in f64 before it is narrowed, so it stays exact far from the world origin.
+/// There are no edits outside the bound of the world.
+pub fn sample_density_delta(voxels: &Octree<Voxel>, position: DVec3) -> f32 {
+    if !is_within_world(position) {
+        return 0.0;
+    }
+    let base: DVec3 = position.floor();
+    let t: Vec3 = (position - base).as_vec3();
+    let base: IVec3 = base.as_ivec3();
+    let mut delta: f32 = 0.0;
+    for i in 0..8 {
+        let offset = IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        let Some(voxel) = voxels.get(base + offset) else {
+            continue;
+        };
+        let weight: f32 = (if offset.x == 1 { t.x } else { 1.0 - t.x })
+            * (if offset.y == 1 { t.y } else { 1.0 - t.y })
+            * (if offset.z == 1 { t.z } else { 1.0 - t.z });
+        delta += weight * voxel.density;
+    }
+    delta
+}
+
+/// Returns true when any edited voxel can affect the mesh of the chunk. Loaded chunks are within the bound of the
+/// world, so their corner fits an i32.
+pub fn is_chunk_edited(voxels: &Octree<Voxel>, key: &ChunkKey) -> bool {
+    let min: IVec3 = key.base().as_ivec3() - IVec3::splat(EDIT_MESH_MARGIN);
+    let max: IVec3 = min + IVec3::splat(key.size() as i32 + 2 * EDIT_MESH_MARGIN + 1);
+    !voxels.query_aabb(min, max).is_empty()
+}
+
+/// Casts a ray from the camera through the terrain density and sends an edit where it hits, so chunks can be edited
+/// before their collider is built and props do not block the brush.
+/// The interact binding digs, the build binding builds and the cycle binding switches to the next brush kind.
+pub fn read_terrain_edit_input(
+    mut edits: EventWriter<TerrainEdit>,
+    mut brush: ResMut<TerrainBrush>,
+    terrain: Res<TerrainData>,
+    density: Res<TerrainDensity>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+    gamepad_query: Query<(Entity, &Gamepad)>,
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<Bindings>,
+    config: Res<TerrainConfig>,
+    origin: Res<FloatingOrigin>,
+) {
+    if keys.just_pressed(bindings.action_cycle_brush) {
+        brush.kind = brush.kind.next();
+        info!("Terrain brush set to {:?}", brush.kind);
+    }
+
+    let mut dig: bool = keys.just_pressed(bindings.action_interact.key);
+    let mut build: bool = keys.just_pressed(bindings.action_build.key);
+    if let Ok((_entity, gamepad)) = gamepad_query.single() {
+        dig |= gamepad.just_pressed(bindings.action_interact.button);
+        build |= gamepad.just_pressed(bindings.action_build.button);
+    }
+    if dig == build {
+        return;
+    }
+
+    let Ok(camera_transform) = camera_query.single() else {
+        return;
+    };
+    let Some(field) = density.field.as_ref() else {
+        return;
+    };
+    // The camera is relative to the floating origin, the terrain is queried at absolute positions.
+    let Some(hit) = terrain.raycast(
+        field,
+        origin.cell.absolute(camera_transform.translation()),
+        camera_transform.forward().as_vec3(),
+        config.edit_reach,
+    ) else {
+        return;
+    };
+
+    edits.write(TerrainEdit {
+        center: hit.position,
+        kind: brush.kind,
+        radius: brush.radius,
+        strength: if dig { -brush.strength } else { brush.strength },
+    });
+}
+
+/// Applies the brush strokes to the edited voxels and remeshes every loaded chunk the strokes can affect.
+/// The regions containing the changed voxels are marked to be saved.
+pub fn apply_terrain_edits(
+    mut edits: EventReader<TerrainEdit>,
+    mut terrain: ResMut<TerrainData>,
+    mut mesh_queue: ResMut<ChunkMeshQueue>,
+    mut regions: ResMut<RegionStore>,
+    density: Res<TerrainDensity>,
+    planet: Option<Res<Planetoid>>,
+) {
+    if edits.is_empty() {
+        return;
+    }
+    // Without a density field there is no terrain to edit yet.
+    let Some(field) = density.field.clone() else {
+        edits.clear();
+        return;
+    };
+
+    for edit in edits.read() {
+        if !is_within_world(edit.center) {
+            warn!("Ignored a {:?} brush outside of the bound of the world", edit.kind);
+            continue;
+        }
+        // Up points away from the center of a planet, which is on the world origin.
+        let up: Vec3 = if planet.is_some() { edit.center.normalize_or(DVec3::Y).as_vec3() } else { Vec3::Y };
+        let reach: i32 = edit.radius.ceil() as i32 + 1;
+        let min: IVec3 = edit.center.round().as_ivec3() - IVec3::splat(reach);
+        let max: IVec3 = edit.center.round().as_ivec3() + IVec3::splat(reach);
+        // A brush near the edge of the loaded area can reach into a region whose saved edits are not loaded yet.
+        regions.load_range(min, max, &mut terrain.voxels);
+
+        // The new deltas are computed before any is written so the smooth brush reads the field before the stroke.
+        let mut changes: Vec<(IVec3, f32)> = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let position = IVec3::new(x, y, z);
+                    let delta: f32 = terrain.density_delta(position);
+                    let new_delta: f32 = brush_delta(edit, up, position, delta, &field, &terrain);
+                    if new_delta != delta {
+                        changes.push((position, new_delta.clamp(-MAX_DENSITY_DELTA, MAX_DENSITY_DELTA)));
+                    }
+                }
+            }
+        }
+
+        // Running meshing tasks keep their snapshot of the voxels, the octree is only copied when one is still alive.
+        let voxels: &mut Octree<Voxel> = Arc::make_mut(&mut terrain.voxels);
+        for (position, density) in changes.iter() {
+            if density.abs() < MIN_DENSITY_DELTA {
+                voxels.remove(*position);
+            } else {
+                voxels.insert(*position, Voxel { density: *density });
+            }
+        }
+        regions.mark_dirty(min, max);
+
+        // Remesh every loaded chunk whose samples can read one of the changed voxels, of any level of detail.
+        let chunk_min: IVec3 = (min - IVec3::splat(EDIT_MESH_MARGIN)).div_euclid(IVec3::splat(CHUNK_SIZE_I32));
+        let chunk_max: IVec3 =
+            (max + IVec3::splat(EDIT_MESH_MARGIN)).div_euclid(IVec3::splat(CHUNK_SIZE_I32)) + IVec3::ONE;
+        let affected: Vec<ChunkKey> = terrain
+            .chunks
+            .query_aabb(chunk_min, chunk_max)
+            .iter()
+            .map(|leaf| ChunkKey::from_octree(leaf.coord(), leaf.level))
+            .collect();
+        for key in affected.iter() {
+            mesh_queue.request(*key);
+        }
+
+        info!(
+            "Applied a {:?} brush at {} to {} voxel(s), remeshing {} chunk(s)",
+            edit.kind,
+            format_value_vec3(edit.center.as_vec3(), None, true),
+            changes.len(),
+            affected.len()
+        );
+    }
+}
+
+// The density delta of the voxel after the stroke.
+fn brush_delta(
+    edit: &TerrainEdit,
+    up: Vec3,
+    position: IVec3,
+    delta: f32,
+    field: &DensityField,
+    terrain: &TerrainData,
+) -> f32 {
+    let offset: Vec3 = (position.as_dvec3() - edit.center).as_vec3();
+    // The edge of the brush is blended over one voxel so the surface moves smoothly between voxels.
+    let falloff: f32 = match edit.kind {
+        BrushKind::Cube => (edit.radius - offset.abs().max_element()).clamp(0.0, 1.0),
+        _ => (edit.radius - offset.length()).clamp(0.0, 1.0),
+    };
+    if falloff <= 0.0 {
+        return delta;
+    }
+
+    let density = |position: IVec3| -> f32 {
+        field.sample(position.as_dvec3()) as f32 + terrain.density_delta(position)
+    };
+    let blend: f32 = (edit.strength.abs() / FULL_BLEND_STRENGTH).min(1.0) * falloff;
+    match edit.kind {
+        BrushKind::Sphere | BrushKind::Cube => delta + edit.strength * falloff,
+        BrushKind::Smooth => {
+            let current: f32 = density(position);
+            let neighbours: f32 = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z]
+                .iter()
+                .map(|direction| density(position + *direction))
+                .sum::<f32>()
+                / 6.0;
+            delta + (neighbours - current) * blend
+        }
+        BrushKind::Flatten => {
+            let current: f32 = density(position);
+            // The height of the voxel above the plane is measured along up rather than along world Y.
+            let target: f32 = -offset.dot(up) * FLATTEN_DENSITY_GRADIENT;
+            delta + (target - current) * blend
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use std::sync::Arc;
+
+use bevy::math::{DVec2, IVec2, Vec2};
+use serde::Deserialize;
+
+use crate::seed::{splitmix64, SplitMix64};
+
+use super::{bake::ElevationMap, cache::BoundedCache};
+
+/// A grid of heights, either a baked elevation map or the height of the terrain around a chunk.
+///
+/// The heights and the cell size are in the same unit, so slopes are the height difference over the cell size.
+/// A baked map wraps around the planet along x, the rows of a local grid end at its edges.
+pub struct Heightfield {
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: f32,
+    pub heights: Vec<f32>,
+    pub wrap_x: bool,
+}
+
+impl Heightfield {
+    pub fn from_fn(width: usize, height: usize, cell_size: f32, mut f: impl FnMut(usize, usize) -> f32) -> Self {
+        let mut heights: Vec<f32> = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                heights.push(f(x, y));
+            }
+        }
+        Self {
+            width,
+            height,
+            cell_size,
+            heights,
+            wrap_x: false,
+        }
+    }
+
+    /// A heightfield of a baked map, the cells are as wide as the pixels at the equator.
+    pub fn from_elevation_map(map: ElevationMap, radius: f32) -> Self {
+        Self {
+            width: map.width as usize,
+            height: map.height as usize,
+            cell_size: 2.0 * std::f32::consts::PI * radius / map.width as f32,
+            heights: map.elevations,
+            wrap_x: true,
+        }
+    }
+
+    pub fn into_elevation_map(self) -> ElevationMap {
+        ElevationMap {
+            width: self.width as u32,
+            height: self.height as u32,
+            elevations: self.heights,
+        }
+    }
+
+    // The index of the cell, wrapping or clamping x and clamping y.
+    fn index(&self, x: i64, y: i64) -> usize {
+        let x: i64 = if self.wrap_x {
+            x.rem_euclid(self.width as i64)
+        } else {
+            x.clamp(0, self.width as i64 - 1)
+        };
+        let y: i64 = y.clamp(0, self.height as i64 - 1);
+        y as usize * self.width + x as usize
+    }
+
+    pub fn get(&self, x: i64, y: i64) -> f32 {
+        self.heights[self.index(x, y)]
+    }
+
+    /// The bilinearly interpolated height at a position in cells.
+    pub fn sample(&self, position: Vec2) -> f32 {
+        self.height_and_gradient(position).0
+    }
+
+    // The height and the gradient in height per cell at a position in cells.
+    fn height_and_gradient(&self, position: Vec2) -> (f32, Vec2) {
+        let (x, y) = (position.x.floor() as i64, position.y.floor() as i64);
+        let (tx, ty) = (position.x - x as f32, position.y - y as f32);
+        let (h00, h10) = (self.get(x, y), self.get(x + 1, y));
+        let (h01, h11) = (self.get(x, y + 1), self.get(x + 1, y + 1));
+        let height: f32 = (h00 * (1.0 - tx) + h10 * tx) * (1.0 - ty) + (h01 * (1.0 - tx) + h11 * tx) * ty;
+        let gradient = Vec2::new(
+            (h10 - h00) * (1.0 - ty) + (h11 - h01) * ty,
+            (h01 - h00) * (1.0 - tx) + (h11 - h10) * tx,
+        );
+        (height, gradient)
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        (self.wrap_x || (position.x >= 0.0 && position.x < (self.width - 1) as f32))
+            && position.y >= 0.0
+            && position.y < (self.height - 1) as f32
+    }
+
+    fn wrapped(&self, mut position: Vec2) -> Vec2 {
+        if self.wrap_x {
+            position.x = position.x.rem_euclid(self.width as f32);
+        }
+        position
+    }
+}
+
+/// Droplets of water run down the heightfield, picking up sediment where they speed up
+/// and depositing it where they slow down, which carves valleys and fills basins.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HydraulicErosionSettings {
+    // The number of droplets.
+    pub iterations: u32,
+    // The most cells a droplet travels before it evaporates.
+    pub max_lifetime: u32,
+    // How much a droplet keeps its direction instead of following the slope, from 0 to 1.
+    pub inertia: f32,
+    // How much sediment a droplet can carry relative to its speed, water and the slope.
+    pub sediment_capacity: f32,
+    // Keeps droplets on flat ground eroding a little, in cells.
+    pub min_sediment_capacity: f32,
+    // The fraction of the missing capacity a droplet erodes each cell.
+    pub erode_speed: f32,
+    // The fraction of the excess sediment a droplet deposits each cell.
+    pub deposit_speed: f32,
+    // The fraction of the water which evaporates each cell.
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+    // The radius in cells of the area a droplet erodes, larger radii give smoother valleys.
+    pub radius: u32,
+}
+
+impl Default for HydraulicErosionSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 50_000,
+            max_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.01,
+            gravity: 4.0,
+            radius: 3,
+        }
+    }
+}
+
+/// Material slides down every slope steeper than the talus angle until the slope rests at it, which forms scree.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ThermalErosionSettings {
+    pub iterations: u32,
+    // The steepest slope loose material rests on, in degrees.
+    pub talus_angle: f32,
+    // The fraction of the excess material moved each iteration, from 0 to 1.
+    pub strength: f32,
+}
+
+impl Default for ThermalErosionSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 20,
+            talus_angle: 35.0,
+            strength: 0.5,
+        }
+    }
+}
+
+/// Runs the hydraulic erosion, the same seed and settings always erode the same way.
+pub fn erode_hydraulic(field: &mut Heightfield, settings: &HydraulicErosionSettings, seed: u64) {
+    if field.width < 2 || field.height < 2 {
+        return;
+    }
+    // The droplets work in cells so the settings mean the same on a baked map as on a local grid.
+    let scale: f32 = 1.0 / field.cell_size;
+    field.heights.iter_mut().for_each(|height| *height *= scale);
+
+    let brush: Vec<(i64, i64, f32)> = erosion_brush(settings.radius as i64);
+    let mut rng = SplitMix64::new(seed);
+    for _ in 0..settings.iterations {
+        let mut position = Vec2::new(
+            rng.range_f32(0.0, (field.width - 1) as f32),
+            rng.range_f32(0.0, (field.height - 1) as f32),
+        );
+        let mut direction: Vec2 = Vec2::ZERO;
+        let mut speed: f32 = 1.0;
+        let mut water: f32 = 1.0;
+        let mut sediment: f32 = 0.0;
+
+        for _ in 0..settings.max_lifetime {
+            let (height, gradient) = field.height_and_gradient(position);
+            direction = direction * settings.inertia - gradient * (1.0 - settings.inertia);
+            let Some(normalized) = direction.try_normalize() else {
+                break;
+            };
+            direction = normalized;
+            let previous: Vec2 = position;
+            position = field.wrapped(position + direction);
+            if !field.contains(position) {
+                break;
+            }
+
+            let delta: f32 = field.sample(position) - height;
+            let capacity: f32 =
+                (-delta * speed * water * settings.sediment_capacity).max(settings.min_sediment_capacity);
+            if sediment > capacity || delta > 0.0 {
+                // Uphill the droplet fills the pit behind it, otherwise it drops what it can no longer carry.
+                let deposit: f32 = if delta > 0.0 {
+                    delta.min(sediment)
+                } else {
+                    (sediment - capacity) * settings.deposit_speed
+                };
+                sediment -= deposit;
+                deposit_bilinear(field, previous, deposit);
+            } else {
+                let amount: f32 = ((capacity - sediment) * settings.erode_speed).min(-delta);
+                sediment += erode_brush(field, previous, amount, &brush);
+            }
+
+            speed = (speed * speed - delta * settings.gravity).max(0.0).sqrt();
+            water *= 1.0 - settings.evaporate_speed;
+        }
+    }
+
+    field.heights.iter_mut().for_each(|height| *height /= scale);
+}
+
+// The cells within the radius with weights falling off with the distance, summing to one.
+fn erosion_brush(radius: i64) -> Vec<(i64, i64, f32)> {
+    let radius: i64 = radius.max(1);
+    let mut brush: Vec<(i64, i64, f32)> = Vec::new();
+    for y in -radius..=radius {
+        for x in -radius..=radius {
+            let distance: f32 = ((x * x + y * y) as f32).sqrt();
+            if distance < radius as f32 {
+                brush.push((x, y, 1.0 - distance / radius as f32));
+            }
+        }
+    }
+    let total: f32 = brush.iter().map(|(_, _, weight)| weight).sum();
+    brush.iter_mut().for_each(|(_, _, weight)| *weight /= total);
+    brush
+}
+
+fn deposit_bilinear(field: &mut Heightfield, position: Vec2, amount: f32) {
+    let (x, y) = (position.x.floor() as i64, position.y.floor() as i64);
+    let (tx, ty) = (position.x - x as f32, position.y - y as f32);
+    for (dx, dy, weight) in [
+        (0, 0, (1.0 - tx) * (1.0 - ty)),
+        (1, 0, tx * (1.0 - ty)),
+        (0, 1, (1.0 - tx) * ty),
+        (1, 1, tx * ty),
+    ] {
+        let index: usize = field.index(x + dx, y + dy);
+        field.heights[index] += amount * weight;
+    }
+}
+
+// Removes the amount spread over the cells around the position, returns how much was removed.
+// The amount is at most the drop to the next cell, so the droplet never digs a pit it would flow back into.
+fn erode_brush(field: &mut Heightfield, position: Vec2, amount: f32, brush: &[(i64, i64, f32)]) -> f32 {
+    let (x, y) = (position.x.round() as i64, position.y.round() as i64);
+    let mut removed: f32 = 0.0;
+    for (dx, dy, weight) in brush {
+        let (cell_x, cell_y) = (x + dx, y + dy);
+        if !field.wrap_x && (cell_x < 0 || cell_x >= field.width as i64) {
+            continue;
+        }
+        if cell_y < 0 || cell_y >= field.height as i64 {
+            continue;
+        }
+        let index: usize = field.index(cell_x, cell_y);
+        let eroded: f32 = amount * weight;
+        field.heights[index] -= eroded;
+        removed += eroded;
+    }
+    removed
+}
+
+/// Runs the thermal erosion, moving material from each cell to its lower neighbours in proportion
+/// to how far they are below the talus slope.
+pub fn erode_thermal(field: &mut Heightfield, settings: &ThermalErosionSettings) {
+    let talus: f32 = settings.talus_angle.to_radians().tan() * field.cell_size;
+    let neighbours: [(i64, i64, f32); 8] = [
+        (-1, -1, std::f32::consts::SQRT_2),
+        (0, -1, 1.0),
+        (1, -1, std::f32::consts::SQRT_2),
+        (-1, 0, 1.0),
+        (1, 0, 1.0),
+        (-1, 1, std::f32::consts::SQRT_2),
+        (0, 1, 1.0),
+        (1, 1, std::f32::consts::SQRT_2),
+    ];
+    let mut changes: Vec<f32> = vec![0.0; field.heights.len()];
+    for _ in 0..settings.iterations {
+        changes.iter_mut().for_each(|change| *change = 0.0);
+        for y in 0..field.height as i64 {
+            for x in 0..field.width as i64 {
+                let height: f32 = field.get(x, y);
+                let mut excess: [f32; 8] = [0.0; 8];
+                let mut total: f32 = 0.0;
+                let mut steepest: f32 = 0.0;
+                for (i, (dx, dy, distance)) in neighbours.iter().enumerate() {
+                    let (neighbour_x, neighbour_y) = (x + dx, y + dy);
+                    if neighbour_y < 0 || neighbour_y >= field.height as i64 {
+                        continue;
+                    }
+                    if !field.wrap_x && (neighbour_x < 0 || neighbour_x >= field.width as i64) {
+                        continue;
+                    }
+                    let difference: f32 = height - field.get(neighbour_x, neighbour_y) - talus * distance;
+                    if difference > 0.0 {
+                        excess[i] = difference;
+                        total += difference;
+                        steepest = steepest.max(difference);
+                    }
+                }
+                if total <= 0.0 {
+                    continue;
+                }
+                // Moving half of the steepest excess levels that slope to the talus angle.
+                let moved: f32 = settings.strength * steepest / 2.0;
+                changes[field.index(x, y)] -= moved;
+                for (i, (dx, dy, _)) in neighbours.iter().enumerate() {
+                    if excess[i] > 0.0 {
+                        changes[field.index(x + dx, y + dy)] += moved * excess[i] / total;
+                    }
+                }
+            }
+        }
+        field
+            .heights
+            .iter_mut()
+            .zip(changes.iter())
+            .for_each(|(height, change)| *height += change);
+    }
+}
+
+// The most recently sampled tiles are kept up to this many, which covers the tiles under the chunks being meshed.
+const MAX_CACHED_TILES: usize = 256;
+
+/// Erodes a height function in overlapping square tiles on the XZ plane, built the first time they are sampled.
+///
+/// Tiles are `2 * tile_size` across and spaced `tile_size` apart, so every point is covered by four tiles whose
+/// results are blended with weights falling off towards their edges. Erosion is not local, a droplet near one point
+/// changes the heights around it, so the blend is what keeps neighbouring tiles from having seams. Each tile is
+/// seeded from its coordinate, so the erosion is the same however the terrain is loaded.
+pub struct ErosionTiles {
+    pub seed: u64,
+    pub tile_size: f32,
+    pub cell_size: f32,
+    pub hydraulic: Option<HydraulicErosionSettings>,
+    pub thermal: Option<ThermalErosionSettings>,
+    tiles: BoundedCache<IVec2, Arc<Heightfield>>,
+}
+
+impl ErosionTiles {
+    pub fn new(
+        seed: u64,
+        tile_size: f32,
+        cell_size: f32,
+        hydraulic: Option<HydraulicErosionSettings>,
+        thermal: Option<ThermalErosionSettings>,
+    ) -> Self {
+        Self {
+            seed,
+            tile_size: tile_size.max(cell_size * 4.0),
+            cell_size,
+            hydraulic,
+            thermal,
+            tiles: BoundedCache::new(MAX_CACHED_TILES),
+        }
+    }
+
+    /// The eroded height at the position, `height` gives the height before erosion at a position on the XZ plane.
+    pub fn sample(&self, x: f64, z: f64, height: impl Fn(f64, f64) -> f64) -> f64 {
+        let stride: f64 = self.tile_size as f64;
+        let (tile_x, tile_z) = ((x / stride).floor(), (z / stride).floor());
+        let (fx, fz) = (x / stride - tile_x, z / stride - tile_z);
+
+        let mut total: f64 = 0.0;
+        for (dx, dz, weight) in [
+            (0, 0, (1.0 - fx) * (1.0 - fz)),
+            (1, 0, fx * (1.0 - fz)),
+            (0, 1, (1.0 - fx) * fz),
+            (1, 1, fx * fz),
+        ] {
+            if weight <= 0.0 {
+                continue;
+            }
+            let coordinate = IVec2::new(tile_x as i32 + dx, tile_z as i32 + dz);
+            let tile: Arc<Heightfield> = self.tile(coordinate, &height);
+            let origin: DVec2 = self.tile_origin(coordinate);
+            let position: Vec2 = ((DVec2::new(x, z) - origin) / self.cell_size as f64).as_vec2();
+            total += tile.sample(position) as f64 * weight;
+        }
+        total
+    }
+
+    // The minimum corner of the tile, including the margin.
+    fn tile_origin(&self, coordinate: IVec2) -> DVec2 {
+        coordinate.as_dvec2() * self.tile_size as f64 - DVec2::splat((self.tile_size + self.margin()) as f64)
+    }
+
+    // Droplets starting outside of the tile still carve into it, the margin gives them room to start.
+    fn margin(&self) -> f32 {
+        self.tile_size / 4.0
+    }
+
+    fn tile(&self, coordinate: IVec2, height: &impl Fn(f64, f64) -> f64) -> Arc<Heightfield> {
+        self.tiles.get_or_insert_with(coordinate, || Arc::new(self.erode_tile(coordinate, height)))
+    }
+
+    fn erode_tile(&self, coordinate: IVec2, height: &impl Fn(f64, f64) -> f64) -> Heightfield {
+        let origin: DVec2 = self.tile_origin(coordinate);
+        let cell_size: f64 = self.cell_size as f64;
+        let cells: usize = ((2.0 * (self.tile_size + self.margin())) / self.cell_size).ceil() as usize + 1;
+        let mut field = Heightfield::from_fn(cells, cells, self.cell_size, |x, z| {
+            height(origin.x + x as f64 * cell_size, origin.y + z as f64 * cell_size) as f32
+        });
+        if let Some(hydraulic) = self.hydraulic.as_ref() {
+            let packed: u64 = ((coordinate.x as u32 as u64) << 32) | coordinate.y as u32 as u64;
+            erode_hydraulic(&mut field, hydraulic, splitmix64(self.seed ^ packed));
+        }
+        if let Some(thermal) = self.thermal.as_ref() {
+            erode_thermal(&mut field, thermal);
+        }
+        field
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use bevy::math::Vec3;
+
+/// A unit sphere triangulated by repeatedly subdividing an icosahedron.
+///
+/// Every vertex has five or six neighbours and the triangles are close to equal in area,
+/// which is what lets the simulation treat each vertex as an equal piece of the surface.
+pub struct Icosphere {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+    // The vertices sharing an edge with each vertex.
+    pub neighbours: Vec<Vec<u32>>,
+    pub subdivisions: u32,
+    // The vertex nearest to the center of each cell of a grid over the faces of the cube around the sphere, the walk
+    // to the vertex nearest a point starts from the one of its cell so it only takes a step or two.
+    cube_starts: Vec<u32>,
+    cube_resolution: usize,
+}
+
+// The cells along an edge of a face of the cube per vertex along an edge of the icosahedron, more than one so a cell
+// is smaller than the spacing of the vertices.
+const CUBE_CELLS_PER_EDGE_VERTEX: usize = 2;
+
+// The cube face and the cell on it a direction points through, as the index into the grid.
+fn cube_cell(point: Vec3, resolution: usize) -> usize {
+    let axis: usize = if point.x.abs() >= point.y.abs() && point.x.abs() >= point.z.abs() {
+        0
+    } else if point.y.abs() >= point.z.abs() {
+        1
+    } else {
+        2
+    };
+    let major: f32 = point[axis];
+    let face: usize = axis * 2 + usize::from(major < 0.0);
+    let cell = |coordinate: f32| -> usize {
+        let t: f32 = (coordinate / major.abs() + 1.0) / 2.0;
+        ((t * resolution as f32) as usize).min(resolution - 1)
+    };
+    let (u, v) = (cell(point[(axis + 1) % 3]), cell(point[(axis + 2) % 3]));
+    (face * resolution + v) * resolution + u
+}
+
+// The direction through the center of a cell of the cube, the inverse of `cube_cell`.
+fn cube_cell_center(face: usize, u: usize, v: usize, resolution: usize) -> Vec3 {
+    let axis: usize = face / 2;
+    let mut point = Vec3::ZERO;
+    point[axis] = if face % 2 == 0 { 1.0 } else { -1.0 };
+    point[(axis + 1) % 3] = (u as f32 + 0.5) / resolution as f32 * 2.0 - 1.0;
+    point[(axis + 2) % 3] = (v as f32 + 0.5) / resolution as f32 * 2.0 - 1.0;
+    point.normalize()
+}
+
+impl Icosphere {
+    /// Builds the sphere, each subdivision splits every triangle into four.
+    /// The sphere has `10 * 4^subdivisions + 2` vertices.
+    pub fn new(subdivisions: u32) -> Self {
+        let t: f32 = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let mut vertices: Vec<Vec3> = [
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ]
+        .into_iter()
+        .map(|vertex| Vec3::from_array(vertex).normalize())
+        .collect();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // Each edge is shared by two triangles, the cache makes them share the midpoint too.
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vec3>| -> u32 {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    vertices.push(((vertices[a as usize] + vertices[b as usize]) / 2.0).normalize());
+                    (vertices.len() - 1) as u32
+                })
+            };
+            let mut subdivided: Vec<[u32; 3]> = Vec::with_capacity(triangles.len() * 4);
+            for [a, b, c] in triangles {
+                let ab: u32 = midpoint(a, b, &mut vertices);
+                let bc: u32 = midpoint(b, c, &mut vertices);
+                let ca: u32 = midpoint(c, a, &mut vertices);
+                subdivided.extend([[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            triangles = subdivided;
+        }
+
+        let mut neighbours: Vec<Vec<u32>> = vec![Vec::with_capacity(6); vertices.len()];
+        for triangle in triangles.iter() {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                if !neighbours[a as usize].contains(&b) {
+                    neighbours[a as usize].push(b);
+                    neighbours[b as usize].push(a);
+                }
+            }
+        }
+
+        let mut sphere = Self {
+            vertices,
+            triangles,
+            neighbours,
+            subdivisions,
+            cube_starts: Vec::new(),
+            cube_resolution: CUBE_CELLS_PER_EDGE_VERTEX << subdivisions,
+        };
+        // Neighbouring cells have neighbouring vertices, so each walk starts from the vertex of the previous cell.
+        let resolution: usize = sphere.cube_resolution;
+        let mut starts: Vec<u32> = Vec::with_capacity(6 * resolution * resolution);
+        let mut previous: u32 = 0;
+        for face in 0..6 {
+            for v in 0..resolution {
+                for u in 0..resolution {
+                    previous = sphere.nearest(cube_cell_center(face, u, v, resolution), previous);
+                    starts.push(previous);
+                }
+            }
+        }
+        sphere.cube_starts = starts;
+        sphere
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// The angle between two neighbouring vertices, the resolution of anything stored per vertex.
+    pub fn spacing(&self) -> f32 {
+        let neighbour: u32 = self.neighbours[0][0];
+        self.vertices[0].angle_between(self.vertices[neighbour as usize])
+    }
+
+    /// Finds the vertex closest to a point on the unit sphere, for lookups with no nearby vertex to start from.
+    /// The walk starts from the vertex of the cube cell the point is in, so it costs about the same anywhere.
+    pub fn nearest_vertex(&self, point: Vec3) -> u32 {
+        self.nearest(point, self.cube_starts[cube_cell(point, self.cube_resolution)])
+    }
+
+    /// Finds the vertex closest to a point on the unit sphere by walking towards it from the start vertex.
+    /// This is fast when the start is already nearby, and always correct on a convex triangulation like this one.
+    pub fn nearest(&self, point: Vec3, start: u32) -> u32 {
+        let mut current: u32 = start;
+        let mut best: f32 = self.vertices[current as usize].dot(point);
+        loop {
+            let mut improved: bool = false;
+            for &neighbour in self.neighbours[current as usize].iter() {
+                let alignment: f32 = self.vertices[neighbour as usize].dot(point);
+                if alignment > best {
+                    best = alignment;
+                    current = neighbour;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+}
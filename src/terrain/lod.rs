@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use bevy::math::{DVec3, I64Vec3, IVec3, Vec3};
+use transvoxel::transition_sides::{no_side, TransitionSide, TransitionSides};
+
+use super::{config::TerrainConfig, is_within_world, CHUNK_SIZE_F32};
+
+/// Identifies a chunk by its level of detail and its coordinate on the grid of chunks of that level.
+///
+/// A chunk at level `lod` is `2^lod` times the size of a level 0 chunk but is meshed with the same
+/// number of subdivisions, so each level doubles the size of a block.
+/// The coordinate is an i64 like the [`crate::origin::GridCell`], and the positions of a chunk are absolute and in f64.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChunkKey {
+    pub coord: I64Vec3,
+    pub lod: u8,
+}
+
+impl ChunkKey {
+    pub fn new(coord: I64Vec3, lod: u8) -> Self {
+        Self { coord, lod }
+    }
+
+    /// The key of a chunk stored in an octree, which is indexed by i32 coordinates.
+    pub fn from_octree(coord: IVec3, lod: u8) -> Self {
+        Self::new(coord.as_i64vec3(), lod)
+    }
+
+    /// Whether the whole chunk is within the bound of the world, so it can be loaded and its corner fits an i32.
+    pub fn is_within_world(&self) -> bool {
+        is_within_world(self.base()) && is_within_world(self.base() + DVec3::splat(self.size() as f64 - 1.0))
+    }
+
+    /// The coordinate the chunk is stored at in an octree, None when it is out of the range of an i32.
+    pub fn octree_coord(&self) -> Option<IVec3> {
+        let [x, y, z] = self.coord.to_array().map(i32::try_from);
+        Some(IVec3::new(x.ok()?, y.ok()?, z.ok()?))
+    }
+
+    /// The length of one side of the chunk in world units.
+    pub fn size(&self) -> f32 {
+        CHUNK_SIZE_F32 * (1 << self.lod) as f32
+    }
+
+    /// The absolute world position of the minimum corner of the chunk.
+    pub fn base(&self) -> DVec3 {
+        self.coord.as_dvec3() * self.size() as f64
+    }
+
+    pub fn center(&self) -> DVec3 {
+        self.base() + DVec3::splat(self.size() as f64 / 2.0)
+    }
+
+    /// The key of the chunk one level coarser which contains this chunk.
+    pub fn parent(&self) -> ChunkKey {
+        ChunkKey::new(self.coord.div_euclid(I64Vec3::splat(2)), self.lod + 1)
+    }
+
+    /// The eight chunks one level finer which make up this chunk.
+    pub fn children(&self) -> [ChunkKey; 8] {
+        let base: I64Vec3 = self.coord * 2;
+        std::array::from_fn(|i| {
+            let offset = I64Vec3::new((i & 1) as i64, ((i >> 1) & 1) as i64, ((i >> 2) & 1) as i64);
+            ChunkKey::new(base + offset, self.lod - 1)
+        })
+    }
+
+    /// The key of the chunk at the given level which contains the absolute world position.
+    pub fn containing(position: DVec3, lod: u8) -> ChunkKey {
+        let size: f64 = CHUNK_SIZE_F32 as f64 * (1_u64 << lod) as f64;
+        ChunkKey::new((position / size).floor().as_i64vec3(), lod)
+    }
+
+    /// Returns true when the two chunks share any volume, chunks of different levels may overlap.
+    pub fn overlaps(&self, other: &ChunkKey) -> bool {
+        let (self_min, self_max) = (self.base(), self.base() + DVec3::splat(self.size() as f64));
+        let (other_min, other_max) = (other.base(), other.base() + DVec3::splat(other.size() as f64));
+        self_min.cmplt(other_max).all() && other_min.cmplt(self_max).all()
+    }
+
+    /// The distance from the absolute point to the closest point of the chunk, zero when the point is inside.
+    pub fn distance_to(&self, position: DVec3) -> f32 {
+        let min: DVec3 = self.base();
+        let max: DVec3 = min + DVec3::splat(self.size() as f64);
+        (position.clamp(min, max) - position).length() as f32
+    }
+}
+
+const SIDES: [(TransitionSide, Vec3); 6] = [
+    (TransitionSide::LowX, Vec3::NEG_X),
+    (TransitionSide::HighX, Vec3::X),
+    (TransitionSide::LowY, Vec3::NEG_Y),
+    (TransitionSide::HighY, Vec3::Y),
+    (TransitionSide::LowZ, Vec3::NEG_Z),
+    (TransitionSide::HighZ, Vec3::Z),
+];
+
+/// Selects the chunks forming concentric rings of detail around the center, with the transition sides for each chunk.
+///
+/// The coarsest chunks within the view radius are selected in a cylinder around the vertical axis, or in a sphere
+/// on a planet where there is no single vertical axis. They are then recursively split into their eight children while
+/// the center is closer than `lod_ring_radius` chunk lengths of that level. A ring radius of 2 or more keeps
+/// neighbouring chunks within one level of each other, which is what transvoxel transition cells can stitch.
+/// Chunks outside the bound of the world are never selected, the terrain ends there.
+pub fn select_lod_chunks(center: DVec3, config: &TerrainConfig, spherical: bool) -> HashMap<ChunkKey, TransitionSides> {
+    // Zero levels of detail is treated as one, the chunks are all at the finest level.
+    let top_lod: u8 = config.lod_levels.saturating_sub(1);
+    let top_center: ChunkKey = ChunkKey::containing(center, top_lod);
+
+    let mut stack: Vec<ChunkKey> = Vec::new();
+    let radius: i32 = config.view_radius;
+    let vertical_radius: i32 = if spherical { radius } else { config.vertical_view_radius };
+    for dx in -radius..=radius {
+        for dy in -vertical_radius..=vertical_radius {
+            for dz in -radius..=radius {
+                let distance_squared: i32 = if spherical { dx * dx + dy * dy + dz * dz } else { dx * dx + dz * dz };
+                if distance_squared > radius * radius {
+                    continue;
+                }
+                let key = ChunkKey::new(top_center.coord + IVec3::new(dx, dy, dz).as_i64vec3(), top_lod);
+                if key.is_within_world() {
+                    stack.push(key);
+                }
+            }
+        }
+    }
+
+    let mut leaves: HashMap<ChunkKey, TransitionSides> = HashMap::new();
+    while let Some(key) = stack.pop() {
+        if key.lod > 0 && key.distance_to(center) < config.lod_ring_radius * key.size() {
+            stack.extend(key.children());
+        } else {
+            leaves.insert(key, no_side());
+        }
+    }
+
+    // A side needs transition cells when the chunk across it is one level coarser.
+    let keys: Vec<ChunkKey> = leaves.keys().copied().collect();
+    for key in keys {
+        let mut sides: TransitionSides = no_side();
+        if key.lod < top_lod {
+            for (side, normal) in SIDES {
+                let outside: DVec3 = key.center() + (normal * (key.size() / 2.0 + CHUNK_SIZE_F32 / 2.0)).as_dvec3();
+                if leaves.contains_key(&ChunkKey::containing(outside, key.lod + 1)) {
+                    sides |= side;
+                }
+            }
+        }
+        leaves.insert(key, sides);
+    }
+
+    leaves
+}
@@ -0,0 +1,244 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use avian3d::prelude::{Collider, CollisionLayers, LayerMask};
+use bevy::{
+    asset::{AssetServer, Assets},
+    log::info,
+    math::DVec3,
+    pbr::MeshMaterial3d,
+    prelude::{Children, Commands, Mesh, Mesh3d, Res, ResMut, Resource},
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+};
+
+use crate::{physics::GameLayer, seed::WorldSeed};
+
+use super::{
+    biome::TerrainBiomes,
+    chunk_mesh::{build_chunk_mesh, is_mesh_empty, TerrainMaterial},
+    config::TerrainConfig,
+    density_graph::{DensityField, TerrainDensity},
+    editing::is_chunk_edited,
+    lod::ChunkKey,
+    octree::Octree,
+    scatter::{scatter_props, spawn_props, PropAssets, PropPlacement},
+    splat::TerrainSplatMaterial,
+    LODPostionTracker, TerrainData, Voxel,
+};
+
+/// The result of meshing a chunk on the async compute pool.
+pub struct ChunkMeshOutput {
+    pub mesh: Mesh,
+    // Only chunks within the configured level of detail get a collider.
+    pub collider: Option<Collider>,
+    // The props scattered over the mesh, only the nearer rings of detail get any.
+    pub props: Vec<PropPlacement>,
+}
+
+// A chunk waiting to be meshed, ordered so the chunk nearest to the tracked position is popped first.
+#[derive(PartialEq)]
+struct PendingChunk {
+    distance: f32,
+    key: ChunkKey,
+}
+
+impl Eq for PendingChunk {}
+
+impl Ord for PendingChunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max heap, so the comparison is reversed to pop the smallest distance.
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then_with(|| other.key.lod.cmp(&self.key.lod))
+    }
+}
+
+impl PartialOrd for PendingChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Tracks the chunks waiting to be meshed and the meshing tasks running on the [`AsyncComputeTaskPool`].
+#[derive(Resource, Default)]
+pub struct ChunkMeshQueue {
+    pending: BinaryHeap<PendingChunk>,
+    // The set of chunks which are still wanted, cancelled entries are skipped when popped from the heap.
+    queued: HashSet<ChunkKey>,
+    in_flight: HashMap<ChunkKey, Task<ChunkMeshOutput>>,
+    center: DVec3,
+}
+
+impl ChunkMeshQueue {
+    /// Queues the chunk to be meshed, any task already running for this chunk is cancelled so the newest request wins.
+    pub fn request(&mut self, key: ChunkKey) {
+        self.in_flight.remove(&key);
+        if self.queued.insert(key) {
+            self.pending.push(PendingChunk {
+                distance: key.distance_to(self.center),
+                key,
+            });
+        }
+    }
+
+    /// Cancels the pending or running meshing task for the chunk. Dropping a [`Task`] cancels it.
+    pub fn cancel(&mut self, key: ChunkKey) {
+        self.queued.remove(&key);
+        self.in_flight.remove(&key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len() + self.in_flight.len()
+    }
+
+    /// Returns true when any chunk sharing volume with the given chunk is queued or being meshed.
+    pub fn is_overlapping_pending(&self, key: &ChunkKey) -> bool {
+        self.queued
+            .iter()
+            .chain(self.in_flight.keys())
+            .any(|pending| pending.overlaps(key))
+    }
+
+    // Rebuilds the heap so the priorities are relative to the new center.
+    fn set_center(&mut self, center: DVec3) {
+        if self.center == center {
+            return;
+        }
+        self.center = center;
+        self.pending = self
+            .queued
+            .iter()
+            .map(|key| PendingChunk {
+                distance: key.distance_to(center),
+                key: *key,
+            })
+            .collect();
+    }
+}
+
+/// Starts meshing tasks for the nearest pending chunks until the configured number of tasks are running.
+/// Nothing is dispatched until the density graph and the biome table have loaded, the props are scattered by biome.
+pub fn dispatch_chunk_mesh_tasks(
+    mut queue: ResMut<ChunkMeshQueue>,
+    terrain: Res<TerrainData>,
+    density: Res<TerrainDensity>,
+    biomes: Res<TerrainBiomes>,
+    asset_server: Res<AssetServer>,
+    world_seed: Res<WorldSeed>,
+    tracked_pos: Res<LODPostionTracker>,
+    config: Res<TerrainConfig>,
+) {
+    queue.set_center(tracked_pos.center());
+    let Some(field) = density.field.as_ref() else {
+        return;
+    };
+    if biomes.is_loading(&asset_server) {
+        return;
+    }
+
+    let scatter_seed: u64 = world_seed.derive("scatter");
+    let task_pool = AsyncComputeTaskPool::get();
+    while queue.in_flight.len() < config.max_mesh_tasks_in_flight {
+        let Some(next) = queue.pending.pop() else {
+            break;
+        };
+        // skip the entries which were cancelled or already dispatched.
+        if !queue.queued.remove(&next.key) {
+            continue;
+        }
+        let Some(chunk) = terrain.chunk(&next.key) else {
+            continue;
+        };
+        let key: ChunkKey = next.key;
+        let transition_sides = chunk.transition_sides;
+        let with_collider: bool = key.lod <= config.collider_max_lod;
+        // The task keeps a snapshot of the edits, so later edits do not change a mesh which is being built.
+        let edits: Option<Arc<Octree<Voxel>>> = if is_chunk_edited(&terrain.voxels, &key) {
+            Some(terrain.voxels.clone())
+        } else {
+            None
+        };
+        let field: Arc<DensityField> = field.clone();
+        let biomes: TerrainBiomes = biomes.clone();
+        let task: Task<ChunkMeshOutput> = task_pool.spawn(async move {
+            let mesh: Mesh = build_chunk_mesh(key, transition_sides, &field, edits.as_deref());
+            // Building the trimesh is as expensive as the mesh itself so it is done on the task as well.
+            let collider: Option<Collider> = if with_collider && !is_mesh_empty(&mesh) {
+                Collider::trimesh_from_mesh(&mesh)
+            } else {
+                None
+            };
+            let props: Vec<PropPlacement> = scatter_props(key, &mesh, &field, &biomes, scatter_seed);
+            ChunkMeshOutput { mesh, collider, props }
+        });
+        queue.in_flight.insert(key, task);
+    }
+}
+
+/// Polls the running meshing tasks and attaches at most the configured number of finished meshes to their chunk each frame.
+/// The collider and the props of the chunk are replaced at the same time, so a remeshed chunk never keeps stale ones.
+/// The props of the chunks which get a collider get colliders too.
+pub fn apply_chunk_mesh_tasks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut queue: ResMut<ChunkMeshQueue>,
+    terrain: Res<TerrainData>,
+    terrain_material: Res<TerrainMaterial>,
+    prop_assets: Res<PropAssets>,
+    config: Res<TerrainConfig>,
+) {
+    let mut finished: Vec<(ChunkKey, ChunkMeshOutput)> = Vec::new();
+    for (key, task) in queue.in_flight.iter_mut() {
+        if finished.len() >= config.mesh_tasks_applied_per_frame {
+            break;
+        }
+        if let Some(output) = block_on(poll_once(task)) {
+            finished.push((*key, output));
+        }
+    }
+
+    let applied: usize = finished.len();
+    for (key, output) in finished {
+        queue.in_flight.remove(&key);
+
+        // The chunk may have been unloaded after the task completed but before it was polled.
+        let Some(chunk) = terrain.chunk(&key) else {
+            continue;
+        };
+
+        // Fully solid or fully empty chunks have no surface, the previous mesh is removed in case the chunk was remeshed.
+        let mut chunk_commands = commands.entity(chunk.entity);
+        if is_mesh_empty(&output.mesh) {
+            chunk_commands.remove::<(Mesh3d, MeshMaterial3d<TerrainSplatMaterial>)>();
+        } else {
+            chunk_commands.insert((
+                Mesh3d(meshes.add(output.mesh)),
+                MeshMaterial3d(terrain_material.0.clone()),
+            ));
+        }
+
+        match output.collider {
+            Some(collider) => {
+                chunk_commands.insert((
+                    collider,
+                    CollisionLayers::new(GameLayer::Terrain, LayerMask::ALL),
+                ));
+            }
+            None => {
+                chunk_commands.remove::<Collider>();
+            }
+        }
+
+        // The props are the only children of a chunk.
+        chunk_commands.despawn_related::<Children>();
+        spawn_props(&mut commands, chunk.entity, &output.props, &prop_assets, key.lod <= config.collider_max_lod);
+    }
+
+    if applied > 0 && queue.len() == 0 {
+        info!("Finished meshing all queued chunks");
+    }
+}
@@ -1,19 +1,73 @@
+use std::sync::Arc;
+
 use bevy::{
+    asset::AssetApp,
+    ecs::schedule::{common_conditions::resource_changed, IntoScheduleConfigs},
+    math::{DVec3, I64Vec3, IVec3},
+    pbr::MaterialPlugin,
     prelude::{
-         App, Plugin, Query, Res, ResMut, Resource, Transform, Update, With,
+         App, Entity, FixedUpdate, GlobalTransform, Last, Plugin, Query, Res, ResMut, Resource, Startup, Update, With,
     },
     time::{Time, Timer, TimerMode}, log::{warn, info},
 };
 
-use crate::{camera::GameCamera, utils::{format_value_f32}};
+use transvoxel::transition_sides::TransitionSides;
+
+use crate::{camera::GameCamera, origin::FloatingOrigin};
+use biome::{load_biome_table, update_biome_table, BiomeTable, BiomeTableLoader};
+use chunk_mesh::{despawn_retired_chunks, update_loaded_chunks};
+use config::TerrainConfig;
+use density_graph::{load_density_graph, update_density_field, DensityGraph, DensityGraphLoader};
+use editing::{apply_terrain_edits, read_terrain_edit_input, TerrainBrush, TerrainEdit};
+use lod::ChunkKey;
+use mesh_queue::{apply_chunk_mesh_tasks, dispatch_chunk_mesh_tasks, ChunkMeshQueue};
+use octree::Octree;
+use region::{save_regions_on_exit, setup_region_store};
+use scatter::setup_prop_assets;
+use splat::{build_terrain_texture_array, setup_terrain_material, TerrainSplatMaterial};
+use water::{apply_buoyancy, place_sea_surface, setup_water, update_water};
 
+pub mod bake;
 pub mod bevy_mesh;
+pub mod biome;
+pub mod cache;
+pub mod caves;
 pub mod chunk_mesh;
+pub mod climate;
+pub mod config;
+pub mod density_graph;
+pub mod editing;
+pub mod erosion;
+pub mod icosphere;
+pub mod lod;
+pub mod mesh_queue;
+pub mod octree;
+pub mod planet;
+pub mod query;
+pub mod region;
+pub mod simulation;
+pub mod scatter;
+pub mod splat;
+pub mod strata;
+pub mod water;
 
 pub const CHUNK_SIZE_F32: f32 = 16.0;
 pub const CHUNK_SIZE_I32: i32 = CHUNK_SIZE_F32 as i32;
 pub const CHUNK_SIZE_F32_MIDPOINT: f32 = CHUNK_SIZE_F32 / 2.0;
 pub const CHUNK_SIZE_I32_MIDPOINT: i32 = CHUNK_SIZE_F32_MIDPOINT as i32;
+// The voxel octree covers 2^24 world units along each axis centered on the origin.
+pub const VOXEL_OCTREE_DEPTH: u8 = 24;
+// The chunk octree covers the same volume, as a chunk is 2^4 voxels along each axis.
+pub const CHUNK_OCTREE_DEPTH: u8 = VOXEL_OCTREE_DEPTH - 4;
+// The terrain is loaded, edited and saved up to this far from the world origin along each axis, the volume both
+// octrees cover. Positions are precise anywhere thanks to the floating origin, but there is no terrain outside of it.
+pub const WORLD_HALF_EXTENT: f64 = (1 << (VOXEL_OCTREE_DEPTH - 1)) as f64;
+
+/// Whether the absolute position is within [`WORLD_HALF_EXTENT`] of the world origin, where its voxel and chunk
+/// coordinates fit the octrees.
+pub fn is_within_world(position: DVec3) -> bool {
+    position.cmpge(DVec3::splat(-WORLD_HALF_EXTENT)).all() && position.cmplt(DVec3::splat(WORLD_HALF_EXTENT)).all()
+}
 
 
 /// Converts a coordinate to a chunk coordinate.
@@ -24,7 +78,7 @@ pub const CHUNK_SIZE_I32_MIDPOINT: i32 = CHUNK_SIZE_F32_MIDPOINT as i32;
 ///
 /// # Arguments
 ///
-/// * `coord` - The coordinate value to convert.
+/// * `coord` - The absolute voxel coordinate to convert, see [`crate::origin::GridCell::voxel`].
 ///
 /// # Returns
 ///
@@ -33,56 +87,101 @@ pub const CHUNK_SIZE_I32_MIDPOINT: i32 = CHUNK_SIZE_F32_MIDPOINT as i32;
 /// # Examples
 ///
 /// ```rust
+/// use voyage_engine::terrain::convert_to_chunk_coordinate;
+///
 /// let coord = -15;
 /// let chunk_coord = convert_to_chunk_coordinate(coord);
 /// assert_eq!(chunk_coord, -1);
 /// ```
-pub fn convert_to_chunk_coordinate(coord: i32) -> i32 {
-    if coord < 0 {
-        (coord + 1) / (CHUNK_SIZE_F32 as i32) - 1
-    } else {
-        coord / CHUNK_SIZE_F32 as i32
-    }
+pub fn convert_to_chunk_coordinate(coord: i64) -> i64 {
+    coord.div_euclid(CHUNK_SIZE_I32 as i64)
 }
 
 #[derive(Resource)]
 pub struct TerrainPlugin;
 
 
+/// The density an edit adds to the procedural density field at a voxel.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
 pub struct Voxel {
-    is_occupied: bool,
+    pub density: f32,
 }
 
-// Define the Octree node
-enum OctreeNode {
-    // Children nodes and voxel data
-    Internal {
-        children: [Option<Box<OctreeNode>>; 8],
-    },
-    Leaf {
-        voxel: Voxel,
-    },
-    Empty,
+/// The single source of truth for the edited voxels and the chunks which are loaded.
+#[derive(Resource)]
+pub struct TerrainData {
+    // Indexed by the voxel position, one voxel is one world unit.
+    // Shared with the meshing tasks, an edit copies the octree only while a task still holds the previous version.
+    pub voxels: Arc<Octree<Voxel>>,
+    // Indexed by chunk coordinate, a chunk of level `lod` is stored as a node `lod` levels above the leaves.
+    pub chunks: Octree<LoadedChunk>,
+    // Chunks which are no longer selected but stay visible until the chunks replacing them are meshed.
+    pub retiring: Vec<(ChunkKey, Entity)>,
 }
 
-// Define the Octree structure
-struct Octree {
-    root: OctreeNode,
+impl Default for TerrainData {
+    fn default() -> Self {
+        Self {
+            voxels: Arc::new(Octree::centered(VOXEL_OCTREE_DEPTH)),
+            chunks: Octree::centered(CHUNK_OCTREE_DEPTH),
+            retiring: Vec::new(),
+        }
+    }
 }
 
-#[derive(Resource)]
-pub struct TerrainData {
-    octree: Octree
+impl TerrainData {
+    pub fn chunk(&self, key: &ChunkKey) -> Option<&LoadedChunk> {
+        self.chunks.get_at_level(key.octree_coord()?, key.lod)
+    }
+
+    pub fn chunk_mut(&mut self, key: &ChunkKey) -> Option<&mut LoadedChunk> {
+        self.chunks.get_mut_at_level(key.octree_coord()?, key.lod)
+    }
+
+    pub fn insert_chunk(&mut self, key: ChunkKey, chunk: LoadedChunk) {
+        let inserted: bool = key
+            .octree_coord()
+            .is_some_and(|coord| self.chunks.insert_at_level(coord, key.lod, chunk));
+        if !inserted {
+            warn!("Chunk {:?} is outside of the terrain and will not be tracked!", key);
+        }
+    }
+
+    pub fn remove_chunk(&mut self, key: &ChunkKey) -> Option<LoadedChunk> {
+        self.chunks.remove_at_level(key.octree_coord()?, key.lod)
+    }
+
+    /// The keys of every loaded chunk of every level of detail.
+    pub fn chunk_keys(&self) -> Vec<ChunkKey> {
+        self.chunks
+            .leaves()
+            .iter()
+            .map(|leaf| ChunkKey::from_octree(leaf.coord(), leaf.level))
+            .collect()
+    }
+
+    /// The density added by edits at the voxel, zero when the voxel was never edited.
+    pub fn density_delta(&self, position: IVec3) -> f32 {
+        self.voxels.get(position).map_or(0.0, |voxel| voxel.density)
+    }
 }
 
 #[derive(Resource)]
 pub struct LODRecalculateTimer(Timer);
 
+#[derive(Clone, PartialEq)]
+pub struct LoadedChunk {
+    pub entity: Entity,
+    // The faces of the chunk which border a coarser chunk, the chunk is remeshed when these change.
+    pub transition_sides: TransitionSides,
+}
+
+
 #[derive(Resource)]
 pub struct LODPostionTracker {
-    cx: i32,
-    cy: i32,
-    cz: i32,
+    cx: i64,
+    cy: i64,
+    cz: i64,
 }
 
 impl Plugin for TerrainPlugin {
@@ -98,18 +197,57 @@ impl Plugin for TerrainPlugin {
             cy: 0,
             cz: 0,
         })
-        .add_systems(Update, check_lod_position);
+        .insert_resource(TerrainConfig::default()) // later we will load from some toml file
+        .init_resource::<TerrainData>()
+        .init_resource::<ChunkMeshQueue>()
+        .init_resource::<TerrainBrush>()
+        .add_event::<TerrainEdit>()
+        .init_asset::<DensityGraph>()
+        .init_asset_loader::<DensityGraphLoader>()
+        .init_asset::<BiomeTable>()
+        .init_asset_loader::<BiomeTableLoader>()
+        .add_plugins(MaterialPlugin::<TerrainSplatMaterial>::default())
+        .add_systems(
+            Startup,
+            (
+                setup_terrain_material,
+                setup_region_store,
+                load_density_graph,
+                load_biome_table,
+                setup_water,
+                setup_prop_assets,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                check_lod_position,
+                update_density_field,
+                update_biome_table,
+                update_loaded_chunks.run_if(resource_changed::<LODPostionTracker>),
+                read_terrain_edit_input,
+                apply_terrain_edits,
+                dispatch_chunk_mesh_tasks,
+                apply_chunk_mesh_tasks,
+                despawn_retired_chunks,
+            )
+                .chain(),
+        )
+        .add_systems(Update, build_terrain_texture_array)
+        .add_systems(Update, (update_water, place_sea_surface).chain().after(update_density_field))
+        .add_systems(FixedUpdate, apply_buoyancy)
+        .add_systems(Last, save_regions_on_exit);
     }
 }
 
 impl LODPostionTracker {
+    // The absolute world position of the center of the tracked chunk.
+    fn center(&self) -> DVec3 {
+        (I64Vec3::new(self.cx, self.cy, self.cz).as_dvec3() + DVec3::splat(0.5)) * CHUNK_SIZE_F32 as f64
+    }
+
     fn to_string(&self) -> String {
-        format!(
-            "[{}, {}, {}]",
-            format_value_f32(self.cx as f32, None, true),
-            format_value_f32(self.cy as f32, None, true),
-            format_value_f32(self.cz as f32, None, true)
-        )
+        format!("[{}, {}, {}]", self.cx, self.cy, self.cz)
     }
 }
 
@@ -117,7 +255,8 @@ pub fn check_lod_position(
     time: Res<Time>,
     mut timer: ResMut<LODRecalculateTimer>,
     mut tracked_pos: ResMut<LODPostionTracker>,
-    camera_query: Query<&Transform, With<GameCamera>>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+    origin: Res<FloatingOrigin>,
 ) {
     // guard: timer hasn't finished, return early.
     if !timer.0.tick(time.delta()).just_finished() {
@@ -130,11 +269,14 @@ pub fn check_lod_position(
         return;    }
 
     // iterate over each camera and update the tracked position. Expects there to be only one camera in the scene.
+    // The camera is parented to the player or the free camera so we must use the global translation.
+    // The translation is relative to the floating origin, the chunks are tracked by the absolute voxel coordinate.
     for camera_transform in camera_query.iter() {
+        let voxel = origin.cell.voxel(camera_transform.translation());
         let cur_position = LODPostionTracker {
-            cx: convert_to_chunk_coordinate(camera_transform.translation.x as i32),
-            cy: convert_to_chunk_coordinate(camera_transform.translation.y as i32),
-            cz: convert_to_chunk_coordinate(camera_transform.translation.z as i32),
+            cx: convert_to_chunk_coordinate(voxel.x),
+            cy: convert_to_chunk_coordinate(voxel.y),
+            cz: convert_to_chunk_coordinate(voxel.z),
         };
         //info!("Your position is: [{}]", transform.translation.to_string());
         if cur_position.cx != tracked_pos.cx
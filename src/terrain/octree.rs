@@ -0,0 +1,407 @@
+use bevy::math::IVec3;
+
+/// A node of the [`Octree`], a leaf stores one value for every cell inside of it.
+#[derive(Clone, Debug)]
+pub enum OctreeNode<T> {
+    // Children are ordered with the x offset in bit 0, the y offset in bit 1 and the z offset in bit 2 of the index.
+    Internal {
+        children: Box<[OctreeNode<T>; 8]>,
+    },
+    Leaf {
+        value: T,
+    },
+    Empty,
+}
+
+/// A leaf found while walking the [`Octree`].
+pub struct OctreeLeaf<'a, T> {
+    // The minimum cell covered by the leaf.
+    pub min: IVec3,
+    // The level of the leaf, a leaf of level `n` covers `2^n` cells along each axis.
+    pub level: u8,
+    pub value: &'a T,
+}
+
+impl<T> OctreeLeaf<'_, T> {
+    pub fn size(&self) -> i32 {
+        1 << self.level
+    }
+
+    /// The coordinate of the leaf on the grid of nodes of its level.
+    pub fn coord(&self) -> IVec3 {
+        self.min.div_euclid(IVec3::splat(self.size()))
+    }
+}
+
+/// A sparse octree covering a cube of `2^depth` cells along each axis, starting at `origin`.
+///
+/// Values can be stored for a single cell or for a whole node of a given level, and nodes whose children
+/// are all empty or all hold the same value are collapsed back into a single node.
+#[derive(Clone)]
+pub struct Octree<T> {
+    root: OctreeNode<T>,
+    origin: IVec3,
+    depth: u8,
+}
+
+impl<T: Clone + PartialEq> Octree<T> {
+    pub fn new(origin: IVec3, depth: u8) -> Self {
+        Self {
+            root: OctreeNode::Empty,
+            origin,
+            depth,
+        }
+    }
+
+    /// Creates an octree centered on the origin, covering cells from `-2^(depth - 1)` to `2^(depth - 1) - 1`.
+    pub fn centered(depth: u8) -> Self {
+        Self::new(IVec3::splat(-(1 << (depth - 1))), depth)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self.root, OctreeNode::Empty)
+    }
+
+    pub fn contains(&self, position: IVec3) -> bool {
+        let local: IVec3 = position - self.origin;
+        local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(1 << self.depth)).all()
+    }
+
+    /// Returns the value of the leaf containing the cell, regardless of the level of the leaf.
+    pub fn get(&self, position: IVec3) -> Option<&T> {
+        if !self.contains(position) {
+            return None;
+        }
+        let local: IVec3 = position - self.origin;
+        let mut node: &OctreeNode<T> = &self.root;
+        let mut level: u8 = self.depth;
+        loop {
+            match node {
+                OctreeNode::Internal { children } => {
+                    level -= 1;
+                    node = &children[child_index(local, level)];
+                }
+                OctreeNode::Leaf { value } => return Some(value),
+                OctreeNode::Empty => return None,
+            }
+        }
+    }
+
+    /// Returns the value of the leaf exactly at the given level and coordinate, leaves of other levels are ignored.
+    pub fn get_at_level(&self, coord: IVec3, level: u8) -> Option<&T> {
+        let local: IVec3 = self.local_min(coord, level)?;
+        let mut node: &OctreeNode<T> = &self.root;
+        let mut node_level: u8 = self.depth;
+        while node_level > level {
+            let OctreeNode::Internal { children } = node else {
+                return None;
+            };
+            node_level -= 1;
+            node = &children[child_index(local, node_level)];
+        }
+        match node {
+            OctreeNode::Leaf { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut_at_level(&mut self, coord: IVec3, level: u8) -> Option<&mut T> {
+        let local: IVec3 = self.local_min(coord, level)?;
+        let mut node: &mut OctreeNode<T> = &mut self.root;
+        let mut node_level: u8 = self.depth;
+        while node_level > level {
+            let OctreeNode::Internal { children } = node else {
+                return None;
+            };
+            node_level -= 1;
+            node = &mut children[child_index(local, node_level)];
+        }
+        match node {
+            OctreeNode::Leaf { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets the value of a single cell, returns false when the cell is outside of the octree.
+    pub fn insert(&mut self, position: IVec3, value: T) -> bool {
+        self.insert_at_level(position, 0, value)
+    }
+
+    /// Sets the value of every cell of the node at the given level and coordinate.
+    pub fn insert_at_level(&mut self, coord: IVec3, level: u8, value: T) -> bool {
+        let Some(local) = self.local_min(coord, level) else {
+            return false;
+        };
+        set_node(&mut self.root, self.depth, local, level, Some(value));
+        true
+    }
+
+    /// Clears a single cell, returning its previous value.
+    pub fn remove(&mut self, position: IVec3) -> Option<T> {
+        self.remove_at_level(position, 0)
+    }
+
+    /// Clears every cell of the node at the given level and coordinate, returning its previous value when the node was a single leaf.
+    pub fn remove_at_level(&mut self, coord: IVec3, level: u8) -> Option<T> {
+        let local: IVec3 = self.local_min(coord, level)?;
+        set_node(&mut self.root, self.depth, local, level, None)
+    }
+
+    /// Returns every leaf intersecting the box from `min` inclusive to `max` exclusive.
+    pub fn query_aabb(&self, min: IVec3, max: IVec3) -> Vec<OctreeLeaf<'_, T>> {
+        let mut leaves: Vec<OctreeLeaf<T>> = Vec::new();
+        collect_leaves(
+            &self.root,
+            self.origin,
+            self.depth,
+            &mut |node_min, level| {
+                let node_max: IVec3 = node_min + IVec3::splat(1 << level);
+                node_min.cmplt(max).all() && min.cmplt(node_max).all()
+            },
+            &mut leaves,
+        );
+        leaves
+    }
+
+    /// Returns every leaf of the octree.
+    pub fn leaves(&self) -> Vec<OctreeLeaf<'_, T>> {
+        let mut leaves: Vec<OctreeLeaf<T>> = Vec::new();
+        collect_leaves(&self.root, self.origin, self.depth, &mut |_, _| true, &mut leaves);
+        leaves
+    }
+
+    /// Returns the leaves at exactly the given level, this is how chunks of a single level of detail are iterated.
+    pub fn leaves_at_level(&self, level: u8) -> Vec<OctreeLeaf<'_, T>> {
+        let mut leaves: Vec<OctreeLeaf<T>> = Vec::new();
+        collect_leaves(
+            &self.root,
+            self.origin,
+            self.depth,
+            &mut |_, node_level| node_level >= level,
+            &mut leaves,
+        );
+        leaves.retain(|leaf| leaf.level == level);
+        leaves
+    }
+
+    // Converts the coordinate of a node at the given level to the local position of its minimum cell.
+    fn local_min(&self, coord: IVec3, level: u8) -> Option<IVec3> {
+        if level > self.depth {
+            return None;
+        }
+        let min: IVec3 = coord * (1 << level);
+        if !self.contains(min) {
+            return None;
+        }
+        Some(min - self.origin)
+    }
+}
+
+impl<T: Clone + PartialEq> OctreeNode<T> {
+    // Replaces an internal node with a single node when all of its children are empty or hold the same value.
+    fn collapse(&mut self) {
+        let OctreeNode::Internal { children } = self else {
+            return;
+        };
+        let collapsed: Option<OctreeNode<T>> = match &children[0] {
+            OctreeNode::Empty if children.iter().all(|child| matches!(child, OctreeNode::Empty)) => {
+                Some(OctreeNode::Empty)
+            }
+            OctreeNode::Leaf { value }
+                if children.iter().all(
+                    |child| matches!(child, OctreeNode::Leaf { value: other } if other == value),
+                ) =>
+            {
+                Some(OctreeNode::Leaf {
+                    value: value.clone(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(node) = collapsed {
+            *self = node;
+        }
+    }
+}
+
+// The index of the child of a node at `child_level + 1` which contains the local position.
+fn child_index(local: IVec3, child_level: u8) -> usize {
+    (((local.x >> child_level) & 1) | (((local.y >> child_level) & 1) << 1) | (((local.z >> child_level) & 1) << 2))
+        as usize
+}
+
+// Sets the node at the target level to a leaf, or to empty when the value is None, splitting the nodes above it as needed.
+fn set_node<T: Clone + PartialEq>(
+    node: &mut OctreeNode<T>,
+    node_level: u8,
+    local: IVec3,
+    level: u8,
+    value: Option<T>,
+) -> Option<T> {
+    if node_level == level {
+        let replacement: OctreeNode<T> = match value {
+            Some(value) => OctreeNode::Leaf { value },
+            None => OctreeNode::Empty,
+        };
+        return match std::mem::replace(node, replacement) {
+            OctreeNode::Leaf { value } => Some(value),
+            _ => None,
+        };
+    }
+
+    // A leaf or empty node is split into eight copies of itself so only part of it can be changed.
+    if !matches!(node, OctreeNode::Internal { .. }) {
+        let fill: OctreeNode<T> = node.clone();
+        *node = OctreeNode::Internal {
+            children: Box::new(std::array::from_fn(|_| fill.clone())),
+        };
+    }
+
+    let previous: Option<T> = match node {
+        OctreeNode::Internal { children } => set_node(
+            &mut children[child_index(local, node_level - 1)],
+            node_level - 1,
+            local,
+            level,
+            value,
+        ),
+        _ => unreachable!("the node was split above"),
+    };
+    node.collapse();
+    previous
+}
+
+fn collect_leaves<'a, T>(
+    node: &'a OctreeNode<T>,
+    node_min: IVec3,
+    level: u8,
+    filter: &mut impl FnMut(IVec3, u8) -> bool,
+    leaves: &mut Vec<OctreeLeaf<'a, T>>,
+) {
+    if !filter(node_min, level) {
+        return;
+    }
+    match node {
+        OctreeNode::Internal { children } => {
+            let half: i32 = 1 << (level - 1);
+            for (index, child) in children.iter().enumerate() {
+                let offset: IVec3 = IVec3::new(
+                    (index & 1) as i32,
+                    ((index >> 1) & 1) as i32,
+                    ((index >> 2) & 1) as i32,
+                ) * half;
+                collect_leaves(child, node_min + offset, level - 1, filter, leaves);
+            }
+        }
+        OctreeNode::Leaf { value } => leaves.push(OctreeLeaf {
+            min: node_min,
+            level,
+            value,
+        }),
+        OctreeNode::Empty => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The minimum cell, level and value of each leaf, sorted so the order of the walk does not matter.
+    fn summary(leaves: Vec<OctreeLeaf<'_, u32>>) -> Vec<(IVec3, u8, u32)> {
+        let mut summary: Vec<(IVec3, u8, u32)> =
+            leaves.iter().map(|leaf| (leaf.min, leaf.level, *leaf.value)).collect();
+        summary.sort_by_key(|(min, level, _)| (min.to_array(), *level));
+        summary
+    }
+
+    #[test]
+    fn insert_and_remove_at_a_level() {
+        let mut octree: Octree<u32> = Octree::centered(8);
+        assert!(octree.insert_at_level(IVec3::new(1, -1, 0), 2, 5));
+        assert_eq!(octree.get(IVec3::new(4, -4, 0)), Some(&5));
+        assert_eq!(octree.get(IVec3::new(7, -1, 3)), Some(&5));
+        assert_eq!(octree.get(IVec3::new(8, -4, 0)), None);
+        assert_eq!(octree.get_at_level(IVec3::new(1, -1, 0), 2), Some(&5));
+        // Only a leaf of exactly the level is found at it.
+        assert_eq!(octree.get_at_level(IVec3::new(2, -2, 0), 1), None);
+
+        // Removing one cell splits the node, the rest of it keeps the value.
+        assert_eq!(octree.remove(IVec3::new(5, -3, 1)), Some(5));
+        assert_eq!(octree.get(IVec3::new(5, -3, 1)), None);
+        assert_eq!(octree.get(IVec3::new(4, -4, 0)), Some(&5));
+        assert_eq!(octree.get_at_level(IVec3::new(1, -1, 0), 2), None);
+
+        octree.remove_at_level(IVec3::new(1, -1, 0), 2);
+        assert_eq!(octree.get(IVec3::new(4, -4, 0)), None);
+        assert!(octree.is_empty());
+
+        // The cube covers -128 to 127 along each axis.
+        assert!(octree.insert(IVec3::splat(-128), 1));
+        assert!(!octree.insert(IVec3::splat(128), 1));
+        assert!(!octree.insert_at_level(IVec3::ZERO, 9, 1));
+    }
+
+    #[test]
+    fn uniform_children_collapse_until_one_changes() {
+        let mut octree: Octree<u32> = Octree::centered(8);
+        for index in 0..8 {
+            octree.insert(IVec3::new(index & 1, (index >> 1) & 1, (index >> 2) & 1), 3);
+        }
+        assert_eq!(summary(octree.leaves()), vec![(IVec3::ZERO, 1, 3)]);
+
+        octree.insert(IVec3::ONE, 4);
+        assert_eq!(octree.leaves().len(), 8);
+        assert_eq!(octree.get(IVec3::ZERO), Some(&3));
+        assert_eq!(octree.get(IVec3::ONE), Some(&4));
+        assert!(octree.leaves_at_level(1).is_empty());
+
+        octree.insert(IVec3::ONE, 3);
+        assert_eq!(summary(octree.leaves()), vec![(IVec3::ZERO, 1, 3)]);
+
+        // Emptying every child collapses the node back to nothing.
+        octree.remove_at_level(IVec3::ZERO, 1);
+        assert!(octree.is_empty());
+    }
+
+    #[test]
+    fn aabb_queries_at_negative_and_boundary_cells() {
+        let mut octree: Octree<u32> = Octree::centered(8);
+        octree.insert(IVec3::NEG_ONE, 1);
+        octree.insert(IVec3::new(-128, 0, 127), 2);
+        octree.insert_at_level(IVec3::new(-1, 0, 0), 2, 3);
+
+        assert_eq!(summary(octree.query_aabb(IVec3::NEG_ONE, IVec3::ZERO)), vec![(IVec3::NEG_ONE, 0, 1)]);
+        // The maximum is exclusive, so the cell at -1 is just outside of this box.
+        assert!(octree.query_aabb(IVec3::new(-4, -1, -1), IVec3::NEG_ONE).is_empty());
+        assert!(octree.query_aabb(IVec3::new(-4, -1, -1), IVec3::new(-1, 0, 0)).is_empty());
+        assert_eq!(
+            summary(octree.query_aabb(IVec3::new(-128, 0, 127), IVec3::new(-127, 1, 128))),
+            vec![(IVec3::new(-128, 0, 127), 0, 2)]
+        );
+        // A box overlapping part of a larger leaf returns the whole leaf.
+        assert_eq!(
+            summary(octree.query_aabb(IVec3::new(-2, 1, 1), IVec3::new(10, 2, 2))),
+            vec![(IVec3::new(-4, 0, 0), 2, 3)]
+        );
+        assert_eq!(octree.query_aabb(IVec3::splat(-128), IVec3::splat(128)).len(), 3);
+    }
+
+    #[test]
+    fn leaves_are_iterated_by_level() {
+        let mut octree: Octree<u32> = Octree::centered(8);
+        octree.insert_at_level(IVec3::ZERO, 2, 1);
+        octree.insert_at_level(IVec3::X, 2, 2);
+        octree.insert_at_level(IVec3::NEG_ONE, 1, 7);
+        octree.insert(IVec3::splat(20), 9);
+
+        assert_eq!(
+            summary(octree.leaves_at_level(2)),
+            vec![(IVec3::ZERO, 2, 1), (IVec3::new(4, 0, 0), 2, 2)]
+        );
+        let level_one: Vec<OctreeLeaf<'_, u32>> = octree.leaves_at_level(1);
+        assert_eq!(level_one.len(), 1);
+        assert_eq!((level_one[0].min, level_one[0].coord()), (IVec3::splat(-2), IVec3::NEG_ONE));
+        assert_eq!(summary(octree.leaves_at_level(0)), vec![(IVec3::splat(20), 0, 9)]);
+        assert!(octree.leaves_at_level(3).is_empty());
+    }
+}
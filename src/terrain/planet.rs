@@ -0,0 +1,76 @@
+use bevy::{
+    math::DVec3,
+    prelude::Resource,
+};
+use serde::Deserialize;
+
+use super::{climate::EARTH_AXIAL_TILT_DEGREES, lod::ChunkKey};
+
+/// A planet the terrain is generated on, it is centered on the world origin.
+///
+/// The planet is described in the density graph, and is only present while the loaded graph has one.
+#[derive(Resource, Deserialize, Clone, Debug, PartialEq)]
+pub struct Planetoid {
+    pub name: String,
+    // The age of the planet in years.
+    pub age: f32,
+    // The diameter of the planet at sea level in world units.
+    pub diameter: f32,
+    // The furthest the surface reaches above or below sea level, chunks further than this from sea level are not loaded.
+    pub relief: f32,
+    // The acceleration of gravity towards the center of the planet.
+    #[serde(default = "default_surface_gravity")]
+    pub surface_gravity: f32,
+    // The angle between the axis the planet spins around and its orbit, in degrees. It sets the seasons of the sky
+    // and the climate of the baked planet.
+    #[serde(default = "default_axial_tilt")]
+    pub axial_tilt: f32,
+    // The climate.ron of the bake the planet was generated from, relative to the assets directory.
+    // Without one the climate is estimated from the latitude.
+    #[serde(default)]
+    pub climate: Option<String>,
+    // The layers.ron of the bake, the rock below the surface follows its terranes.
+    // Without one the rock is the same default stack as on flat terrain.
+    #[serde(default)]
+    pub layers: Option<String>,
+    // The world units per km of the baked elevation, the scale of the Elevation nodes of the graph.
+    #[serde(default = "default_elevation_scale")]
+    pub elevation_scale: f32,
+    pub is_tectonic: bool,
+    pub is_habitable: bool,
+}
+
+fn default_surface_gravity() -> f32 {
+    9.81
+}
+
+fn default_elevation_scale() -> f32 {
+    1000.0
+}
+
+fn default_axial_tilt() -> f32 {
+    EARTH_AXIAL_TILT_DEGREES
+}
+
+impl Planetoid {
+    pub fn radius(&self) -> f32 {
+        self.diameter / 2.0
+    }
+
+    /// Returns true when the chunk can contain the surface, the rest of the chunks are entirely solid or entirely empty.
+    /// The shell is grown by one cell of the chunk so the normals at the edge of the shell are still sampled.
+    pub fn intersects_shell(&self, key: &ChunkKey) -> bool {
+        let min: DVec3 = key.base();
+        let max: DVec3 = min + DVec3::splat(key.size() as f64);
+        let nearest: f64 = DVec3::ZERO.clamp(min, max).length();
+        let furthest: f64 = min.abs().max(max.abs()).length();
+        let (radius, relief, margin) = (self.radius() as f64, self.relief as f64, key.size() as f64 / 16.0);
+        nearest <= radius + relief + margin && furthest >= radius - relief - margin
+    }
+
+    // The point on the sea level sphere above or below the position, the surface noise is sampled there
+    // so every point along a line from the center of the planet agrees on the height of the surface.
+    pub(crate) fn surface_point(&self, position: DVec3) -> DVec3 {
+        position.normalize_or(DVec3::Y) * self.radius() as f64
+    }
+}
@@ -0,0 +1,191 @@
+use bevy::math::{DVec3, IVec3, Vec3};
+
+use super::{
+    bevy_mesh::THRESHOLD, density_graph::DensityField, editing::sample_density_delta, strata::VoxelMaterial,
+    TerrainData,
+};
+
+// Questions gameplay can ask the terrain without going through physics, answered from the procedural field and the
+// edited voxels like the meshes are. The positions are absolute, not relative to the floating origin.
+
+// Halving the segment of the ray which crosses the surface this many times finds the hit to within 1/256 of a voxel.
+const SURFACE_REFINEMENTS: usize = 8;
+// The distance the density is sampled at on either side of a point to find its gradient.
+const GRADIENT_STEP: f64 = 0.5;
+
+/// The density and the material of the terrain at a point.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TerrainSample {
+    pub density: f32,
+    pub material: VoxelMaterial,
+}
+
+/// Where a ray first meets the surface of the terrain.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TerrainRayHit {
+    // The absolute position of the hit on the surface.
+    pub position: DVec3,
+    pub distance: f32,
+    // The voxel the ray was crossing when it hit, the cell between the integer coordinates around the hit.
+    pub voxel: IVec3,
+    pub normal: Vec3,
+    pub material: VoxelMaterial,
+}
+
+impl TerrainData {
+    /// The density at the absolute position, the procedural density plus what the edits added there.
+    pub fn density_at(&self, field: &DensityField, position: DVec3) -> f32 {
+        field.sample(position) as f32 + sample_density_delta(&self.voxels, position)
+    }
+
+    /// The density and the material at the absolute position, the material is air outside of the terrain.
+    pub fn sample(&self, field: &DensityField, position: DVec3) -> TerrainSample {
+        let density: f32 = self.density_at(field, position);
+        TerrainSample {
+            density,
+            material: field.material_for(position, density as f64),
+        }
+    }
+
+    /// The material at the absolute position including the edits.
+    pub fn material_at(&self, field: &DensityField, position: DVec3) -> VoxelMaterial {
+        self.sample(field, position).material
+    }
+
+    /// The direction out of the terrain at the absolute position, against the gradient of the density.
+    /// It is the normal of the surface at points on it, and world up where the density is flat.
+    pub fn normal_at(&self, field: &DensityField, position: DVec3) -> Vec3 {
+        let density = |offset: DVec3| self.density_at(field, position + offset * GRADIENT_STEP);
+        let gradient = Vec3::new(
+            density(DVec3::X) - density(DVec3::NEG_X),
+            density(DVec3::Y) - density(DVec3::NEG_Y),
+            density(DVec3::Z) - density(DVec3::NEG_Z),
+        );
+        (-gradient).normalize_or(Vec3::Y)
+    }
+
+    /// The first point along the ray where the terrain becomes solid, within the maximum distance.
+    ///
+    /// The ray steps from voxel to voxel like a DDA, sampling the density where it crosses each voxel boundary, so it
+    /// can not step over the surface inside a voxel unless the surface enters and leaves the same voxel. Once a step
+    /// crosses the threshold, the segment is halved until the hit is on the isosurface the meshes are built from.
+    /// A ray starting inside of the terrain has no surface to hit on its way out, so it hits nothing.
+    pub fn raycast(
+        &self,
+        field: &DensityField,
+        origin: DVec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<TerrainRayHit> {
+        let direction: DVec3 = direction.as_dvec3().try_normalize()?;
+        let max_distance: f64 = max_distance as f64;
+        let density = |distance: f64| self.density_at(field, origin + direction * distance);
+
+        let mut previous: f64 = 0.0;
+        if density(previous) >= THRESHOLD {
+            return None;
+        }
+
+        // The distance along the ray to the next boundary of a voxel along each axis, and between the boundaries.
+        let delta: DVec3 = direction.abs().recip();
+        let ahead: DVec3 = DVec3::select(direction.cmpgt(DVec3::ZERO), DVec3::ONE, DVec3::ZERO);
+        let next_boundary: DVec3 = origin.floor() + ahead;
+        let mut boundary: DVec3 = DVec3::select(
+            direction.cmpeq(DVec3::ZERO),
+            DVec3::INFINITY,
+            (next_boundary - origin) / direction,
+        );
+
+        while previous < max_distance {
+            // Step into the voxel whose boundary is nearest.
+            let axis: usize = if boundary.x <= boundary.y && boundary.x <= boundary.z {
+                0
+            } else if boundary.y <= boundary.z {
+                1
+            } else {
+                2
+            };
+            let distance: f64 = boundary[axis].min(max_distance);
+            if density(distance) >= THRESHOLD {
+                let (mut air, mut solid) = (previous, distance);
+                for _ in 0..SURFACE_REFINEMENTS {
+                    let middle: f64 = (air + solid) / 2.0;
+                    if density(middle) >= THRESHOLD {
+                        solid = middle;
+                    } else {
+                        air = middle;
+                    }
+                }
+                return Some(self.ray_hit(field, origin, direction, solid));
+            }
+            previous = distance;
+            boundary[axis] += delta[axis];
+        }
+        None
+    }
+
+    fn ray_hit(&self, field: &DensityField, origin: DVec3, direction: DVec3, distance: f64) -> TerrainRayHit {
+        let position: DVec3 = origin + direction * distance;
+        // Just inside of the surface, so the material is the solid the ray hit.
+        let inside: DVec3 = position + direction * 1.0e-3;
+        TerrainRayHit {
+            position,
+            distance: distance as f32,
+            voxel: inside.floor().as_ivec3(),
+            normal: self.normal_at(field, position),
+            material: field.material_for(inside, self.density_at(field, inside).max(f32::EPSILON) as f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{seed::WorldSeed, terrain::density_graph::DensityGraph};
+
+    // The longest a ray can travel through one voxel, halved by each refinement.
+    const HIT_TOLERANCE: f32 = 1.8 / (1 << SURFACE_REFINEMENTS) as f32;
+
+    fn field(root: &str) -> DensityField {
+        let graph: DensityGraph = ron::from_str(&format!("(root: {})", root)).unwrap();
+        DensityField::compile(&graph, &WorldSeed(0)).unwrap()
+    }
+
+    #[test]
+    fn hits_flat_ground_on_the_isosurface() {
+        let field: DensityField = field("Plane(normal: (0.0, 1.0, 0.0), height: 0.0)");
+        let terrain = TerrainData::default();
+        let direction = Vec3::new(0.2, -1.0, 0.1);
+        let hit: TerrainRayHit = terrain.raycast(&field, DVec3::new(0.3, 10.0, -0.7), direction, 50.0).unwrap();
+
+        let expected: f32 = 10.0 / -direction.normalize().y;
+        assert!((hit.distance - expected).abs() < HIT_TOLERANCE, "hit at {}, expected {}", hit.distance, expected);
+        assert!(hit.position.y.abs() < HIT_TOLERANCE as f64);
+        assert_eq!(hit.voxel.y, -1);
+        assert!(hit.normal.abs_diff_eq(Vec3::Y, 1.0e-4), "normal {}", hit.normal);
+    }
+
+    #[test]
+    fn misses_without_a_surface_ahead() {
+        let field: DensityField = field("Plane(normal: (0.0, 1.0, 0.0), height: 0.0)");
+        let terrain = TerrainData::default();
+        // Pointing away from the ground.
+        assert_eq!(terrain.raycast(&field, DVec3::new(0.0, 10.0, 0.0), Vec3::Y, 50.0), None);
+        // The ground is further than the ray reaches.
+        assert_eq!(terrain.raycast(&field, DVec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y, 5.0), None);
+        // Starting inside of the ground.
+        assert_eq!(terrain.raycast(&field, DVec3::new(0.0, -3.0, 0.0), Vec3::NEG_Y, 50.0), None);
+        assert_eq!(terrain.raycast(&field, DVec3::new(0.0, -3.0, 0.0), Vec3::Y, 50.0), None);
+    }
+
+    #[test]
+    fn hits_a_sphere_facing_out_of_it() {
+        let field: DensityField = field("Sphere(center: (0.0, 0.0, 0.0), radius: 20.0)");
+        let terrain = TerrainData::default();
+        let hit: TerrainRayHit = terrain.raycast(&field, DVec3::new(50.0, 3.0, 4.0), Vec3::NEG_X, 100.0).unwrap();
+
+        let surface = Vec3::new((20.0_f32 * 20.0 - 3.0 * 3.0 - 4.0 * 4.0).sqrt(), 3.0, 4.0);
+        assert!((hit.distance - (50.0 - surface.x)).abs() < HIT_TOLERANCE, "hit at {}", hit.distance);
+        assert!(hit.normal.abs_diff_eq(surface / 20.0, 2.0e-3), "normal {}", hit.normal);
+    }
+}
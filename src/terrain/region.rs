@@ -0,0 +1,437 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bevy::{
+    app::AppExit,
+    log::{info, warn},
+    math::IVec3,
+    prelude::{Commands, EventReader, Res, ResMut, Resource},
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::seed::WorldSeed;
+
+use super::{config::TerrainConfig, lod::ChunkKey, octree::Octree, TerrainData, Voxel, CHUNK_SIZE_I32};
+
+/// The number of chunks along each axis of a region file.
+pub const REGION_SIZE_CHUNKS: i32 = 32;
+pub const REGION_SIZE_VOXELS: i32 = REGION_SIZE_CHUNKS * CHUNK_SIZE_I32;
+// The level of the voxel octree node covering a whole region, 2^9 = 32 chunks of 16 voxels.
+const REGION_LEVEL: u8 = 9;
+// The level of the voxel octree node covering a single chunk.
+const CHUNK_LEVEL: u8 = 4;
+
+const REGION_MAGIC: [u8; 4] = *b"VRGN";
+// Bump this when the layout changes, files of another version are ignored rather than misread.
+const REGION_VERSION: u16 = 1;
+// magic + version + seed + chunk count.
+const HEADER_LENGTH: usize = 4 + 2 + 8 + 4;
+// chunk index + payload offset + payload length.
+const TABLE_ENTRY_LENGTH: usize = 2 + 4 + 4;
+// voxel index + level + density.
+const VOXEL_ENTRY_LENGTH: usize = 2 + 1 + 4;
+
+/// Saves and loads the terrain edits as region files of 32³ chunks.
+///
+/// A region file starts with a header of the magic bytes, the format version, the world seed and the number of chunks,
+/// followed by a table with the index, offset and length of each chunk's payload. Each payload is a zlib compressed list of
+/// the edited octree leaves inside the chunk, as the index of the minimum voxel in the chunk, the leaf level and the density.
+/// Everything is little endian. Files are stored per world seed, so different worlds never read each other's edits.
+#[derive(Resource)]
+pub struct RegionStore {
+    directory: PathBuf,
+    seed: u64,
+    // The regions whose edits are in the voxel octree.
+    loaded: HashSet<IVec3>,
+    // The loaded regions with edits which have not been written yet.
+    dirty: HashSet<IVec3>,
+    // The regions whose file could not be read. They are never written, so the file and the edits saved in it are
+    // kept for when it can be read again.
+    failed: HashSet<IVec3>,
+}
+
+impl RegionStore {
+    pub fn new(config: &TerrainConfig, seed: &WorldSeed) -> Self {
+        Self {
+            directory: PathBuf::from(&config.save_directory)
+                .join(seed.0.to_string())
+                .join("regions"),
+            seed: seed.0,
+            loaded: HashSet::new(),
+            dirty: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    /// Loads the edits of every region overlapping the chunk which is not loaded yet.
+    /// Loaded chunks are within the bound of the world, so their corner fits an i32.
+    pub fn load_chunk(&mut self, key: &ChunkKey, voxels: &mut Arc<Octree<Voxel>>) {
+        let min = key.base().as_ivec3();
+        self.load_range(min, min + IVec3::splat(key.size() as i32 - 1), voxels);
+    }
+
+    /// Loads the edits of every region overlapping the voxels from `min` to `max` inclusive which is not loaded yet.
+    pub fn load_range(&mut self, min: IVec3, max: IVec3, voxels: &mut Arc<Octree<Voxel>>) {
+        for region in regions_in_range(min, max) {
+            if self.loaded.contains(&region) || self.failed.contains(&region) {
+                continue;
+            }
+            match self.read_region(region) {
+                Ok(Some(leaves)) => {
+                    let voxels: &mut Octree<Voxel> = Arc::make_mut(voxels);
+                    for (min, level, density) in leaves.iter() {
+                        voxels.insert_at_level(min.div_euclid(IVec3::splat(1 << level)), *level, Voxel { density: *density });
+                    }
+                    info!("Loaded {} edit(s) from region {}", leaves.len(), region);
+                }
+                Ok(None) => (),
+                Err(error) => {
+                    warn!("Failed to read region {}, it will not be saved until it can be read: {}", region, error);
+                    self.failed.insert(region);
+                    continue;
+                }
+            }
+            self.loaded.insert(region);
+        }
+    }
+
+    /// Marks the regions overlapping the voxels from `min` to `max` inclusive as needing to be written.
+    pub fn mark_dirty(&mut self, min: IVec3, max: IVec3) {
+        for region in regions_in_range(min, max) {
+            self.dirty.insert(region);
+        }
+    }
+
+    /// Writes back and evicts the regions which no loaded chunk overlaps anymore. The regions which failed to read
+    /// are evicted without being written, and are read again when a chunk overlaps them.
+    pub fn unload_unused(&mut self, terrain: &mut TerrainData) {
+        let mut used: HashSet<IVec3> = HashSet::new();
+        for key in terrain.chunk_keys() {
+            let min = key.base().as_ivec3();
+            used.extend(regions_in_range(min, min + IVec3::splat(key.size() as i32 - 1)));
+        }
+        let unused: Vec<IVec3> = self
+            .loaded
+            .union(&self.failed)
+            .filter(|region| !used.contains(region))
+            .copied()
+            .collect();
+        if unused.is_empty() {
+            return;
+        }
+        for region in unused.iter() {
+            self.write_if_dirty(*region, &terrain.voxels);
+            self.loaded.remove(region);
+            self.failed.remove(region);
+        }
+        let voxels: &mut Octree<Voxel> = Arc::make_mut(&mut terrain.voxels);
+        for region in unused.iter() {
+            voxels.remove_at_level(*region, REGION_LEVEL);
+        }
+    }
+
+    /// Writes every region with unsaved edits.
+    pub fn flush(&mut self, voxels: &Octree<Voxel>) {
+        let dirty: Vec<IVec3> = self.dirty.iter().copied().collect();
+        for region in dirty {
+            self.write_if_dirty(region, voxels);
+        }
+    }
+
+    fn write_if_dirty(&mut self, region: IVec3, voxels: &Octree<Voxel>) {
+        if !self.dirty.contains(&region) {
+            return;
+        }
+        // Writing would replace the file which could not be read, and every edit saved in it with it.
+        if self.failed.contains(&region) {
+            warn!("Not saving the edits of region {}, its file could not be read", region);
+            self.dirty.remove(&region);
+            return;
+        }
+        match self.write_region(region, voxels) {
+            Ok(()) => {
+                self.dirty.remove(&region);
+            }
+            Err(error) => warn!("Failed to write region {}: {}", region, error),
+        }
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.directory
+            .join(format!("r.{}.{}.{}.bin", region.x, region.y, region.z))
+    }
+
+    // Returns the minimum voxel, level and density of each leaf stored in the region file, None when there is no file.
+    fn read_region(&self, region: IVec3) -> io::Result<Option<Vec<(IVec3, u8, f32)>>> {
+        let bytes: Vec<u8> = match fs::read(self.region_path(region)) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        if bytes.len() < HEADER_LENGTH || bytes[0..4] != REGION_MAGIC {
+            return Err(invalid_data("not a region file"));
+        }
+        let version: u16 = u16::from_le_bytes(bytes[4..6].try_into().unwrap());
+        if version != REGION_VERSION {
+            return Err(invalid_data(&format!("unsupported region version {}", version)));
+        }
+        let seed: u64 = u64::from_le_bytes(bytes[6..14].try_into().unwrap());
+        if seed != self.seed {
+            return Err(invalid_data(&format!("region belongs to world seed {}", seed)));
+        }
+        let chunk_count: usize = u32::from_le_bytes(bytes[14..18].try_into().unwrap()) as usize;
+
+        let region_min: IVec3 = region * REGION_SIZE_VOXELS;
+        let mut leaves: Vec<(IVec3, u8, f32)> = Vec::new();
+        for i in 0..chunk_count {
+            let entry: &[u8] = bytes
+                .get(HEADER_LENGTH + i * TABLE_ENTRY_LENGTH..HEADER_LENGTH + (i + 1) * TABLE_ENTRY_LENGTH)
+                .ok_or_else(|| invalid_data("truncated chunk table"))?;
+            let chunk_index: i32 = u16::from_le_bytes(entry[0..2].try_into().unwrap()) as i32;
+            let offset: usize = u32::from_le_bytes(entry[2..6].try_into().unwrap()) as usize;
+            let length: usize = u32::from_le_bytes(entry[6..10].try_into().unwrap()) as usize;
+            let payload: &[u8] = bytes
+                .get(offset..offset + length)
+                .ok_or_else(|| invalid_data("truncated chunk payload"))?;
+
+            let mut decoded: Vec<u8> = Vec::new();
+            ZlibDecoder::new(payload).read_to_end(&mut decoded)?;
+            let chunk_min: IVec3 = region_min + unpack_index(chunk_index, REGION_SIZE_CHUNKS) * CHUNK_SIZE_I32;
+            for voxel in decoded.chunks_exact(VOXEL_ENTRY_LENGTH) {
+                let voxel_index: i32 = u16::from_le_bytes(voxel[0..2].try_into().unwrap()) as i32;
+                let level: u8 = voxel[2];
+                let density: f32 = f32::from_le_bytes(voxel[3..7].try_into().unwrap());
+                leaves.push((chunk_min + unpack_index(voxel_index, CHUNK_SIZE_I32), level, density));
+            }
+        }
+        Ok(Some(leaves))
+    }
+
+    // Rewrites the whole region file, through a temporary file so a crash never leaves a partial region behind.
+    fn write_region(&self, region: IVec3, voxels: &Octree<Voxel>) -> io::Result<()> {
+        let region_min: IVec3 = region * REGION_SIZE_VOXELS;
+        let region_max: IVec3 = region_min + IVec3::splat(REGION_SIZE_VOXELS);
+
+        // Leaves no larger than a chunk always lie within a single chunk, larger leaves are split into chunk sized entries.
+        let mut chunks: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+        for leaf in voxels.query_aabb(region_min, region_max) {
+            if leaf.level <= CHUNK_LEVEL {
+                push_voxel_entry(&mut chunks, region_min, leaf.min, leaf.level, leaf.value.density);
+                continue;
+            }
+            let min: IVec3 = leaf.min.max(region_min);
+            let max: IVec3 = (leaf.min + IVec3::splat(leaf.size())).min(region_max);
+            for x in (min.x..max.x).step_by(CHUNK_SIZE_I32 as usize) {
+                for y in (min.y..max.y).step_by(CHUNK_SIZE_I32 as usize) {
+                    for z in (min.z..max.z).step_by(CHUNK_SIZE_I32 as usize) {
+                        push_voxel_entry(&mut chunks, region_min, IVec3::new(x, y, z), CHUNK_LEVEL, leaf.value.density);
+                    }
+                }
+            }
+        }
+
+        let path: PathBuf = self.region_path(region);
+        if chunks.is_empty() {
+            // Every edit in the region was undone.
+            return match fs::remove_file(&path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            };
+        }
+
+        let mut payloads: Vec<(u16, Vec<u8>)> = Vec::new();
+        for (chunk_index, entries) in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&entries)?;
+            payloads.push((chunk_index, encoder.finish()?));
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&REGION_MAGIC);
+        bytes.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&(payloads.len() as u32).to_le_bytes());
+        let mut offset: usize = HEADER_LENGTH + payloads.len() * TABLE_ENTRY_LENGTH;
+        for (chunk_index, payload) in payloads.iter() {
+            bytes.extend_from_slice(&chunk_index.to_le_bytes());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            offset += payload.len();
+        }
+        for (_, payload) in payloads.iter() {
+            bytes.extend_from_slice(payload);
+        }
+
+        fs::create_dir_all(&self.directory)?;
+        let temporary_path: PathBuf = path.with_extension("tmp");
+        fs::write(&temporary_path, &bytes)?;
+        fs::rename(&temporary_path, &path)
+    }
+}
+
+// The coordinates of every region overlapping the voxels from `min` to `max` inclusive.
+fn regions_in_range(min: IVec3, max: IVec3) -> Vec<IVec3> {
+    let min: IVec3 = min.div_euclid(IVec3::splat(REGION_SIZE_VOXELS));
+    let max: IVec3 = max.div_euclid(IVec3::splat(REGION_SIZE_VOXELS));
+    let mut regions: Vec<IVec3> = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                regions.push(IVec3::new(x, y, z));
+            }
+        }
+    }
+    regions
+}
+
+fn push_voxel_entry(chunks: &mut BTreeMap<u16, Vec<u8>>, region_min: IVec3, min: IVec3, level: u8, density: f32) {
+    let local: IVec3 = min - region_min;
+    let chunk_index: u16 = pack_index(local.div_euclid(IVec3::splat(CHUNK_SIZE_I32)), REGION_SIZE_CHUNKS);
+    let voxel_index: u16 = pack_index(local.rem_euclid(IVec3::splat(CHUNK_SIZE_I32)), CHUNK_SIZE_I32);
+    let entries: &mut Vec<u8> = chunks.entry(chunk_index).or_default();
+    entries.extend_from_slice(&voxel_index.to_le_bytes());
+    entries.push(level);
+    entries.extend_from_slice(&density.to_le_bytes());
+}
+
+fn pack_index(local: IVec3, size: i32) -> u16 {
+    (local.x + local.y * size + local.z * size * size) as u16
+}
+
+fn unpack_index(index: i32, size: i32) -> IVec3 {
+    IVec3::new(index % size, (index / size) % size, index / (size * size))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+pub fn setup_region_store(mut commands: Commands, config: Res<TerrainConfig>, seed: Res<WorldSeed>) {
+    commands.insert_resource(RegionStore::new(&config, &seed));
+}
+
+/// Writes every region with unsaved edits before the app exits.
+pub fn save_regions_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut regions: ResMut<RegionStore>,
+    terrain: Res<TerrainData>,
+) {
+    if exit_events.read().next().is_none() {
+        return;
+    }
+    regions.flush(&terrain.voxels);
+    info!("Saved the terrain edits");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::terrain::VOXEL_OCTREE_DEPTH;
+
+    const SEED: u64 = 42;
+
+    fn store(directory: &Path, seed: u64) -> RegionStore {
+        RegionStore {
+            directory: directory.to_path_buf(),
+            seed,
+            loaded: HashSet::new(),
+            dirty: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    // An empty directory of its own under the system temporary directory.
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(format!("voyage_region_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    // A few edits spread over the chunks of the first region, one in a leaf larger than a chunk.
+    fn edited_voxels() -> Octree<Voxel> {
+        let mut voxels: Octree<Voxel> = Octree::centered(VOXEL_OCTREE_DEPTH);
+        voxels.insert(IVec3::new(1, 2, 3), Voxel { density: 1.5 });
+        voxels.insert(IVec3::new(17, 40, 5), Voxel { density: -2.25 });
+        voxels.insert(IVec3::new(500, 3, 300), Voxel { density: 0.75 });
+        voxels.insert_at_level(IVec3::new(2, 0, 1), CHUNK_LEVEL + 1, Voxel { density: -4.0 });
+        voxels
+    }
+
+    fn load_first_region(store: &mut RegionStore) -> Arc<Octree<Voxel>> {
+        let mut voxels: Arc<Octree<Voxel>> = Arc::new(Octree::centered(VOXEL_OCTREE_DEPTH));
+        store.load_range(IVec3::ZERO, IVec3::splat(REGION_SIZE_VOXELS - 1), &mut voxels);
+        voxels
+    }
+
+    #[test]
+    fn edits_are_read_back_as_written() {
+        let directory: PathBuf = temporary_directory("read_back");
+        let written: Octree<Voxel> = edited_voxels();
+        store(&directory, SEED).write_region(IVec3::ZERO, &written).unwrap();
+
+        let mut reader: RegionStore = store(&directory, SEED);
+        let read: Arc<Octree<Voxel>> = load_first_region(&mut reader);
+        let leaf_min = IVec3::new(2, 0, 1) * (1 << (CHUNK_LEVEL + 1));
+        for position in [IVec3::new(1, 2, 3), IVec3::new(17, 40, 5), IVec3::new(500, 3, 300), leaf_min + 20] {
+            assert_eq!(read.get(position), written.get(position), "voxel {}", position);
+        }
+        assert_eq!(read.get(IVec3::new(2, 2, 3)), None);
+        assert!(reader.loaded.contains(&IVec3::ZERO));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn damaged_headers_are_rejected() {
+        let directory: PathBuf = temporary_directory("headers");
+        let writer: RegionStore = store(&directory, SEED);
+        writer.write_region(IVec3::ZERO, &edited_voxels()).unwrap();
+        let path: PathBuf = writer.region_path(IVec3::ZERO);
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+
+        // A payload cut short.
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(writer.read_region(IVec3::ZERO).is_err());
+        // A header cut short.
+        fs::write(&path, &bytes[..HEADER_LENGTH - 1]).unwrap();
+        assert!(writer.read_region(IVec3::ZERO).is_err());
+
+        let mut other_version: Vec<u8> = bytes.clone();
+        other_version[4..6].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+        fs::write(&path, &other_version).unwrap();
+        assert!(writer.read_region(IVec3::ZERO).is_err());
+
+        // The file is intact, but it was written for another world.
+        fs::write(&path, &bytes).unwrap();
+        assert!(store(&directory, SEED + 1).read_region(IVec3::ZERO).is_err());
+        assert!(writer.read_region(IVec3::ZERO).unwrap().is_some());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn unreadable_regions_are_never_overwritten() {
+        let directory: PathBuf = temporary_directory("unreadable");
+        let writer: RegionStore = store(&directory, SEED);
+        writer.write_region(IVec3::ZERO, &edited_voxels()).unwrap();
+        let path: PathBuf = writer.region_path(IVec3::ZERO);
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        let damaged: &[u8] = &bytes[..bytes.len() - 1];
+        fs::write(&path, damaged).unwrap();
+
+        // Editing the region and saving must keep the file which could not be read.
+        let mut reader: RegionStore = store(&directory, SEED);
+        let mut voxels: Arc<Octree<Voxel>> = load_first_region(&mut reader);
+        assert!(!reader.loaded.contains(&IVec3::ZERO));
+        Arc::make_mut(&mut voxels).insert(IVec3::new(8, 8, 8), Voxel { density: 3.0 });
+        reader.mark_dirty(IVec3::new(8, 8, 8), IVec3::new(8, 8, 8));
+        reader.flush(&voxels);
+        assert_eq!(fs::read(&path).unwrap(), damaged);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
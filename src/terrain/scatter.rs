@@ -0,0 +1,371 @@
+use avian3d::prelude::{Collider, CollisionLayers, LayerMask};
+use bevy::{
+    asset::{AssetServer, Assets, Handle},
+    color::Color,
+    log::warn,
+    math::{DVec3, Quat, Vec3},
+    pbr::{MeshMaterial3d, StandardMaterial},
+    prelude::{
+        ChildOf, Commands, Cone, Cylinder, Entity, Mesh, Mesh3d, Meshable, Res, ResMut, Resource, Sphere, Transform,
+    },
+    render::mesh::{Indices, VertexAttributeValues},
+    scene::{Scene, SceneRoot},
+    utils::default,
+};
+
+use crate::{
+    physics::GameLayer,
+    seed::{splitmix64, SplitMix64},
+};
+
+use super::{biome::TerrainBiomes, density_graph::DensityField, lod::ChunkKey, water::Sea};
+
+// Props are scattered over the triangles of a chunk when it is meshed, so they follow edits and are replaced with the
+// chunk. Candidates are thrown at random over the triangles, more than will be kept, and taken in a random order as
+// long as they are far enough from the props already placed. This dart throwing gives blue noise, props are spread
+// evenly without lining up. The random numbers are seeded from the chunk, so a chunk is decorated the same way every
+// time it is meshed with the same surface.
+
+// The candidates thrown for each spacing squared of surface, enough that the spacing and not the candidates limits
+// how many props are placed.
+const CANDIDATES_PER_SPACING: f32 = 4.0;
+
+/// What a prop looks like.
+#[derive(Clone, Copy, Debug)]
+pub enum PropModel {
+    // A cone of leaves on a trunk, built from primitives.
+    Tree { trunk_height: f32, canopy_height: f32, canopy_radius: f32 },
+    // A flattened icosphere.
+    Rock,
+    // A scene of a glTF model, relative to the assets directory.
+    Scene(&'static str),
+}
+
+/// The collider a prop gets near the camera, in the space of the prop before its scale.
+#[derive(Clone, Copy, Debug)]
+pub enum PropCollider {
+    // A capsule standing on the origin.
+    Trunk { radius: f32, height: f32 },
+    Ball { radius: f32 },
+}
+
+/// A kind of prop and where it is scattered.
+#[derive(Clone, Copy, Debug)]
+pub struct PropKind {
+    pub model: PropModel,
+    // The least distance between this prop and any other.
+    pub spacing: f32,
+    // The fraction of the candidates which become props, the vegetation density of the biome scales it for plants.
+    pub chance: f32,
+    pub vegetation: bool,
+    // The steepest surface the prop stands on, as the least dot product of the surface normal with up.
+    pub min_up_dot: f32,
+    // The range of the height above sea level in world units.
+    pub min_elevation: f32,
+    pub max_elevation: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Plants grow upright, rocks lie along the surface.
+    pub align_to_surface: bool,
+    // How far the prop is sunk into the ground, before its scale.
+    pub sink: f32,
+    // Chunks of a coarser level of detail than this have none of the prop.
+    pub max_lod: u8,
+    pub collider: Option<PropCollider>,
+}
+
+/// The props scattered over the terrain. The index of a kind is stored with each placement.
+pub const PROP_KINDS: [PropKind; 4] = [
+    PropKind {
+        model: PropModel::Tree {
+            trunk_height: 2.0,
+            canopy_height: 4.5,
+            canopy_radius: 1.5,
+        },
+        spacing: 5.0,
+        chance: 0.6,
+        vegetation: true,
+        min_up_dot: 0.85,
+        min_elevation: 0.5,
+        max_elevation: f32::INFINITY,
+        min_scale: 0.7,
+        max_scale: 1.4,
+        align_to_surface: false,
+        sink: 0.2,
+        max_lod: 1,
+        collider: Some(PropCollider::Trunk { radius: 0.25, height: 2.5 }),
+    },
+    PropKind {
+        model: PropModel::Rock,
+        spacing: 3.0,
+        chance: 0.12,
+        vegetation: false,
+        min_up_dot: 0.4,
+        min_elevation: f32::NEG_INFINITY,
+        max_elevation: f32::INFINITY,
+        min_scale: 0.3,
+        max_scale: 0.8,
+        align_to_surface: true,
+        sink: 0.15,
+        max_lod: 0,
+        collider: None,
+    },
+    PropKind {
+        model: PropModel::Rock,
+        spacing: 12.0,
+        chance: 0.15,
+        vegetation: false,
+        min_up_dot: 0.6,
+        min_elevation: f32::NEG_INFINITY,
+        max_elevation: f32::INFINITY,
+        min_scale: 1.8,
+        max_scale: 3.5,
+        align_to_surface: true,
+        sink: 0.2,
+        max_lod: 1,
+        collider: Some(PropCollider::Ball { radius: 0.4 }),
+    },
+    PropKind {
+        model: PropModel::Scene("models/FlightHelmet/FlightHelmet.gltf#Scene0"),
+        spacing: 48.0,
+        chance: 0.02,
+        vegetation: false,
+        min_up_dot: 0.9,
+        min_elevation: 0.5,
+        max_elevation: f32::INFINITY,
+        min_scale: 1.0,
+        max_scale: 1.0,
+        align_to_surface: false,
+        sink: 0.0,
+        max_lod: 0,
+        collider: Some(PropCollider::Ball { radius: 0.3 }),
+    },
+];
+
+/// A prop placed on a chunk, relative to the chunk like its mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct PropPlacement {
+    pub kind: usize,
+    pub transform: Transform,
+}
+
+// A point a prop may be placed at.
+struct Candidate {
+    kind: usize,
+    position: Vec3,
+    normal: Vec3,
+}
+
+/// Scatters the props over the triangles of the chunk mesh, which is relative to the base of the chunk.
+/// The seed is the scatter seed of the world, it is mixed with the chunk so each chunk gets its own props.
+pub fn scatter_props(
+    key: ChunkKey,
+    mesh: &Mesh,
+    field: &DensityField,
+    biomes: &TerrainBiomes,
+    seed: u64,
+) -> Vec<PropPlacement> {
+    let (Some(VertexAttributeValues::Float32x3(positions)), Some(VertexAttributeValues::Float32x3(normals))) =
+        (mesh.attribute(Mesh::ATTRIBUTE_POSITION), mesh.attribute(Mesh::ATTRIBUTE_NORMAL))
+    else {
+        return Vec::new();
+    };
+    let Some(Indices::U32(indices)) = mesh.indices() else {
+        return Vec::new();
+    };
+    if PROP_KINDS.iter().all(|kind| key.lod > kind.max_lod) {
+        return Vec::new();
+    }
+
+    let [x, y, z] = key.coord.to_array().map(|coordinate| coordinate as u64);
+    let hash: u64 = splitmix64(x ^ splitmix64(y ^ splitmix64(z ^ splitmix64(key.lod as u64))));
+    let mut rng = SplitMix64::new(splitmix64(seed ^ hash));
+
+    // Throw the candidates over each triangle in proportion to its area.
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from(positions[triangle[corner] as usize]));
+        let [na, nb, nc] = [0, 1, 2].map(|corner| Vec3::from(normals[triangle[corner] as usize]));
+        let area: f32 = (b - a).cross(c - a).length() / 2.0;
+        for (index, kind) in PROP_KINDS.iter().enumerate() {
+            if key.lod > kind.max_lod {
+                continue;
+            }
+            let expected: f32 = area / (kind.spacing * kind.spacing) * CANDIDATES_PER_SPACING;
+            let count: usize = expected.floor() as usize + usize::from(rng.next_f32() < expected.fract());
+            for _ in 0..count {
+                // A uniform point of the triangle, the points past the diagonal are folded back into it.
+                let (mut u, mut v) = (rng.next_f32(), rng.next_f32());
+                if u + v > 1.0 {
+                    (u, v) = (1.0 - u, 1.0 - v);
+                }
+                candidates.push(Candidate {
+                    kind: index,
+                    position: a + (b - a) * u + (c - a) * v,
+                    normal: (na + (nb - na) * u + (nc - na) * v).normalize_or(Vec3::Y),
+                });
+            }
+        }
+    }
+    for index in (1..candidates.len()).rev() {
+        candidates.swap(index, rng.below(index + 1));
+    }
+
+    let base: DVec3 = key.base();
+    let sea = Sea::for_planet(field.planet());
+    let mut placed: Vec<PropPlacement> = Vec::new();
+    for candidate in candidates {
+        let kind: &PropKind = &PROP_KINDS[candidate.kind];
+        let world: DVec3 = base + candidate.position.as_dvec3();
+        let up: Vec3 = field.up(world.as_vec3());
+        if candidate.normal.dot(up) < kind.min_up_dot {
+            continue;
+        }
+        let elevation: f32 = sea.elevation(world) as f32;
+        if elevation < kind.min_elevation || elevation > kind.max_elevation {
+            continue;
+        }
+        // Every prop keeps the larger of the two spacings from every other.
+        let crowded: bool = placed.iter().any(|other| {
+            let spacing: f32 = kind.spacing.max(PROP_KINDS[other.kind].spacing);
+            other.transform.translation.distance_squared(candidate.position) < spacing * spacing
+        });
+        if crowded {
+            continue;
+        }
+        let mut chance: f32 = kind.chance;
+        if kind.vegetation {
+            chance *= biomes
+                .biome_at(world, field.planet())
+                .map_or(1.0, |biome| biome.vegetation_density);
+        }
+        if rng.next_f32() >= chance {
+            continue;
+        }
+
+        let along: Vec3 = if kind.align_to_surface { candidate.normal } else { up };
+        let rotation: Quat = Quat::from_rotation_arc(Vec3::Y, along)
+            * Quat::from_rotation_y(rng.range_f32(0.0, std::f32::consts::TAU));
+        let scale: f32 = rng.range_f32(kind.min_scale, kind.max_scale);
+        placed.push(PropPlacement {
+            kind: candidate.kind,
+            transform: Transform::from_translation(candidate.position - along * kind.sink * scale)
+                .with_rotation(rotation)
+                .with_scale(Vec3::splat(scale)),
+        });
+    }
+    placed
+}
+
+// How a kind of prop is rendered, every prop of a kind shares the handles so they are drawn as instances.
+enum PropVisual {
+    Mesh(Handle<Mesh>, Handle<StandardMaterial>),
+    Scene(Handle<Scene>),
+}
+
+/// The meshes and scenes of each of the [`PROP_KINDS`].
+#[derive(Resource)]
+pub struct PropAssets {
+    visuals: Vec<PropVisual>,
+}
+
+pub fn setup_prop_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    // The colors of the plants are in their vertices, the material only shades them.
+    let plant_material = materials.add(StandardMaterial {
+        perceptual_roughness: 0.9,
+        ..default()
+    });
+    let rock_material = materials.add(StandardMaterial {
+        base_color: Color::srgb(0.4, 0.38, 0.36),
+        perceptual_roughness: 0.95,
+        ..default()
+    });
+    let rock_mesh: Handle<Mesh> = match Sphere::new(0.5).mesh().ico(1) {
+        Ok(mesh) => meshes.add(mesh.scaled_by(Vec3::new(1.0, 0.6, 1.0))),
+        Err(error) => {
+            warn!("Failed to build the rock mesh: {}", error);
+            meshes.add(Mesh::from(Sphere::new(0.5)))
+        }
+    };
+
+    let visuals: Vec<PropVisual> = PROP_KINDS
+        .iter()
+        .map(|kind| match kind.model {
+            PropModel::Tree { trunk_height, canopy_height, canopy_radius } => PropVisual::Mesh(
+                meshes.add(tree_mesh(trunk_height, canopy_height, canopy_radius)),
+                plant_material.clone(),
+            ),
+            PropModel::Rock => PropVisual::Mesh(rock_mesh.clone(), rock_material.clone()),
+            PropModel::Scene(path) => PropVisual::Scene(asset_server.load(path)),
+        })
+        .collect();
+    commands.insert_resource(PropAssets { visuals });
+}
+
+// A trunk with a cone of leaves overlapping its top, standing on the origin.
+fn tree_mesh(trunk_height: f32, canopy_height: f32, canopy_radius: f32) -> Mesh {
+    let trunk: Mesh = with_color(
+        Mesh::from(Cylinder::new(0.2, trunk_height)).translated_by(Vec3::Y * trunk_height / 2.0),
+        [0.25, 0.15, 0.08, 1.0],
+    );
+    let canopy: Mesh = with_color(
+        Mesh::from(Cone::new(canopy_radius, canopy_height))
+            .translated_by(Vec3::Y * (trunk_height * 0.8 + canopy_height / 2.0)),
+        [0.08, 0.25, 0.08, 1.0],
+    );
+    let mut tree: Mesh = trunk;
+    if let Err(error) = tree.merge(&canopy) {
+        warn!("Failed to merge the canopy into the tree mesh: {}", error);
+    }
+    tree
+}
+
+fn with_color(mut mesh: Mesh, color: [f32; 4]) -> Mesh {
+    let colors: Vec<[f32; 4]> = vec![color; mesh.count_vertices()];
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+/// Spawns the props as children of the chunk, so they move and are despawned with it.
+/// Near chunks give their props colliders, which become part of the static body of the chunk.
+pub fn spawn_props(
+    commands: &mut Commands,
+    chunk: Entity,
+    props: &[PropPlacement],
+    assets: &PropAssets,
+    with_colliders: bool,
+) {
+    for prop in props {
+        let kind: &PropKind = &PROP_KINDS[prop.kind];
+        let mut entity = commands.spawn((prop.transform, ChildOf(chunk)));
+        match &assets.visuals[prop.kind] {
+            PropVisual::Mesh(mesh, material) => {
+                entity.insert((Mesh3d(mesh.clone()), MeshMaterial3d(material.clone())));
+            }
+            PropVisual::Scene(scene) => {
+                entity.insert(SceneRoot(scene.clone()));
+            }
+        }
+        let Some(collider) = kind.collider.filter(|_| with_colliders) else {
+            continue;
+        };
+        // The collider is a sibling of the prop, moved to the center of its shape.
+        let (shape, center) = match collider {
+            PropCollider::Trunk { radius, height } => {
+                (Collider::capsule(radius, (height - 2.0 * radius).max(0.0)), Vec3::Y * height / 2.0)
+            }
+            PropCollider::Ball { radius } => (Collider::sphere(radius), Vec3::ZERO),
+        };
+        commands.spawn((
+            prop.transform * Transform::from_translation(center),
+            shape,
+            CollisionLayers::new(GameLayer::Terrain, LayerMask::ALL),
+            ChildOf(chunk),
+        ));
+    }
+}
@@ -1,183 +1,653 @@
+use std::{collections::VecDeque, f32::consts::PI, time::Instant};
 
+use bevy::{
+    log::info,
+    math::{Quat, Vec3},
+};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
 
+use super::icosphere::Icosphere;
+use crate::seed::SplitMix64;
 
-struct Planetoid {
-    name: String,
-    age: f32,
-    diameter: f32,
-    is_tectonic: bool,
-    is_habitable: bool,
+// A headless and deterministic plate tectonics simulation on a triangulated sphere, the same config always
+// produces the same planet. Elevations are in km relative to sea level, distances in km and times in years.
+//
+// Plates move by rotating around an axis through the center of the planet. Each step the crust at a vertex is
+// taken from wherever a plate carried it from:
+//     Nothing arrives - the plates diverge, new oceanic crust forms at the ridge.
+//     One plate arrives - the crust moves along with its plate.
+//     Several plates arrive - the plates converge, one overrides the others which subduct beneath it.
+//         Oceanic to Oceanic - the older plate subsides.
+//         Oceanic to Continental - the oceanic plate subsides.
+//         Continental to Continental - the terranes collide and the older plate subsides.
+// Subduction uplifts the overriding plate near the front and pulls the subducting plate towards it, collisions
+// raise mountains in proportion to the consumed terrane, continents erode and the ocean floor subsides as it ages.
+// Large continental plates occasionally rift in two.
+
+pub const SEA_LEVEL: f32 = 0.0;
+pub const TIME_STEP: f32 = 2_000_000.0;
+pub const PLANET_RADIUS: f32 = 6370.0;
+pub const MAX_OCEANIC_RIDGE_ELEVATION: f32 = -1.0;
+pub const ABYSSAL_PLAIN_ELEVATION: f32 = -6.0;
+pub const OCEANIC_TRENCH_ELEVATION: f32 = -10.0;
+pub const MAX_CONTINENTAL_ALTITUDE: f32 = 10.0;
+pub const SUBDUCTION_DISTANCE: f32 = 1800.0;
+pub const COLLISION_DISTANCE: f32 = 4200.0;
+pub const COLLISION_COEFFICIENT_PER_KM: f32 = 0.000013;
+// 100mm
+pub const MAX_PLATE_SPEED_PER_YEAR: f32 = 100.0e-6;
+// 0.04mm
+pub const BASE_OCEANIC_ELEVATION_DAMPENING_PER_YEAR: f32 = 0.04e-6;
+// 0.03mm
+pub const BASE_CONTINENTAL_EROSION_PER_YEAR: f32 = 0.03e-6;
+// 0.3mm
+pub const BASE_SEDIMENT_ACCRETION_PER_YEAR: f32 = 0.3e-6;
+// 0.6mm
+pub const BASE_SUBDUCTION_UPLIFT_PER_YEAR: f32 = 0.6e-6;
+// The weight of the relative plate motion when bending the fold direction.
+pub const BETA_CONSTANT: f32 = 1.0;
+// How much of its speed a plate turns towards its subduction fronts each step.
+const SLAB_PULL_FACTOR: f32 = 0.1;
+// The expected number of rifts of a fully continental plate of the average initial area each step.
+const RIFT_RATE: f32 = 0.02;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrustType {
+    Continental,
+    Oceanic,
 }
 
-struct Plate {
+/// The crust at a vertex of the simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct Crust {
+    pub plate: u16,
+    pub crust_type: CrustType,
+    pub elevation: f32,
+    // The years since the crust formed at a ridge, or since the start of the simulation.
+    pub age: f32,
+    // The direction the crust was last folded in by subduction or a collision, zero when it never was.
+    pub fold_direction: Vec3,
+}
 
+pub struct Plate {
+    // The plate rotates counter clockwise around this axis.
+    pub axis: Vec3,
+    // In radians per year.
+    pub angular_speed: f32,
+    pub centroid: Vec3,
+    // The number of vertices the plate covers.
+    pub area: usize,
 }
 
-struct PlanetoidSimulation {
-    planetoid: Planetoid,
-    plates: Plate,
+impl Plate {
+    // The surface velocity in km per year at a point on the unit sphere.
+    fn velocity_at(&self, point: Vec3, radius: f32) -> Vec3 {
+        self.axis.cross(point) * self.angular_speed * radius
+    }
+
+    fn rotation(&self) -> Quat {
+        Quat::from_axis_angle(self.axis, self.angular_speed * TIME_STEP)
+    }
+
+    fn set_angular_velocity(&mut self, angular_velocity: Vec3, radius: f32) {
+        let max_speed: f32 = MAX_PLATE_SPEED_PER_YEAR / radius;
+        self.angular_speed = angular_velocity.length().min(max_speed);
+        self.axis = angular_velocity.try_normalize().unwrap_or(self.axis);
+    }
 }
 
-fn load_biome_tables() -> void {
+pub struct TectonicsConfig {
+    pub seed: u64,
+    // The icosphere has `10 * 4^subdivisions + 2` vertices.
+    pub subdivisions: u32,
+    pub plate_count: u32,
+    // The fraction of the surface which starts as continental crust.
+    pub continental_ratio: f32,
+    // In km.
+    pub radius: f32,
+}
 
+impl Default for TectonicsConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            subdivisions: 5,
+            plate_count: 12,
+            continental_ratio: 0.3,
+            radius: PLANET_RADIUS,
+        }
+    }
 }
 
-/*
+// Where two plates meet and one is consumed beneath the other.
+struct Front {
+    vertex: u32,
+    overriding: u16,
+    subducting: u16,
+    // Both sides are continental, the terranes collide instead of subducting.
+    collision: bool,
+}
 
-*/
-fn generate_planetoid_simulation(seed: i32, subdivisions: i32, initial_plate_count: i32) -> PlanetoidSimulation {
-    // Select points for initial plates
-    // flood fill till not free plate segments are found
-    // recenter plate on center of mass.
-    // generate random plate movement direction and angular velocity.
+pub struct PlanetoidSimulation {
+    // The seed the simulation was generated from.
+    pub seed: u64,
+    pub sphere: Icosphere,
+    pub plates: Vec<Plate>,
+    pub crust: Vec<Crust>,
+    // The years simulated so far.
+    pub age: f64,
+    pub radius: f32,
+    // The average number of vertices of a plate at initialization, A0 in the design.
+    initial_average_area: f32,
+    rng: SplitMix64,
 }
 
+impl PlanetoidSimulation {
+    /// Triangulates the sphere, grows the plates from random seed vertices until they cover it,
+    /// and gives each plate a random motion.
+    pub fn generate(config: &TectonicsConfig) -> Self {
+        let sphere: Icosphere = Icosphere::new(config.subdivisions);
+        let mut rng: SplitMix64 = SplitMix64::new(config.seed);
+        let plate_count: usize = (config.plate_count as usize).clamp(1, sphere.len().min(u16::MAX as usize));
+
+        let owners: Vec<u16> = grow_plates(&sphere, plate_count, &mut rng);
+
+        // Low frequency noise decides where the continents are, the threshold is the quantile which
+        // makes the requested fraction of the vertices continental.
+        let noise = Fbm::<Perlin>::new((rng.next_u64() >> 32) as u32)
+            .set_octaves(4)
+            .set_frequency(1.2);
+        let heights: Vec<f32> = sphere
+            .vertices
+            .iter()
+            .map(|vertex| noise.get(vertex.as_dvec3().to_array()) as f32)
+            .collect();
+        let mut sorted: Vec<f32> = heights.clone();
+        sorted.sort_by(f32::total_cmp);
+        let quantile: usize = ((1.0 - config.continental_ratio.clamp(0.0, 1.0)) * (sorted.len() - 1) as f32) as usize;
+        let threshold: f32 = sorted[quantile];
+
+        let crust: Vec<Crust> = owners
+            .iter()
+            .zip(heights.iter())
+            .map(|(&plate, &height)| {
+                let relative: f32 = height - threshold;
+                let (crust_type, elevation) = if relative > 0.0 {
+                    (CrustType::Continental, (0.2 + relative * 4.0).min(2.0))
+                } else {
+                    (
+                        CrustType::Oceanic,
+                        (ABYSSAL_PLAIN_ELEVATION + 2.0 + relative * 8.0)
+                            .clamp(ABYSSAL_PLAIN_ELEVATION - 1.0, MAX_OCEANIC_RIDGE_ELEVATION - 1.0),
+                    )
+                };
+                Crust {
+                    plate,
+                    crust_type,
+                    elevation,
+                    age: 0.0,
+                    fold_direction: Vec3::ZERO,
+                }
+            })
+            .collect();
+
+        let max_speed: f32 = MAX_PLATE_SPEED_PER_YEAR / config.radius;
+        let plates: Vec<Plate> = (0..plate_count)
+            .map(|_| Plate {
+                axis: random_unit_vector(&mut rng),
+                angular_speed: rng.range_f32(0.3, 1.0) * max_speed,
+                centroid: Vec3::ZERO,
+                area: 0,
+            })
+            .collect();
+
+        let mut simulation = Self {
+            seed: config.seed,
+            initial_average_area: sphere.len() as f32 / plate_count as f32,
+            sphere,
+            plates,
+            crust,
+            age: 0.0,
+            radius: config.radius,
+            rng,
+        };
+        simulation.update_plates();
+        simulation
+    }
+
+    pub fn elevation(&self, vertex: usize) -> f32 {
+        self.crust[vertex].elevation
+    }
+
+    pub fn crust_type(&self, vertex: usize) -> CrustType {
+        self.crust[vertex].crust_type
+    }
+
+    pub fn crust_age(&self, vertex: usize) -> f32 {
+        self.crust[vertex].age
+    }
+
+    /// The crust at the vertex closest to the direction from the center of the planet.
+    pub fn sample(&self, direction: Vec3) -> &Crust {
+        let vertex: u32 = self.sphere.nearest_vertex(direction.normalize_or(Vec3::Y));
+        &self.crust[vertex as usize]
+    }
+
+    /// The surface area of the planet each vertex stands for, in km².
+    pub fn vertex_area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius / self.sphere.len() as f32
+    }
+
+    pub fn run(&mut self, steps: u32) {
+        let start: Instant = Instant::now();
+        for _ in 0..steps {
+            self.step();
+        }
+        info!(
+            "Simulated {} million years of tectonics on {} plates in {:?}",
+            steps as f32 * TIME_STEP / 1_000_000.0,
+            self.plates.iter().filter(|plate| plate.area > 0).count(),
+            start.elapsed()
+        );
+    }
+
+    /// Advances the simulation by one [`TIME_STEP`].
+    pub fn step(&mut self) {
+        let fronts: Vec<Front> = self.move_plates();
+        self.subduct(&fronts);
+        self.collide(&fronts);
+        self.slab_pull(&fronts);
+        self.erode();
+        self.rift();
+        self.update_plates();
+        self.age += TIME_STEP as f64;
+    }
+
+    // Moves the crust along with the plates, returning the vertices where plates converge.
+    fn move_plates(&mut self) -> Vec<Front> {
+        // The inverse rotation finds where the crust arriving at a vertex came from.
+        let inverse_rotations: Vec<Quat> = self.plates.iter().map(|plate| plate.rotation().inverse()).collect();
+        let rotations: Vec<Quat> = self.plates.iter().map(Plate::rotation).collect();
+
+        let mut fronts: Vec<Front> = Vec::new();
+        let mut moved: Vec<Crust> = Vec::with_capacity(self.crust.len());
+        for (vertex, point) in self.sphere.vertices.iter().enumerate() {
+            let mut arrived: Option<usize> = None;
+            for (plate, inverse_rotation) in inverse_rotations.iter().enumerate() {
+                if self.plates[plate].area == 0 {
+                    continue;
+                }
+                let source: usize = self.sphere.nearest(*inverse_rotation * *point, vertex as u32) as usize;
+                if self.crust[source].plate as usize != plate {
+                    continue;
+                }
+                arrived = match arrived {
+                    None => Some(source),
+                    Some(other) => {
+                        let (over, under) = if overrides(&self.crust[source], &self.crust[other]) {
+                            (source, other)
+                        } else {
+                            (other, source)
+                        };
+                        fronts.push(Front {
+                            vertex: vertex as u32,
+                            overriding: self.crust[over].plate,
+                            subducting: self.crust[under].plate,
+                            collision: self.crust[over].crust_type == CrustType::Continental
+                                && self.crust[under].crust_type == CrustType::Continental,
+                        });
+                        Some(over)
+                    }
+                };
+            }
+
+            moved.push(match arrived {
+                Some(source) => {
+                    let mut crust: Crust = self.crust[source];
+                    crust.fold_direction = rotations[crust.plate as usize] * crust.fold_direction;
+                    crust
+                }
+                // The plates pulled apart here, new oceanic crust forms at the ridge on the plate that was here.
+                None => Crust {
+                    plate: self.crust[vertex].plate,
+                    crust_type: CrustType::Oceanic,
+                    elevation: MAX_OCEANIC_RIDGE_ELEVATION,
+                    age: 0.0,
+                    fold_direction: Vec3::ZERO,
+                },
+            });
+        }
+        self.crust = moved;
+        fronts
+    }
+
+    // Uplifts the overriding plate near each subduction front and sinks the trench in front of it.
+    fn subduct(&mut self, fronts: &[Front]) {
+        let subductions: Vec<&Front> = fronts.iter().filter(|front| !front.collision).collect();
+        if subductions.is_empty() {
+            return;
+        }
+        let sources: Vec<(u32, u16)> = subductions.iter().map(|front| (front.vertex, front.overriding)).collect();
+        for (vertex, source, distance) in self.spread(&sources, SUBDUCTION_DISTANCE) {
+            let front: &Front = subductions[source];
+            let front_point: Vec3 = self.sphere.vertices[front.vertex as usize];
+            let relative_velocity: Vec3 = self.plates[front.subducting as usize].velocity_at(front_point, self.radius)
+                - self.plates[front.overriding as usize].velocity_at(front_point, self.radius);
+
+            let crust: &mut Crust = &mut self.crust[vertex as usize];
+            let normalized_elevation: f32 = (crust.elevation - OCEANIC_TRENCH_ELEVATION)
+                / (MAX_CONTINENTAL_ALTITUDE - OCEANIC_TRENCH_ELEVATION);
+            let uplift: f32 = BASE_SUBDUCTION_UPLIFT_PER_YEAR
+                * distance_transfer(distance, SUBDUCTION_DISTANCE)
+                * speed_transfer(relative_velocity.length())
+                * height_transfer(normalized_elevation);
+            crust.elevation += uplift * TIME_STEP;
+            crust.fold_direction = fold(crust.fold_direction, relative_velocity * TIME_STEP, self.sphere.vertices[vertex as usize]);
+        }
 
-fn compute_planetoid_simulation_step(sim: PlanetoidSimulation) -> void {
-    // start timer
-    // move plates
-    // compute collision list
-    // for each collision update plate values uplift.
-    // generate new plate segements at divergent boundaries.
-    // recompute plate center of mass?
-    // generate random events
-        // meteoric impacts
-            // terrain disruption
-            // mineral deposition
-        // volcanic events
-            // deposit ash 
-            // deposit volcanic rock
-    // update oceanic current simulation
-    // update wind simulation
-    // update moisture simulations
-    // update biome deposits
-        // organic deposits
-        // sedimentary deposits
-    // update biomes placements
-    // erode
-    // complete.
-    // end timer, report result.
+        // The subducting side of the front bends down into a trench, which is partly filled by accreted sediment.
+        let trench_floor: f32 = OCEANIC_TRENCH_ELEVATION + BASE_SEDIMENT_ACCRETION_PER_YEAR * TIME_STEP;
+        for front in subductions {
+            for &neighbour in self.sphere.neighbours[front.vertex as usize].iter() {
+                let crust: &mut Crust = &mut self.crust[neighbour as usize];
+                if crust.plate == front.subducting && crust.crust_type == CrustType::Oceanic {
+                    crust.elevation = crust.elevation.min(trench_floor);
+                }
+            }
+        }
+    }
+
+    // Raises mountains around each continental collision, in proportion to the area of the terranes
+    // consumed between the two plates this step.
+    fn collide(&mut self, fronts: &[Front]) {
+        let collisions: Vec<&Front> = fronts.iter().filter(|front| front.collision).collect();
+        if collisions.is_empty() {
+            return;
+        }
+        let vertex_area: f32 = self.vertex_area();
+        let sources: Vec<(u32, u16)> = collisions.iter().map(|front| (front.vertex, front.overriding)).collect();
+        for (vertex, source, distance) in self.spread(&sources, COLLISION_DISTANCE) {
+            let front: &Front = collisions[source];
+            let front_point: Vec3 = self.sphere.vertices[front.vertex as usize];
+            let terrane_area: f32 = collisions
+                .iter()
+                .filter(|other| other.overriding == front.overriding && other.subducting == front.subducting)
+                .count() as f32
+                * vertex_area;
+            let relative_speed: f32 = (self.plates[front.subducting as usize].velocity_at(front_point, self.radius)
+                - self.plates[front.overriding as usize].velocity_at(front_point, self.radius))
+            .length();
+            // Faster collisions reach further inland.
+            let reach: f32 = COLLISION_DISTANCE * speed_transfer(relative_speed).sqrt();
+            if distance > reach {
+                continue;
+            }
+
+            let point: Vec3 = self.sphere.vertices[vertex as usize];
+            let crust: &mut Crust = &mut self.crust[vertex as usize];
+            crust.elevation += COLLISION_COEFFICIENT_PER_KM * terrane_area * distance_transfer(distance, reach);
+            // The folds run perpendicular to the direction towards the collision.
+            if let Some(away) = (point - front_point).try_normalize() {
+                crust.fold_direction = point.cross(away).cross(point).normalize_or_zero();
+            }
+        }
+    }
+
+    // Turns each plate towards its subduction fronts, as the sinking slab pulls the rest of the plate after it.
+    fn slab_pull(&mut self, fronts: &[Front]) {
+        for (index, plate) in self.plates.iter_mut().enumerate() {
+            let pull: Vec3 = fronts
+                .iter()
+                .filter(|front| !front.collision && front.subducting as usize == index)
+                .map(|front| plate.centroid.cross(self.sphere.vertices[front.vertex as usize]).normalize_or_zero())
+                .sum();
+            let Some(pull) = pull.try_normalize() else {
+                continue;
+            };
+            let angular_velocity: Vec3 = plate.axis * plate.angular_speed;
+            let max_speed: f32 = MAX_PLATE_SPEED_PER_YEAR / self.radius;
+            plate.set_angular_velocity(angular_velocity + pull * SLAB_PULL_FACTOR * max_speed, self.radius);
+        }
+    }
+
+    // Continents wear down in proportion to their height and the ocean floor subsides towards the trench depth as it ages.
+    fn erode(&mut self) {
+        for crust in self.crust.iter_mut() {
+            match crust.crust_type {
+                CrustType::Continental => {
+                    if crust.elevation > SEA_LEVEL {
+                        crust.elevation -= (crust.elevation / MAX_CONTINENTAL_ALTITUDE)
+                            * BASE_CONTINENTAL_EROSION_PER_YEAR
+                            * TIME_STEP;
+                    }
+                }
+                CrustType::Oceanic => {
+                    crust.elevation -= (1.0 - crust.elevation / OCEANIC_TRENCH_ELEVATION)
+                        * BASE_OCEANIC_ELEVATION_DAMPENING_PER_YEAR
+                        * TIME_STEP;
+                }
+            }
+            crust.elevation = crust.elevation.clamp(OCEANIC_TRENCH_ELEVATION, MAX_CONTINENTAL_ALTITUDE);
+            crust.age += TIME_STEP;
+        }
+    }
+
+    // Splits plates in two, the chance is x * e^-x where x grows with the continental area of the plate.
+    fn rift(&mut self) {
+        for index in 0..self.plates.len() {
+            if self.plates[index].area < 2 || self.plates.len() >= u16::MAX as usize {
+                continue;
+            }
+            let continental: usize = self
+                .crust
+                .iter()
+                .filter(|crust| crust.plate as usize == index && crust.crust_type == CrustType::Continental)
+                .count();
+            // The continental fraction times the relative area of the plate is its continental area over A0.
+            let x: f32 = RIFT_RATE * continental as f32 / self.initial_average_area;
+            if self.rng.next_f32() >= x * (-x).exp() {
+                continue;
+            }
+
+            let members: Vec<u32> = (0..self.crust.len() as u32)
+                .filter(|vertex| self.crust[*vertex as usize].plate as usize == index)
+                .collect();
+            let a: u32 = members[self.rng.below(members.len())];
+            let b: u32 = members[self.rng.below(members.len())];
+            if a == b {
+                continue;
+            }
+
+            // The half closer to b becomes the new plate, and moves away from the half closer to a.
+            let new_plate: u16 = self.plates.len() as u16;
+            let sources: [(u32, u16); 2] = [(a, index as u16), (b, index as u16)];
+            for (vertex, source, _) in self.spread(&sources, f32::INFINITY) {
+                if source == 1 {
+                    self.crust[vertex as usize].plate = new_plate;
+                }
+            }
+            let (point_a, point_b) = (self.sphere.vertices[a as usize], self.sphere.vertices[b as usize]);
+            let max_speed: f32 = MAX_PLATE_SPEED_PER_YEAR / self.radius;
+            let angular_velocity: Vec3 = self.plates[index].axis * self.plates[index].angular_speed
+                + point_a.cross(point_b).normalize_or_zero() * max_speed * 0.5;
+            let mut plate = Plate {
+                axis: self.plates[index].axis,
+                angular_speed: 0.0,
+                centroid: Vec3::ZERO,
+                area: 0,
+            };
+            plate.set_angular_velocity(angular_velocity, self.radius);
+            self.plates.push(plate);
+            info!("Plate {} rifted, creating plate {}", index, new_plate);
+        }
+    }
+
+    fn update_plates(&mut self) {
+        let mut sums: Vec<Vec3> = vec![Vec3::ZERO; self.plates.len()];
+        let mut areas: Vec<usize> = vec![0; self.plates.len()];
+        for (crust, point) in self.crust.iter().zip(self.sphere.vertices.iter()) {
+            sums[crust.plate as usize] += *point;
+            areas[crust.plate as usize] += 1;
+        }
+        for ((plate, sum), area) in self.plates.iter_mut().zip(sums).zip(areas) {
+            plate.centroid = sum.normalize_or_zero();
+            plate.area = area;
+        }
+    }
+
+    // A breadth first search from the source vertices which only crosses vertices of the plate of each source,
+    // returning each reached vertex with the index of the closest source and the distance to it in km.
+    fn spread(&self, sources: &[(u32, u16)], max_distance: f32) -> Vec<(u32, usize, f32)> {
+        let step: f32 = self.sphere.spacing() * self.radius;
+        let mut visited: Vec<bool> = vec![false; self.sphere.len()];
+        let mut queue: VecDeque<(u32, usize, u32)> = VecDeque::new();
+        for (index, (vertex, plate)) in sources.iter().enumerate() {
+            if !visited[*vertex as usize] && self.crust[*vertex as usize].plate == *plate {
+                visited[*vertex as usize] = true;
+                queue.push_back((*vertex, index, 0));
+            }
+        }
+
+        let mut reached: Vec<(u32, usize, f32)> = Vec::new();
+        while let Some((vertex, source, hops)) = queue.pop_front() {
+            let distance: f32 = hops as f32 * step;
+            reached.push((vertex, source, distance));
+            if distance + step > max_distance {
+                continue;
+            }
+            for &neighbour in self.sphere.neighbours[vertex as usize].iter() {
+                if visited[neighbour as usize] || self.crust[neighbour as usize].plate != sources[source].1 {
+                    continue;
+                }
+                visited[neighbour as usize] = true;
+                queue.push_back((neighbour, source, hops + 1));
+            }
+        }
+        reached
+    }
 }
 
-fn bake_planetoid_simulation_state(sim: PlanetoidSimulation) -> void {
-    // height maps
-    // layer information
-    // biome map
-    // persistant noise settings and seeds,
+// Grows the plates from random seed vertices, claiming a random free neighbour of a random plate each
+// iteration so the plates get irregular borders, until every vertex belongs to a plate.
+fn grow_plates(sphere: &Icosphere, plate_count: usize, rng: &mut SplitMix64) -> Vec<u16> {
+    let mut owners: Vec<Option<u16>> = vec![None; sphere.len()];
+    let mut frontiers: Vec<Vec<u32>> = Vec::with_capacity(plate_count);
+    while frontiers.len() < plate_count {
+        let seed: usize = rng.below(sphere.len());
+        if owners[seed].is_none() {
+            owners[seed] = Some(frontiers.len() as u16);
+            frontiers.push(vec![seed as u32]);
+        }
+    }
+
+    loop {
+        let growing: Vec<usize> = (0..plate_count).filter(|plate| !frontiers[*plate].is_empty()).collect();
+        if growing.is_empty() {
+            break;
+        }
+        let plate: usize = growing[rng.below(growing.len())];
+        let index: usize = rng.below(frontiers[plate].len());
+        let vertex: u32 = frontiers[plate][index];
+        let free: Vec<u32> = sphere.neighbours[vertex as usize]
+            .iter()
+            .copied()
+            .filter(|neighbour| owners[*neighbour as usize].is_none())
+            .collect();
+        if free.is_empty() {
+            frontiers[plate].swap_remove(index);
+            continue;
+        }
+        let claimed: u32 = free[rng.below(free.len())];
+        owners[claimed as usize] = Some(plate as u16);
+        frontiers[plate].push(claimed);
+    }
+
+    owners.into_iter().map(|owner| owner.unwrap_or(0)).collect()
 }
 
+fn random_unit_vector(rng: &mut SplitMix64) -> Vec3 {
+    loop {
+        let candidate: Vec3 = Vec3::new(
+            rng.range_f32(-1.0, 1.0),
+            rng.range_f32(-1.0, 1.0),
+            rng.range_f32(-1.0, 1.0),
+        );
+        let length_squared: f32 = candidate.length_squared();
+        if length_squared > 0.0001 && length_squared <= 1.0 {
+            return candidate.normalize();
+        }
+    }
+}
 
-struct Plate {
-    crust_type: CrustType, // Xc
-    crust_thickness: f32, // e
-    relief_elevation: f32, //z
-    crust_age: f32, //Ao
-    local_ridge_direction: Vec3, // r
-    orogeny_age: f32, // o
-    orogeny_type: u32, // f
-    local_fold_direction: Vec3, //Ac
-    triangulation: Vec<f32>
+// Continental crust overrides oceanic crust, otherwise the older crust subsides.
+fn overrides(a: &Crust, b: &Crust) -> bool {
+    match (a.crust_type, b.crust_type) {
+        (CrustType::Continental, CrustType::Oceanic) => true,
+        (CrustType::Oceanic, CrustType::Continental) => false,
+        _ => a.age < b.age || (a.age == b.age && a.elevation >= b.elevation),
+    }
 }
 
-struct Terranes {
-    height: f32,
-    stack: Vec<(u16, f32)> // ordered list of tuples sediment type + thickness.
+// Falls from 1 at the front to 0 at the given distance.
+fn distance_transfer(distance: f32, reach: f32) -> f32 {
+    let x: f32 = (distance / reach).min(1.0);
+    (1.0 - x * x).powi(2)
 }
 
-enum CrustType {
-    Continental,
-    Oceanic
-}
-
-
-
-// Steps 
-/*
-    Constants:
-    SEA_LEVEL = 0km
-    TIME_STEP = 2000000 years
-    PlANET_RADIUS = 6370km
-    MAX_OCEANIC_REDIGE_ELEVATION = -1km
-    ABYSSAL_PLAIN_ELVATION = -6km
-    OCEANIC_TRENCH_ELEVATION = -10km
-    MAX_CONTINENTAL_ALTITUDE = 10km
-    SUBDUCTION_DISTANCE = 1800km
-    COLLISION_DISTANCE = 4200km
-    COLLISION_COEFFICIENT_PER_KM = 0.000013
-    MAX_PLATE_SPEED_PER_YEAR = 100mm
-    BASE_OCEANIC_ELEVASTION_DAMPENING_PER_YEAR = 0.04mm
-    BASE_CONTINENTAL_EROSION_PER_YEAR = 0.03mm
-    BASE_SEDIMENT_ACCRETION_PER_YEAR = 0.3mm
-    BASE_SUBDUCTION_UPLIFT_PER_YEAR = 0.6mm
-
-    BETA_CONTSTANT = 1.0 subject to change not listed in docks, just a weight to blend.
-
-    Function Curves: 
-    height_transfer(val: f32) -> f32 {
-        let res = val * val;
-        return res;
-    }
-
-    RUNTIME CONSTANT
-    A0 = Average of plate areas at initilization
-
-    Generate a Icosahedron with a high density of triangles.
-    Generete n Plates at randomly selected locations, settings values for Crust Type and applying low frequency noise to terranes.
-        Expand these plates to encompas all unclaimed points generated during triangluation.
-    Simulate Plate movement taking into account for user input for t years.
-        Subduction 
-            Oceanic to Oceanic - older plate subsides
-            Oceanic to Continental - Oceanic subsides
-            Continental to Continental - Promotes to Contentintal Collision when terranes collide, otherwise the older plate subsides.
-
-            Subduction Computations:
-                For all points p on contental crust with subducted plate beneath:
-                    p.relative_speed = (subducting.speedVec - uplifting.speedVec).normalize
-                    p.uplift_amount = BASE_SUBDUCTION_UPLIFT_PER_YEAR * distance_transfer_curve(p.distance_to_subduction_front) * speed_transfer_curve(p.relative_speed) * hieght_transfer_curve()
-                    p.z_squgies = (p.relief_elevation - OCEANIC_TRENCH_ELEVATION)/(MAX_CONTINENTAL_ALTITUDE - OCEANIC_TRENCH_ELEVATION)
-                    elevation_next = p.elevation + (p.uplift_amount * TIME_STEP);
-                    uplifting.fold_dir = uplifting.fold_dir + BETA_CONTSTANT * (subducting.speed - uplifting.speed) * TIME_STEP // this could be done with matrix math.
-                Slab Pull:
-                    we are changing the plate rotation (w) to point towards the subduction front.
-                    w(t + TIME_STEP) = w(t) + E * FOR (int k = 0; k > n - 1; k++)(((Ci X Qk)/(Ci X Qk).normalize)) * TIME_STEP
-                    Ci is the centroid position of the plate
-                    Qk are the points inside the subduction front aka underneath the overriding plate. Maybe we average this point and aim the w vector to this point.
-                    E is less than 1 basically a blending thing. How much does teh slab pull matter
-
-
-            Continental Collision:
-                n = normal to sphere at p
-                p = position of plate.
-                q = centeroid of the terrane
-                r = Rc * SQRT(v(q)/V0) * A/A0    // Power law
-                f() = (1 - (x / r)^2)^2
-                delta_z(p) = delta_c * A * f(d(p, R))
-                z(p, t + TIME_STEP) = z(p, t) + delta_z(p)
-                f(p, t + TIME_STEP) = (n X (p - q)/ ((p - q).normalize)) X n
-                If close enough remove the terranes from subducting plate and onto overriding
-            
-            
-            Oceanic Crust Generation & Plate Sampling:
-                When a plate_hook is ready set its height with this
-                alpha = distance_to_rift(plate) / (distance_to_ridge(p) + distance_to_plate(p))
-                z(p, t + TIME_STEP) = alpha * interpolation_between_plates() + (1 - alpha) * height of ridge(p, t)
-            
-            
-            Plate Rifting: aka when to rift
-            For each Plate
-                Generate a number between 0.0f - 1.0f if probabilty is higher cause a rifting event.
-                    Probability = x * e ^ -x
-                    where x = average_number_of_rifts_in_time_window * crust_type_ratio(plate) * plate.Area / AverageAreaAtInit 
-
-                    Rifting is done by cracking the plate at some point where the mantle is very hot possiblyh and craeting a line between the two halves.
-            Contenental Erosion and Oceanic Dampening:
-                for each terrane:
-                    elevation = elevation - ((1 - (elvation/OCEANIC_TRENCH_ELEVATION)) * BASE_OCEANIC_ELEVASTION_DAMPENING_PER_YEAR * TIME_STEP)
-                for each oceanic crust terrane:
-                    elevation = elevation - ((elvation/OCEANIC_TRENCH_ELEVATION) * BASE_OCEANIC_ELEVASTION_DAMPENING_PER_YEAR * TIME_STEP)
-    
-    Apply Amplification aka. post processing and noise to higher detail.
-    Save data to disk for fetch later.
-
-    Then we do historical simulation.
- */
\ No newline at end of file
+// Faster convergence uplifts more, relative to the fastest a plate can move.
+fn speed_transfer(relative_speed: f32) -> f32 {
+    (relative_speed / MAX_PLATE_SPEED_PER_YEAR).min(1.0)
+}
+
+// Higher crust is uplifted more, the elevation is normalized between the trench and the highest continent.
+fn height_transfer(normalized_elevation: f32) -> f32 {
+    normalized_elevation * normalized_elevation
+}
+
+// Bends the fold direction by the relative motion of the plates, keeping it tangent to the sphere.
+fn fold(fold_direction: Vec3, relative_motion: Vec3, point: Vec3) -> Vec3 {
+    let folded: Vec3 = fold_direction + BETA_CONSTANT * relative_motion;
+    folded.reject_from_normalized(point).normalize_or_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything the simulation leaves behind, with the floats as bits so identical means bit for bit.
+    fn state(simulation: &PlanetoidSimulation) -> (Vec<(u16, CrustType, u32, u32, [u32; 3])>, Vec<[u32; 5]>, u64) {
+        let crust = simulation
+            .crust
+            .iter()
+            .map(|crust| {
+                let fold: [u32; 3] = crust.fold_direction.to_array().map(f32::to_bits);
+                (crust.plate, crust.crust_type, crust.elevation.to_bits(), crust.age.to_bits(), fold)
+            })
+            .collect();
+        let plates = simulation
+            .plates
+            .iter()
+            .map(|plate| {
+                let [x, y, z] = plate.axis.to_array().map(f32::to_bits);
+                [x, y, z, plate.angular_speed.to_bits(), plate.area as u32]
+            })
+            .collect();
+        (crust, plates, simulation.age.to_bits())
+    }
+
+    #[test]
+    fn the_same_config_simulates_the_same_planet() {
+        let config = TectonicsConfig {
+            seed: 7,
+            subdivisions: 3,
+            ..TectonicsConfig::default()
+        };
+        let mut first: PlanetoidSimulation = PlanetoidSimulation::generate(&config);
+        let mut second: PlanetoidSimulation = PlanetoidSimulation::generate(&config);
+        assert!(state(&first) == state(&second), "the generated planets differ");
+
+        first.run(20);
+        second.run(20);
+        assert!(state(&first) == state(&second), "the simulated planets differ");
+    }
+}
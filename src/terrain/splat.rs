@@ -0,0 +1,234 @@
+use bevy::{
+    asset::{Asset, AssetServer, Assets, Handle},
+    color::Srgba,
+    image::{Image, ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+    log::{info, warn},
+    math::{UVec4, Vec4},
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline, StandardMaterial},
+    prelude::{Commands, Res, ResMut, Resource},
+    reflect::TypePath,
+    render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderRef, ShaderType, SpecializedMeshPipelineError,
+            TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+        },
+    },
+    utils::default,
+};
+
+use super::{
+    bevy_mesh::{ATTRIBUTE_MATERIAL_IDS, ATTRIBUTE_MATERIAL_WEIGHTS, ATTRIBUTE_TRIPLANAR_POSITION},
+    chunk_mesh::TerrainMaterial,
+    strata::VoxelMaterial,
+};
+
+// The terrain is shaded by projecting a layer of a texture array along each axis for each of the materials of a
+// triangle and blending them by the weights of the vertices. The textures only add detail, each is divided by its mean
+// color and tinted with the color of the material, so a few textures cover every material.
+
+const SHADER_PATH: &str = "shaders/terrain.wgsl";
+// The number of materials the shading has room for, at least `VoxelMaterial::COUNT`.
+const MATERIAL_SLOTS: usize = 16;
+const MAX_TEXTURE_LAYERS: usize = 8;
+// The layers of the texture array, relative to the assets directory. They must have the same size.
+const TEXTURE_LAYERS: [&str; 2] = ["textures/proto_dark_01.png", "textures/texture_02.png"];
+// The layer and the tint of each material, in linear RGB.
+const MATERIAL_APPEARANCE: [(VoxelMaterial, u32, [f32; 3]); VoxelMaterial::COUNT] = [
+    (VoxelMaterial::Air, 0, [1.0, 0.0, 1.0]),
+    (VoxelMaterial::Soil, 1, [0.18, 0.11, 0.06]),
+    (VoxelMaterial::Basalt, 0, [0.06, 0.06, 0.07]),
+    (VoxelMaterial::Granite, 0, [0.35, 0.32, 0.3]),
+    (VoxelMaterial::Limestone, 0, [0.55, 0.52, 0.42]),
+    (VoxelMaterial::Sandstone, 0, [0.5, 0.3, 0.15]),
+    (VoxelMaterial::Metamorphic, 0, [0.2, 0.19, 0.23]),
+    (VoxelMaterial::Coal, 0, [0.02, 0.02, 0.02]),
+    (VoxelMaterial::IronOre, 0, [0.3, 0.1, 0.05]),
+    (VoxelMaterial::CopperOre, 0, [0.1, 0.3, 0.25]),
+    (VoxelMaterial::GoldOre, 0, [0.7, 0.5, 0.1]),
+    (VoxelMaterial::Grass, 1, [0.08, 0.2, 0.04]),
+    (VoxelMaterial::Snow, 1, [0.9, 0.92, 0.95]),
+];
+// The world units one repeat of a texture covers, it must divide the triplanar period.
+const TEXTURE_SIZE: f32 = 4.0;
+// Higher is a sharper transition between the projections along each axis.
+const TRIPLANAR_SHARPNESS: f32 = 4.0;
+
+/// The material of the terrain chunks.
+pub type TerrainSplatMaterial = ExtendedMaterial<StandardMaterial, TerrainMaterialExtension>;
+
+/// What the terrain shader needs to know about the materials and the texture array.
+#[derive(ShaderType, Clone, Debug)]
+pub struct TerrainShading {
+    // The layer of the texture array of each material, four materials to a vector.
+    pub layers: [UVec4; MATERIAL_SLOTS / 4],
+    pub tints: [Vec4; MATERIAL_SLOTS],
+    // The mean color of each layer, the samples are divided by it.
+    pub layer_means: [Vec4; MAX_TEXTURE_LAYERS],
+    pub texture_size: f32,
+    pub triplanar_sharpness: f32,
+}
+
+impl Default for TerrainShading {
+    fn default() -> Self {
+        let mut layers: [UVec4; MATERIAL_SLOTS / 4] = [UVec4::ZERO; MATERIAL_SLOTS / 4];
+        let mut tints: [Vec4; MATERIAL_SLOTS] = [Vec4::ONE; MATERIAL_SLOTS];
+        for (material, layer, tint) in MATERIAL_APPEARANCE {
+            let slot: usize = material as usize;
+            layers[slot / 4][slot % 4] = layer;
+            tints[slot] = Vec4::new(tint[0], tint[1], tint[2], 1.0);
+        }
+        Self {
+            layers,
+            tints,
+            layer_means: [Vec4::ONE; MAX_TEXTURE_LAYERS],
+            texture_size: TEXTURE_SIZE,
+            triplanar_sharpness: TRIPLANAR_SHARPNESS,
+        }
+    }
+}
+
+/// Shades the terrain from the material ids and weights of its vertices, see [`super::bevy_mesh::BevyMeshBuilder`].
+/// Until the texture array is built the materials are shaded with their tints alone.
+#[derive(Asset, AsBindGroup, TypePath, Clone, Debug, Default)]
+pub struct TerrainMaterialExtension {
+    #[uniform(100)]
+    pub shading: TerrainShading,
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub textures: Option<Handle<Image>>,
+}
+
+impl MaterialExtension for TerrainMaterialExtension {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    // The material attributes are added after the attributes of the base pipeline, the prepass shaders ignore them.
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let material_layout = layout.0.get_layout(&[
+            ATTRIBUTE_MATERIAL_IDS.at_shader_location(8),
+            ATTRIBUTE_MATERIAL_WEIGHTS.at_shader_location(9),
+            ATTRIBUTE_TRIPLANAR_POSITION.at_shader_location(10),
+        ])?;
+        descriptor.vertex.buffers[0].attributes.extend(material_layout.attributes);
+        Ok(())
+    }
+}
+
+/// The images the texture array is built from, the array is built once they have all loaded.
+#[derive(Resource)]
+pub struct TerrainTextureLayers {
+    handles: Vec<Handle<Image>>,
+    built: bool,
+}
+
+pub fn setup_terrain_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<TerrainSplatMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let handle = materials.add(TerrainSplatMaterial {
+        base: StandardMaterial {
+            perceptual_roughness: 0.9,
+            ..default()
+        },
+        extension: TerrainMaterialExtension::default(),
+    });
+    commands.insert_resource(TerrainMaterial(handle));
+    commands.insert_resource(TerrainTextureLayers {
+        handles: TEXTURE_LAYERS.iter().map(|path| asset_server.load(*path)).collect(),
+        built: false,
+    });
+}
+
+/// Stacks the texture layers into an array once they have loaded and gives it to the terrain material.
+pub fn build_terrain_texture_array(
+    mut layers: ResMut<TerrainTextureLayers>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TerrainSplatMaterial>>,
+    terrain_material: Res<TerrainMaterial>,
+    asset_server: Res<AssetServer>,
+) {
+    if layers.built || !layers.handles.iter().all(|handle| asset_server.is_loaded_with_dependencies(handle)) {
+        return;
+    }
+    layers.built = true;
+
+    let mut data: Vec<u8> = Vec::new();
+    let mut means: Vec<Vec4> = Vec::new();
+    let mut size: Option<Extent3d> = None;
+    for (handle, path) in layers.handles.iter().zip(TEXTURE_LAYERS) {
+        let Some(image) = images.get(handle) else {
+            warn!("The terrain texture {} is not loaded, the terrain is shaded without textures", path);
+            return;
+        };
+        let layer_size: Extent3d = image.texture_descriptor.size;
+        let format: TextureFormat = image.texture_descriptor.format;
+        if format != TextureFormat::Rgba8UnormSrgb || *size.get_or_insert(layer_size) != layer_size {
+            warn!(
+                "The terrain texture {} is not an sRGB image the size of the first, the terrain is shaded without textures",
+                path
+            );
+            return;
+        }
+        let Some(pixels) = image.data.as_ref() else {
+            warn!("The terrain texture {} has no data, the terrain is shaded without textures", path);
+            return;
+        };
+        means.push(mean_color(pixels));
+        data.extend_from_slice(pixels);
+    }
+    let Some(size) = size else {
+        return;
+    };
+
+    let mut array = Image::new(
+        Extent3d {
+            depth_or_array_layers: TEXTURE_LAYERS.len() as u32,
+            ..size
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    array.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    array.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+
+    let Some(material) = materials.get_mut(&terrain_material.0) else {
+        return;
+    };
+    for (layer, mean) in means.into_iter().enumerate().take(MAX_TEXTURE_LAYERS) {
+        material.extension.shading.layer_means[layer] = mean;
+    }
+    material.extension.textures = Some(images.add(array));
+    info!("Built the terrain texture array from {} layer(s)", TEXTURE_LAYERS.len());
+}
+
+// The mean linear color of RGBA8 sRGB pixels.
+fn mean_color(pixels: &[u8]) -> Vec4 {
+    let linear: Vec<f32> = (0..=255).map(|value| Srgba::gamma_function(value as f32 / 255.0)).collect();
+    let mut sum: Vec4 = Vec4::ZERO;
+    for pixel in pixels.chunks_exact(4) {
+        sum += Vec4::new(linear[pixel[0] as usize], linear[pixel[1] as usize], linear[pixel[2] as usize], 1.0);
+    }
+    (sum / sum.w.max(1.0)).max(Vec4::splat(1.0e-3))
+}
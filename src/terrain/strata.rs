@@ -0,0 +1,242 @@
+use std::{fmt, sync::Arc};
+
+use bevy::math::{DVec3, Vec3};
+use noise::{NoiseFn, Perlin};
+
+use crate::seed::WorldSeed;
+
+use super::{
+    bake::{Sediment, Terranes},
+    icosphere::Icosphere,
+    planet::Planetoid,
+};
+
+// The rock below the surface of the terrain. Each column has a stack of layers from the surface down, taken from the
+// terranes of the nearest vertex of a baked planet or from a default stack on flat terrain. The layer boundaries are
+// warped by noise so they undulate instead of following the surface exactly, and ore veins run through the layers
+// that host them as thin sheets of noise, which makes them different for every world seed.
+
+/// The material of a voxel, the value is what the meshes and the save files store.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[repr(u8)]
+pub enum VoxelMaterial {
+    #[default]
+    Air = 0,
+    Soil = 1,
+    Basalt = 2,
+    Granite = 3,
+    Limestone = 4,
+    Sandstone = 5,
+    Metamorphic = 6,
+    Coal = 7,
+    IronOre = 8,
+    CopperOre = 9,
+    GoldOre = 10,
+    // Soil facing the sky, the strata never return it, the meshes decide it from the slope of the surface.
+    Grass = 11,
+    // Whatever lies above the snow line and is flat enough to hold it, also decided by the meshes.
+    Snow = 12,
+}
+
+impl VoxelMaterial {
+    pub const COUNT: usize = 13;
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        use VoxelMaterial::*;
+        [Air, Soil, Basalt, Granite, Limestone, Sandstone, Metamorphic, Coal, IronOre, CopperOre, GoldOre, Grass, Snow]
+            .get(index as usize)
+            .copied()
+    }
+
+    pub fn is_ore(&self) -> bool {
+        matches!(
+            self,
+            VoxelMaterial::Coal | VoxelMaterial::IronOre | VoxelMaterial::CopperOre | VoxelMaterial::GoldOre
+        )
+    }
+}
+
+impl From<Sediment> for VoxelMaterial {
+    fn from(sediment: Sediment) -> Self {
+        match sediment {
+            Sediment::Basalt => VoxelMaterial::Basalt,
+            Sediment::Granite => VoxelMaterial::Granite,
+            Sediment::MarineSediment => VoxelMaterial::Limestone,
+            Sediment::Sandstone => VoxelMaterial::Sandstone,
+            Sediment::Metamorphic => VoxelMaterial::Metamorphic,
+        }
+    }
+}
+
+// Soil covers the land, the sea floor is bare rock.
+const SOIL_DEPTH: f64 = 1.5;
+// The stack of flat terrain and of planets without baked layers, in world units from the surface down.
+// The last layer continues all the way down.
+const DEFAULT_STACK: [(VoxelMaterial, f64); 4] = [
+    (VoxelMaterial::Sandstone, 24.0),
+    (VoxelMaterial::Limestone, 32.0),
+    (VoxelMaterial::Metamorphic, 48.0),
+    (VoxelMaterial::Granite, f64::INFINITY),
+];
+// How far the layer boundaries move up and down, and how quickly they change.
+const BOUNDARY_WARP: f64 = 6.0;
+const BOUNDARY_WARP_FREQUENCY: f64 = 0.01;
+
+// A kind of ore, with the rock it forms in.
+struct OreVein {
+    ore: VoxelMaterial,
+    hosts: &'static [VoxelMaterial],
+    // The frequency of the sheets, higher is more and smaller veins.
+    frequency: f64,
+    // The half thickness of a sheet in noise values, higher is thicker veins.
+    thickness: f64,
+    // Veins only form this many world units below the surface, so the rarer ores need digging for.
+    min_depth: f64,
+}
+
+// Checked in order, so a rarer ore sharing a host wins over a common one.
+const ORE_VEINS: [OreVein; 4] = [
+    OreVein {
+        ore: VoxelMaterial::GoldOre,
+        hosts: &[VoxelMaterial::Metamorphic, VoxelMaterial::Granite],
+        frequency: 0.08,
+        thickness: 0.015,
+        min_depth: 24.0,
+    },
+    // Volcanic rock is where copper settles out of the hot water rising through it.
+    OreVein {
+        ore: VoxelMaterial::CopperOre,
+        hosts: &[VoxelMaterial::Basalt, VoxelMaterial::Granite],
+        frequency: 0.05,
+        thickness: 0.03,
+        min_depth: 12.0,
+    },
+    OreVein {
+        ore: VoxelMaterial::IronOre,
+        hosts: &[VoxelMaterial::Metamorphic, VoxelMaterial::Sandstone, VoxelMaterial::Basalt],
+        frequency: 0.04,
+        thickness: 0.04,
+        min_depth: 6.0,
+    },
+    // Coal forms in seams along the sedimentary layers.
+    OreVein {
+        ore: VoxelMaterial::Coal,
+        hosts: &[VoxelMaterial::Sandstone, VoxelMaterial::Limestone],
+        frequency: 0.03,
+        thickness: 0.06,
+        min_depth: 3.0,
+    },
+];
+
+/// The baked terranes of a planet, with the sphere their vertices are on.
+pub struct PlanetStrata {
+    pub sphere: Arc<Icosphere>,
+    pub terranes: Vec<Terranes>,
+}
+
+impl fmt::Debug for PlanetStrata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PlanetStrata({} vertices)", self.sphere.len())
+    }
+}
+
+impl PlanetStrata {
+    pub fn new(sphere: Arc<Icosphere>, terranes: Vec<Terranes>) -> Result<Self, String> {
+        if terranes.len() != sphere.len() {
+            return Err(format!(
+                "there are {} terranes, the sphere of {} subdivisions has {} vertices",
+                terranes.len(),
+                sphere.subdivisions,
+                sphere.len()
+            ));
+        }
+        Ok(Self { sphere, terranes })
+    }
+}
+
+/// Decides the material of the rock at a depth below the surface, compiled with the density field.
+pub struct Strata {
+    boundary_noise: Perlin,
+    vein_noise: Vec<(Perlin, Perlin)>,
+    planet: Option<(Planetoid, Arc<PlanetStrata>)>,
+}
+
+impl Strata {
+    pub fn new(world_seed: &WorldSeed, planet: Option<&Planetoid>, strata: Option<Arc<PlanetStrata>>) -> Self {
+        Self {
+            boundary_noise: Perlin::new(world_seed.derive_u32("strata/boundaries")),
+            vein_noise: (0..ORE_VEINS.len())
+                .map(|index| {
+                    (
+                        Perlin::new(world_seed.derive_u32(&format!("strata/ore/{}/sheets", index))),
+                        Perlin::new(world_seed.derive_u32(&format!("strata/ore/{}/pockets", index))),
+                    )
+                })
+                .collect(),
+            planet: planet.cloned().zip(strata),
+        }
+    }
+
+    /// The material at the absolute position, the depth is how far below the surface it is in world units.
+    pub fn material_at(&self, position: DVec3, depth: f64) -> VoxelMaterial {
+        if depth <= 0.0 {
+            return VoxelMaterial::Air;
+        }
+        let rock: VoxelMaterial = self.rock_at(position, depth);
+        if rock == VoxelMaterial::Soil {
+            return rock;
+        }
+        for (vein, (sheets, pockets)) in ORE_VEINS.iter().zip(self.vein_noise.iter()) {
+            if depth < vein.min_depth || !vein.hosts.contains(&rock) {
+                continue;
+            }
+            // Where the sheet noise crosses zero is a thin surface, the pocket noise breaks it into separate veins.
+            let p: DVec3 = position * vein.frequency;
+            if sheets.get(p.to_array()).abs() < vein.thickness && pockets.get((p * 0.5).to_array()) > 0.2 {
+                return vein.ore;
+            }
+        }
+        rock
+    }
+
+    /// The rock right under the soil at the absolute position, what shows where the surface is too steep for soil.
+    pub fn bedrock_at(&self, position: DVec3) -> VoxelMaterial {
+        self.rock_at(position, SOIL_DEPTH)
+    }
+
+    fn rock_at(&self, position: DVec3, depth: f64) -> VoxelMaterial {
+        let warp: f64 = self.boundary_noise.get((position * BOUNDARY_WARP_FREQUENCY).to_array()) * BOUNDARY_WARP;
+        let Some((planet, strata)) = self.planet.as_ref() else {
+            if depth < SOIL_DEPTH {
+                return VoxelMaterial::Soil;
+            }
+            return layer_at(DEFAULT_STACK.iter().copied(), depth - SOIL_DEPTH + warp);
+        };
+
+        let direction: Vec3 = position.as_vec3().normalize_or(Vec3::Y);
+        let vertex: usize = strata.sphere.nearest_vertex(direction) as usize;
+        let terranes: &Terranes = &strata.terranes[vertex];
+        if terranes.height > 0.0 && depth < SOIL_DEPTH {
+            return VoxelMaterial::Soil;
+        }
+        let scale: f64 = planet.elevation_scale as f64;
+        let layers = terranes.stack.iter().filter_map(|(sediment, thickness)| {
+            Some((VoxelMaterial::from(Sediment::from_index(*sediment)?), *thickness as f64 * scale))
+        });
+        layer_at(layers, depth + warp)
+    }
+}
+
+// The layer at the depth, the last layer continues below the bottom of the stack.
+fn layer_at(layers: impl Iterator<Item = (VoxelMaterial, f64)>, depth: f64) -> VoxelMaterial {
+    let mut bottom: f64 = 0.0;
+    let mut last: VoxelMaterial = VoxelMaterial::Granite;
+    for (material, thickness) in layers {
+        bottom += thickness;
+        last = material;
+        if depth < bottom {
+            return material;
+        }
+    }
+    last
+}
@@ -0,0 +1,545 @@
+use std::{cmp::Ordering, collections::BinaryHeap, f64::consts::TAU, sync::Arc};
+
+use avian3d::prelude::{GravityScale, LinearVelocity, Position, RigidBody};
+use bevy::{
+    asset::{Assets, Handle},
+    color::Color,
+    log::info,
+    math::{DVec3, I64Vec3, Vec3},
+    pbr::{MeshMaterial3d, StandardMaterial},
+    prelude::{
+        AlphaMode, Commands, Component, Entity, GlobalTransform, Mesh, Mesh3d, Query, Res, ResMut, Resource,
+        Transform, Visibility, With,
+    },
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+    time::Time,
+    utils::default,
+};
+
+use crate::{
+    camera::GameCamera,
+    origin::{FloatingOrigin, GridCell},
+    physics::GravityField,
+};
+
+use super::{
+    biome::FLAT_TERRAIN_UNITS_PER_KM,
+    config::TerrainConfig,
+    density_graph::{DensityField, TerrainDensity},
+    planet::Planetoid,
+    simulation::SEA_LEVEL,
+};
+
+// The sea fills everything below sea level, a plane on flat terrain and a shell around a planet, when the density
+// graph asks for one. Caves below sea level are flooded with it, so graphs with deep caves go without. Lakes fill the
+// basins of the surface around the camera up to the height their water would spill over, they are found on a grid of
+// columns whose surface height is searched in the density field. The grid moves with the camera in steps of a quarter
+// of its width and is filled again on the async compute pool.
+
+// The sea is drawn as a disc of rings around the camera with every vertex on the sea surface. The rings are spaced
+// quadratically, so near the camera the triangles are a few units across and on a planet they stay within a few
+// hundredths of a unit of the sphere the buoyancy is measured against, while the far rings reach the horizon.
+const SEA_RINGS: usize = 64;
+const SEA_SEGMENTS: usize = 64;
+// The disc is built again around the camera once the camera is this far from its center.
+const SEA_RECENTER_DISTANCE: f64 = 64.0;
+// How far below the floor of a lake the water still reaches, as the floor between the columns is not flat.
+const LAKE_FLOOR_MARGIN: f64 = 1.0;
+
+/// The sea level of flat terrain or of a planet.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sea {
+    // The radius of the sea around a planet centered on the world origin, None on flat terrain.
+    radius: Option<f64>,
+    // The height of the sea plane of flat terrain.
+    level: f64,
+}
+
+impl Sea {
+    pub fn for_planet(planet: Option<&Planetoid>) -> Self {
+        match planet {
+            Some(planet) => Sea {
+                radius: Some(planet.radius() as f64 + (SEA_LEVEL * planet.elevation_scale) as f64),
+                level: 0.0,
+            },
+            None => Sea {
+                radius: None,
+                level: (SEA_LEVEL * FLAT_TERRAIN_UNITS_PER_KM) as f64,
+            },
+        }
+    }
+
+    /// How far the absolute position is above sea level.
+    pub fn elevation(&self, position: DVec3) -> f64 {
+        match self.radius {
+            Some(radius) => position.length() - radius,
+            None => position.y - self.level,
+        }
+    }
+
+    /// The direction away from the sea floor at the absolute position.
+    pub fn up(&self, position: DVec3) -> DVec3 {
+        match self.radius {
+            Some(_) => position.normalize_or(DVec3::Y),
+            None => DVec3::Y,
+        }
+    }
+
+    /// The point at the elevation above sea level on the vertical line through the position.
+    pub fn lift(&self, position: DVec3, elevation: f64) -> DVec3 {
+        match self.radius {
+            Some(radius) => position.normalize_or(DVec3::Y) * (radius + elevation),
+            None => DVec3::new(position.x, self.level + elevation, position.z),
+        }
+    }
+
+    // Two directions along the sea surface at the point, east and north with up completing a right handed frame.
+    fn tangents(&self, point: DVec3) -> (DVec3, DVec3) {
+        let up: DVec3 = self.up(point);
+        let reference: DVec3 = if up.y.abs() < 0.99 { DVec3::Y } else { DVec3::Z };
+        let east: DVec3 = reference.cross(up).normalize();
+        (east, up.cross(east))
+    }
+
+    /// A disc of the sea surface reaching the extent around the center, relative to the center moved onto the sea.
+    /// On a planet the extent is measured along the surface and stops a radian around the planet.
+    pub fn disc_mesh(&self, center: DVec3, extent: f64) -> Mesh {
+        let center: DVec3 = self.lift(center, 0.0);
+        let (east, north) = self.tangents(center);
+        let extent: f64 = self.radius.map_or(extent, |radius| extent.min(radius));
+
+        let mut positions: Vec<Vec3> = vec![Vec3::ZERO];
+        let mut normals: Vec<Vec3> = vec![self.up(center).as_vec3()];
+        for ring in 1..=SEA_RINGS {
+            let along: f64 = extent * (ring as f64 / SEA_RINGS as f64).powi(2);
+            // The distance on the tangent plane which lands the distance along the surface once it is lifted.
+            let tangent: f64 = self.radius.map_or(along, |radius| radius * (along / radius).tan());
+            for segment in 0..SEA_SEGMENTS {
+                let angle: f64 = segment as f64 / SEA_SEGMENTS as f64 * TAU;
+                let point: DVec3 = self.lift(center + (east * angle.cos() + north * angle.sin()) * tangent, 0.0);
+                positions.push((point - center).as_vec3());
+                normals.push(self.up(point).as_vec3());
+            }
+        }
+
+        // The vertices go around each ring from east to north, so the triangles wind counter clockwise seen from up.
+        let vertex = |ring: usize, segment: usize| (1 + (ring - 1) * SEA_SEGMENTS + segment % SEA_SEGMENTS) as u32;
+        let mut indices: Vec<u32> = Vec::with_capacity(SEA_RINGS * SEA_SEGMENTS * 6);
+        for segment in 0..SEA_SEGMENTS {
+            indices.extend([0, vertex(1, segment), vertex(1, segment + 1)]);
+        }
+        for ring in 1..SEA_RINGS {
+            for segment in 0..SEA_SEGMENTS {
+                let (inner, inner_next) = (vertex(ring, segment), vertex(ring, segment + 1));
+                let (outer, outer_next) = (vertex(ring + 1, segment), vertex(ring + 1, segment + 1));
+                indices.extend([inner, outer, outer_next, inner, outer_next, inner_next]);
+            }
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+        mesh.insert_indices(Indices::U32(indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh
+    }
+}
+
+/// The lakes of a square grid of columns on the plane tangent to the sea at its center.
+pub struct LakeMap {
+    sea: Sea,
+    // Whether the sea is there, lakes below sea level are part of it when it is.
+    flooded: bool,
+    // The center of the grid on the sea surface and the directions of its rows, its columns and up there.
+    center: DVec3,
+    east: DVec3,
+    north: DVec3,
+    up: DVec3,
+    cells: usize,
+    cell_size: f64,
+    // The water level and the floor of each cell above sea level, None where the cell is dry.
+    lakes: Vec<Option<(f32, f32)>>,
+}
+
+impl LakeMap {
+    // The grid around the center, which is moved onto the sea surface.
+    fn new(sea: Sea, flooded: bool, center: DVec3, cells: usize, cell_size: f64) -> Self {
+        let center: DVec3 = sea.lift(center, 0.0);
+        let (east, north) = sea.tangents(center);
+        Self {
+            sea,
+            flooded,
+            center,
+            east,
+            north,
+            up: sea.up(center),
+            cells,
+            cell_size,
+            lakes: vec![None; cells * cells],
+        }
+    }
+
+    // The point on the tangent plane at the center of the cell.
+    fn cell_point(&self, x: f64, z: f64) -> DVec3 {
+        let half: f64 = self.cells as f64 / 2.0;
+        self.center + (self.east * (x + 0.5 - half) + self.north * (z + 0.5 - half)) * self.cell_size
+    }
+
+    // The cell whose column contains the absolute position.
+    fn cell_at(&self, position: DVec3) -> Option<usize> {
+        let on_plane: DVec3 = match self.sea.radius {
+            Some(radius) => {
+                let direction: DVec3 = position.normalize_or(DVec3::Y);
+                let cosine: f64 = direction.dot(self.up);
+                if cosine <= 0.0 {
+                    return None;
+                }
+                direction * (radius / cosine)
+            }
+            None => position,
+        };
+        let half: f64 = self.cells as f64 / 2.0;
+        let x: f64 = (on_plane - self.center).dot(self.east) / self.cell_size + half;
+        let z: f64 = (on_plane - self.center).dot(self.north) / self.cell_size + half;
+        if x < 0.0 || z < 0.0 || x >= self.cells as f64 || z >= self.cells as f64 {
+            return None;
+        }
+        Some(z as usize * self.cells + x as usize)
+    }
+
+    /// How far the absolute position is below the surface of a lake, None where there is no lake above or below it.
+    pub fn depth_at(&self, position: DVec3) -> Option<f64> {
+        let (level, floor) = self.lakes[self.cell_at(position)?]?;
+        let elevation: f64 = self.sea.elevation(position);
+        (elevation >= floor as f64 - LAKE_FLOOR_MARGIN).then_some(level as f64 - elevation)
+    }
+
+    /// Fills the basins of the surface of the field, the water of a basin rises until it spills over its lowest rim.
+    ///
+    /// The surface is flooded from the edges of the grid inwards, always continuing from the lowest cell reached so
+    /// far, so each cell is reached at the lowest level water could leave it at. This is the priority flood of
+    /// Barnes et al. Basins which reach the edge of the grid drain out of it, as their rim is not known.
+    fn fill(&mut self, field: &DensityField, relief: f64, min_depth: f64) {
+        let cells: usize = self.cells;
+        let mut heights: Vec<f64> = Vec::with_capacity(cells * cells);
+        for z in 0..cells {
+            for x in 0..cells {
+                let point: DVec3 = self.cell_point(x as f64, z as f64);
+                let base: DVec3 = self.sea.lift(point, 0.0);
+                let up: DVec3 = self.sea.up(point);
+                heights.push(field.surface_height(base, up, -relief, relief, self.cell_size));
+            }
+        }
+
+        let mut levels: Vec<f64> = vec![f64::NAN; cells * cells];
+        let mut open: BinaryHeap<FloodCell> = BinaryHeap::new();
+        for z in 0..cells {
+            for x in 0..cells {
+                if x == 0 || z == 0 || x == cells - 1 || z == cells - 1 {
+                    let index: usize = z * cells + x;
+                    levels[index] = heights[index];
+                    open.push(FloodCell { level: heights[index], index });
+                }
+            }
+        }
+        while let Some(FloodCell { level, index }) = open.pop() {
+            let (x, z) = ((index % cells) as i64, (index / cells) as i64);
+            for (dx, dz) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (nx, nz) = (x + dx, z + dz);
+                if nx < 0 || nz < 0 || nx >= cells as i64 || nz >= cells as i64 {
+                    continue;
+                }
+                let neighbour: usize = nz as usize * cells + nx as usize;
+                if !levels[neighbour].is_nan() {
+                    continue;
+                }
+                levels[neighbour] = heights[neighbour].max(level);
+                open.push(FloodCell { level: levels[neighbour], index: neighbour });
+            }
+        }
+
+        // Basins below sea level are under the sea already when there is one.
+        for (index, lake) in self.lakes.iter_mut().enumerate() {
+            let (level, floor) = (levels[index], heights[index]);
+            let above_sea: bool = !self.flooded || level > 0.0;
+            *lake = (level - floor >= min_depth && above_sea).then_some((level as f32, floor as f32));
+        }
+    }
+
+    /// A mesh of the surface of the lakes relative to the center of the grid, one quad for each flooded cell.
+    pub fn mesh(&self) -> Option<Mesh> {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        for (index, lake) in self.lakes.iter().enumerate() {
+            let Some((level, _)) = lake else {
+                continue;
+            };
+            let (x, z) = ((index % self.cells) as f64, (index / self.cells) as f64);
+            let first: u32 = positions.len() as u32;
+            for (dx, dz) in [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)] {
+                let corner: DVec3 = self.sea.lift(self.cell_point(x + dx, z + dz), *level as f64);
+                positions.push((corner - self.center).as_vec3());
+                normals.push(self.sea.up(corner).as_vec3());
+            }
+            // Counter clockwise seen from up, as east, north and up are right handed.
+            indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        if indices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+        mesh.insert_indices(Indices::U32(indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        Some(mesh)
+    }
+
+    pub fn lake_count(&self) -> usize {
+        self.lakes.iter().filter(|lake| lake.is_some()).count()
+    }
+}
+
+// A cell reached by the flood, ordered so the lowest level is popped first.
+#[derive(PartialEq)]
+struct FloodCell {
+    level: f64,
+    index: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max heap, so the comparison is reversed to pop the lowest level.
+        other.level.total_cmp(&self.level).then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The sea and the lakes around the camera, and the entities they are rendered with.
+#[derive(Resource)]
+pub struct Water {
+    // None when the density graph has no sea.
+    pub sea: Option<Sea>,
+    pub lakes: Option<Arc<LakeMap>>,
+    // Where the sea disc is centered, None to build it again.
+    sea_center: Option<DVec3>,
+    // The grid step the lakes were last filled around, None to fill them again.
+    lake_step: Option<I64Vec3>,
+    task: Option<Task<LakeMap>>,
+    sea_entity: Entity,
+    lake_entity: Option<Entity>,
+    material: Handle<StandardMaterial>,
+}
+
+impl Water {
+    /// How far the absolute position is below the surface of the sea or a lake, negative above the water.
+    pub fn depth_at(&self, position: DVec3) -> f64 {
+        let sea: f64 = self.sea.map_or(f64::NEG_INFINITY, |sea| -sea.elevation(position));
+        let lake: Option<f64> = self.lakes.as_ref().and_then(|lakes| lakes.depth_at(position));
+        lake.map_or(sea, |lake| lake.max(sea))
+    }
+}
+
+/// Marks the entity the sea is rendered with.
+#[derive(Component)]
+pub struct SeaSurface;
+
+/// How a dynamic body floats, bodies without it float like wood.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Buoyancy {
+    // The density of the body over the density of water, bodies below 1 float.
+    pub relative_density: f32,
+    // Half the height of the body, it is fully submerged once its center is this far below the surface.
+    pub half_height: f32,
+    // How quickly the water slows the body down when it is fully submerged, per second.
+    pub drag: f32,
+}
+
+impl Default for Buoyancy {
+    fn default() -> Self {
+        Self {
+            relative_density: 0.6,
+            half_height: 0.5,
+            drag: 1.5,
+        }
+    }
+}
+
+impl Buoyancy {
+    /// The fraction of the body below the surface when its center is at the depth.
+    pub fn submerged(&self, depth: f32) -> f32 {
+        (depth / (2.0 * self.half_height.max(1.0e-3)) + 0.5).clamp(0.0, 1.0)
+    }
+}
+
+pub fn setup_water(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::srgba(0.05, 0.2, 0.3, 0.7),
+        perceptual_roughness: 0.1,
+        reflectance: 0.3,
+        alpha_mode: AlphaMode::Blend,
+        // The surface is seen from below while swimming.
+        double_sided: true,
+        cull_mode: None,
+        ..default()
+    });
+    // The sea is hidden until the density graph has loaded and decided whether there is one.
+    let sea_entity = commands
+        .spawn((
+            SeaSurface,
+            MeshMaterial3d(material.clone()),
+            Transform::default(),
+            GridCell::default(),
+            Visibility::Hidden,
+        ))
+        .id();
+    commands.insert_resource(Water {
+        sea: None,
+        lakes: None,
+        sea_center: None,
+        lake_step: None,
+        task: None,
+        sea_entity,
+        lake_entity: None,
+        material,
+    });
+}
+
+/// Reshapes the sea when the density graph changes and builds its disc again around the camera as it moves, and fills
+/// the lakes again when the graph changes or the camera moves a quarter of the lake grid away from its center.
+pub fn update_water(
+    mut commands: Commands,
+    mut water: ResMut<Water>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    density: Res<TerrainDensity>,
+    config: Res<TerrainConfig>,
+    origin: Res<FloatingOrigin>,
+    camera_query: Query<&GlobalTransform, With<GameCamera>>,
+) {
+    let Some(field) = density.field.as_ref() else {
+        return;
+    };
+    let sea_level = Sea::for_planet(field.planet());
+    if density.is_changed() {
+        water.sea = field.has_sea().then_some(sea_level);
+        water.sea_center = None;
+        let visibility = if water.sea.is_some() { Visibility::Inherited } else { Visibility::Hidden };
+        let sea_entity: Entity = water.sea_entity;
+        commands.entity(sea_entity).insert(visibility);
+        water.lake_step = None;
+        water.task = None;
+    }
+
+    // Attach the lakes once they are filled, the mesh is placed at the center of the grid and shifted with the world.
+    if let Some(task) = water.task.as_mut() {
+        if let Some(lakes) = block_on(poll_once(task)) {
+            water.task = None;
+            if let Some(entity) = water.lake_entity.take() {
+                commands.entity(entity).despawn();
+            }
+            if let Some(mesh) = lakes.mesh() {
+                let material: Handle<StandardMaterial> = water.material.clone();
+                water.lake_entity = Some(
+                    commands
+                        .spawn((
+                            Mesh3d(meshes.add(mesh)),
+                            MeshMaterial3d(material),
+                            Transform::from_translation(origin.cell.relative(lakes.center)),
+                            origin.cell,
+                        ))
+                        .id(),
+                );
+            }
+            info!("Filled {} lake cell(s) around the camera", lakes.lake_count());
+            water.lakes = Some(Arc::new(lakes));
+        }
+    }
+
+    let Ok(camera_transform) = camera_query.single() else {
+        return;
+    };
+    let camera: DVec3 = origin.cell.absolute(camera_transform.translation());
+    if let Some(sea) = water.sea {
+        let under_camera: DVec3 = sea.lift(camera, 0.0);
+        if water.sea_center.is_none_or(|center| center.distance(under_camera) > SEA_RECENTER_DISTANCE) {
+            let mesh: Mesh = sea.disc_mesh(under_camera, config.sea_extent as f64);
+            let sea_entity: Entity = water.sea_entity;
+            commands.entity(sea_entity).insert(Mesh3d(meshes.add(mesh)));
+            water.sea_center = Some(under_camera);
+        }
+    }
+
+    let stride: f64 = (config.lake_cells as f64 * config.lake_cell_size as f64 / 4.0).max(1.0);
+    let step: I64Vec3 = (camera / stride).round().as_i64vec3();
+    if water.task.is_some() || water.lake_step == Some(step) {
+        return;
+    }
+    water.lake_step = Some(step);
+
+    let field: Arc<DensityField> = field.clone();
+    let relief: f64 = field.planet().map_or(config.flat_relief, |planet| planet.relief) as f64;
+    let min_depth: f64 = config.min_lake_depth as f64;
+    let mut lakes = LakeMap::new(
+        sea_level,
+        water.sea.is_some(),
+        step.as_dvec3() * stride,
+        config.lake_cells,
+        config.lake_cell_size as f64,
+    );
+    water.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        lakes.fill(&field, relief, min_depth);
+        lakes
+    }));
+}
+
+/// Places the sea disc at its center, relative to the floating origin so it is shifted with the rest of the world.
+pub fn place_sea_surface(
+    water: Res<Water>,
+    origin: Res<FloatingOrigin>,
+    mut sea_query: Query<(&mut Transform, &mut GridCell), With<SeaSurface>>,
+) {
+    let Some(center) = water.sea_center else {
+        return;
+    };
+    let Ok((mut transform, mut cell)) = sea_query.get_mut(water.sea_entity) else {
+        return;
+    };
+    *cell = origin.cell;
+    transform.translation = origin.cell.relative(center);
+}
+
+/// Pushes the dynamic bodies in water up against gravity by the fraction of them below the surface, and slows them
+/// down by it. Like gravity the push is scaled by the [`GravityScale`] of the body.
+pub fn apply_buoyancy(
+    water: Res<Water>,
+    field: Res<GravityField>,
+    origin: Res<FloatingOrigin>,
+    time: Res<Time>,
+    mut bodies: Query<(&RigidBody, &Position, &mut LinearVelocity, Option<&GravityScale>, Option<&Buoyancy>)>,
+) {
+    let delta: f32 = time.delta_secs();
+    for (rigid_body, position, mut linear_velocity, gravity_scale, buoyancy) in bodies.iter_mut() {
+        if !rigid_body.is_dynamic() {
+            continue;
+        }
+        let buoyancy: Buoyancy = buoyancy.copied().unwrap_or_default();
+        let depth: f32 = water.depth_at(origin.cell.absolute(position.0)) as f32;
+        let submerged: f32 = buoyancy.submerged(depth);
+        if submerged <= 0.0 {
+            continue;
+        }
+        let scale: f32 = gravity_scale.map_or(1.0, |scale| scale.0);
+        let lift: f32 = submerged / buoyancy.relative_density.max(1.0e-3);
+        linear_velocity.0 -= field.acceleration_at(position.0) * lift * scale * delta;
+        linear_velocity.0 *= (-buoyancy.drag * submerged * delta).exp();
+    }
+}
@@ -1,13 +1,8 @@
 use bevy::{
-    asset::{Assets, Handle},
     input::ButtonInput,
     log::{info, warn},
     math::{f32, EulerRot, Quat, Vec2, Vec3},
-    prelude::{KeyCode, Mesh, Query, Res, ResMut, With},
-    render::{
-        mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
-        render_asset::RenderAssetUsages,
-    },
+    prelude::{KeyCode, Query, Res, With},
     window::{CursorGrabMode, PrimaryWindow, Window},
 };
 
@@ -16,7 +11,7 @@ use std::{
     ops::{Add, Mul, Sub},
 };
 
-use crate::{user_interface::themes::NO_PERCENTAGE, Bindings};
+use crate::{config::Bindings, user_interface::themes::NO_PERCENTAGE};
 
 #[macro_export]
 macro_rules! ternary {
@@ -231,72 +226,6 @@ fn grab_mode_stringified(grab_mode: &CursorGrabMode) -> String {
     }
 }
 
-// * --- Generate Meshes ---
-pub fn generate_plane_mesh(
-    meshes: &mut ResMut<Assets<Mesh>>,
-    width: f32,
-    length: f32,
-    uv_scale: f32,
-) -> Handle<Mesh> {
-    let half_width = width / 2.0;
-    let half_length = length / 2.0;
-
-    let vertices = vec![
-        // Top face
-        (
-            [-half_width, 0.0, half_length],
-            [0.0, 1.0, 0.0],
-            [0.0, uv_scale * length],
-        ), // Top-left
-        (
-            [half_width, 0.0, half_length],
-            [0.0, 1.0, 0.0],
-            [uv_scale * width, uv_scale * length],
-        ), // Top-right
-        (
-            [half_width, 0.0, -half_length],
-            [0.0, 1.0, 0.0],
-            [uv_scale * width, 0.0],
-        ), // Bottom-right
-        (
-            [-half_width, 0.0, -half_length],
-            [0.0, 1.0, 0.0],
-            [0.0, 0.0],
-        ), // Bottom-left
-    ];
-
-    let indices = vec![
-        0, 1, 2, 2, 3, 0, // top face
-    ];
-
-    let mut positions = Vec::new();
-    let mut normals = Vec::new();
-    let mut uvs = Vec::new();
-
-    for (position, normal, uv) in vertices {
-        positions.push(position);
-        normals.push(normal);
-        uvs.push(uv);
-    }
-
-    let mut mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::RENDER_WORLD,
-    );
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::from(positions),
-    );
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
-    mesh.insert_indices(Indices::U32(indices));
-
-    meshes.add(
-        mesh.with_generated_tangents()
-            .expect("Failed to generate tangents for the mesh"),
-    )
-}
-
 // * --- Valid File Extensions ---
 const VALID_EXTENSIONS_VIDEO: [&str; 3] = ["mp4", "avi", "mkv"];
 const VALID_EXTENSIONS_SCREENSHOT: [&str; 3] = ["png", "jpeg", "bmp"];